@@ -1,4 +1,7 @@
 fn main() {
     println!("cargo::rerun-if-changed=link.ld");
-    println!("cargo::rustc-link-arg=-Tlink.ld");
+    // Only firmware builds use the Alarmo's memory layout, host unit tests link normally
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        println!("cargo::rustc-link-arg=-Tlink.ld");
+    }
 }
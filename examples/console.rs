@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+
+use alarmo::display::console::ScreenConsole;
+use alarmo::display::AlarmoScreen;
+use alarmo::Alarmo;
+use core::fmt::Write;
+use cortex_m::prelude::_embedded_hal_blocking_delay_DelayMs;
+use cortex_m_rt::entry;
+
+// A panic handler is required
+use panic_halt as _;
+
+#[entry]
+fn main() -> ! {
+    let alarmo = unsafe { Alarmo::init() };
+
+    let mut screen = AlarmoScreen::new(alarmo.display);
+    screen.display_mut().set_backlight(1.0);
+
+    let mut console = ScreenConsole::new(screen);
+    console.clear().unwrap();
+
+    writeln!(console, "\x1b[1;32mAlarmo console\x1b[0m").unwrap();
+    writeln!(
+        console,
+        "Press buttons to log them, long lines wrap around."
+    )
+    .unwrap();
+
+    let mut count = 0u32;
+    loop {
+        let buttons = &alarmo.buttons;
+        if buttons.mail() || buttons.back() || buttons.dial_click() {
+            count += 1;
+            writeln!(
+                console,
+                "\x1b[33m#{count}\x1b[0m mail={} back={} dial={}",
+                buttons.mail(),
+                buttons.back(),
+                buttons.dial_click()
+            )
+            .unwrap();
+        }
+        alarmo.delay.borrow_mut().delay_ms(100_u16);
+    }
+}
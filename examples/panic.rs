@@ -1,5 +1,5 @@
 //! You can enable the `panic` feature to get panic messages to display on the Alarmo's screen,
-//! instead of silently halting.
+//! instead of silently halting. Press Back on the panic screen to reboot.
 
 #![no_main]
 #![no_std]
@@ -29,12 +29,13 @@ fn main() -> ! {
     loop {}
 }
 
-// Some other exceptions to intercept
+// Some other exceptions to intercept. Alternatively, enable the `fault-handlers` feature (and
+// remove the HardFault handler below) to show decoded faults on the panic screen.
 
 #[exception]
 unsafe fn HardFault(ef: &cortex_m_rt::ExceptionFrame) -> ! {
-    // Note: enable the `alloc` feature to see the message, otherwise messages with placeholders
-    // will appear empty
+    // Show the faulting registers on the panic screen
+    alarmo::panic::set_exception_frame(ef);
     panic!("HardFault at {:#?}", ef)
 }
 
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+use alarmo::display::AlarmoScreen;
+use alarmo::Alarmo;
+use cortex_m_rt::entry;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+};
+
+// A panic handler is required
+use panic_halt as _;
+
+#[entry]
+fn main() -> ! {
+    let alarmo = unsafe { Alarmo::init() };
+
+    // Resets and configures the LCD for the Alarmo's panel, no need for a separate driver
+    let mut screen = AlarmoScreen::new(alarmo.display);
+
+    // IMPORTANT! Display has no backlight by default, so you won't see anything unless you add one.
+    screen.display_mut().set_backlight(1.0);
+
+    screen.clear(Rgb565::BLACK).unwrap();
+
+    // Rectangles are filled in a single GRAM window
+    Rectangle::new(Point::new(20, 20), Size::new(280, 200))
+        .into_styled(PrimitiveStyle::with_fill(Rgb565::BLUE))
+        .draw(&mut screen)
+        .unwrap();
+
+    let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+    Text::new("Rust on Alarmo!", Point::new(115, 120), style)
+        .draw(&mut screen)
+        .unwrap();
+
+    loop {
+        continue;
+    }
+}
@@ -9,14 +9,13 @@ extern crate alloc;
 use cortex_m::prelude::*;
 use stm32h7xx_hal::prelude::*;
 
+use alarmo::storage::{BlockDevice, EmmcStorage, BLOCK_SIZE};
 use alarmo::{Alarmo, AlarmoOptions};
 use alloc::vec;
 use alloc::vec::Vec;
 use cortex_m::peripheral::NVIC;
 use cortex_m_rt::entry;
 use stm32h7xx_hal::interrupt;
-use stm32h7xx_hal::pac::SDMMC2;
-use stm32h7xx_hal::sdmmc::{Emmc, Sdmmc};
 use stm32h7xx_hal::usb_hs::{UsbBus, USB1};
 use usb_device::device::{UsbDeviceBuilder, UsbDeviceState, UsbVidPid};
 
@@ -65,9 +64,8 @@ fn main() -> ! {
     while emmc.init(26.MHz()).is_err() {
         alarmo.delay.borrow_mut().delay_ms(1000u32);
     }
-    let card = emmc.card().unwrap();
-    let usb_block_count = card.ext_csd.sector_count().swap_bytes();
-    let usb_block_size = 512;
+    let mut storage = EmmcStorage::new(emmc).unwrap();
+    let usb_block_count = storage.block_count();
 
     // IMPORTANT! For Alarmo, you need to mask this interrupt, otherwise you will have to handle
     // and clear it.
@@ -102,9 +100,9 @@ fn main() -> ! {
         let _ = scsi.poll(|command| {
             scsi_process(
                 command,
-                &mut emmc,
+                &mut storage,
                 &mut state,
-                usb_block_size as u32,
+                BLOCK_SIZE as u32,
                 usb_block_count,
             )
             .unwrap();
@@ -114,7 +112,7 @@ fn main() -> ! {
 
 fn scsi_process(
     mut command: Command<ScsiCommand, Scsi<BulkOnly<UsbBus<USB1>, &mut [u8]>>>,
-    emmc: &mut Sdmmc<SDMMC2, Emmc>,
+    storage: &mut impl BlockDevice,
     state: &mut State,
     block_size: u32,
     block_count: u32,
@@ -211,7 +209,7 @@ fn scsi_process(
                 let mut buf = if state.cur_buf.is_none() {
                     let mut buf = vec![0u8; block_size as usize * len as usize];
                     // Read blocks from eMMC
-                    if let Err(e) = emmc.read_blocks(lba, &mut buf) {
+                    if let Err(e) = storage.read(lba, &mut buf) {
                         panic!("Error reading blocks {lba} x{len}: {:?}", e);
                     }
                     buf
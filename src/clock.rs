@@ -0,0 +1,68 @@
+//! Monotonic clock, based on a free-running hardware timer (TIM2).
+
+use stm32h7xx_hal::pac::TIM2;
+
+/// Frequency of the clock, in ticks per second.
+pub const TICKS_PER_SECOND: u32 = 10_000;
+
+const TICKS_PER_MS: u32 = TICKS_PER_SECOND / 1000;
+
+/// Monotonic clock, started by [`Alarmo::init`](crate::Alarmo::init).
+///
+/// The clock has a resolution of 100µs and wraps around after about 4.9 days. Durations between
+/// [`Instant`]s are computed with wrapping arithmetic, so they stay correct across a wrap as long
+/// as they are shorter than that.
+pub struct Clock {
+    _tim2: TIM2,
+}
+
+/// A point in time, as measured by [`Clock`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Instant {
+    ticks: u32,
+}
+
+impl Clock {
+    pub(crate) fn new(tim2: TIM2) -> Self {
+        Self { _tim2: tim2 }
+    }
+
+    pub fn now(&self) -> Instant {
+        now()
+    }
+}
+
+impl Instant {
+    pub const fn from_ticks(ticks: u32) -> Self {
+        Self { ticks }
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    /// Returns the time since the clock was started, in milliseconds.
+    pub fn as_millis(&self) -> u32 {
+        self.ticks / TICKS_PER_MS
+    }
+
+    /// Returns the number of milliseconds elapsed between `earlier` and `self`.
+    pub fn millis_since(&self, earlier: Instant) -> u32 {
+        self.ticks.wrapping_sub(earlier.ticks) / TICKS_PER_MS
+    }
+
+    /// Returns the instant `ms` milliseconds after `self`.
+    pub fn add_millis(&self, ms: u32) -> Instant {
+        Instant {
+            ticks: self.ticks.wrapping_add(ms.wrapping_mul(TICKS_PER_MS)),
+        }
+    }
+}
+
+/// Reads the clock without going through [`Clock`], e.g. from interrupt or panic handlers.
+pub(crate) fn now() -> Instant {
+    // Reading the counter has no side effects
+    Instant {
+        ticks: unsafe { (*TIM2::ptr()).cnt.read().bits() },
+    }
+}
@@ -4,6 +4,8 @@ use crate::pac::adc::AdcBuffers;
 use crate::pac::timers::DialTimers;
 use core::cell::UnsafeCell;
 use cortex_m::peripheral::SCB;
+// The standard library provides these methods for host unit tests
+#[cfg_attr(test, allow(unused_imports))]
 use micromath::F32Ext;
 use stm32h7xx_hal::prelude::_embedded_hal_PwmPin;
 
@@ -0,0 +1,247 @@
+//! Low-level access to the LCD's 16-bit parallel (8080) bus.
+//!
+//! The LCD is connected to the FMC as a 16-bit SRAM. FMC address line A6 drives the D/CX
+//! signal (bit 7 of the byte address, as the bus is 16 bits wide), so commands and data are
+//! written to two different addresses.
+//!
+//! [`AlarmoDisplay`](super::AlarmoDisplay) uses [`FmcBus`] by default. The [`DisplayBus`] trait
+//! allows replacing it with a mock (see [`AlarmoDisplay::from_bus`]), e.g. to check the
+//! command sequences generated by the display in host tests.
+//!
+//! [`AlarmoDisplay::from_bus`]: super::AlarmoDisplay::from_bus
+//!
+//! ## Data formats
+//! Every [`DataFormat`] is supported. Since the bus is 16 bits wide, 16-bit values are written
+//! as a single bus word:
+//!
+//! | Format                      | Bus writes                                              |
+//! |-----------------------------|---------------------------------------------------------|
+//! | `U8`, `U8Iter`              | one 8-bit write per byte                                |
+//! | `U16`                       | one 16-bit write per value, as-is                       |
+//! | `U16BE`, `U16BEIter`        | one 16-bit write per value, as-is (first byte on D15-8) |
+//! | `U16LE`, `U16LEIter`        | one 16-bit write per value, with its bytes swapped      |
+//!
+//! Commands are always 8 bits wide. 16-bit command formats are split into bytes in the
+//! requested byte order (native order for `U16`).
+
+use super::SelectPin;
+use display_interface::{DataFormat, DisplayError};
+
+const BASE: usize = 0xc0000000;
+const RS_PIN: u8 = 6;
+const COMMAND_PTR: *mut u8 = BASE as *mut u8;
+const DATA8_PTR: *mut u8 = (BASE + (1 << (RS_PIN + 1))) as *mut u8;
+pub(super) const DATA16_PTR: *mut u16 = (BASE + (1 << (RS_PIN + 1))) as *mut u16;
+
+/// A bus with separate command and data modes.
+pub trait DisplayBus {
+    /// Drives the chip select line (CSX), low if `selected`. Commands and data are only
+    /// accepted while the display is selected.
+    fn select(&mut self, selected: bool);
+
+    /// Writes a command byte (D/CX low).
+    fn write_command(&mut self, cmd: u8);
+
+    /// Writes a byte on the lower 8 data lines (D/CX high).
+    fn write_data8(&mut self, data: u8);
+
+    /// Writes a 16-bit word (D/CX high).
+    fn write_data16(&mut self, data: u16);
+
+    /// Reads a 16-bit word (D/CX high). Command parameters are returned on the lower 8 lines.
+    fn read_data16(&mut self) -> u16;
+}
+
+/// The LCD bus, as mapped by the FMC. Chip select is driven as a GPIO.
+pub struct FmcBus {
+    select_pin: SelectPin,
+}
+
+impl FmcBus {
+    pub(super) fn new(select_pin: SelectPin) -> Self {
+        Self { select_pin }
+    }
+}
+
+impl DisplayBus for FmcBus {
+    #[inline]
+    fn select(&mut self, selected: bool) {
+        if selected {
+            self.select_pin.set_low();
+        } else {
+            self.select_pin.set_high();
+        }
+    }
+
+    #[inline]
+    fn write_command(&mut self, cmd: u8) {
+        unsafe { COMMAND_PTR.write_volatile(cmd) };
+    }
+
+    #[inline]
+    fn write_data8(&mut self, data: u8) {
+        unsafe { DATA8_PTR.write_volatile(data) };
+    }
+
+    #[inline]
+    fn write_data16(&mut self, data: u16) {
+        unsafe { DATA16_PTR.write_volatile(data) };
+    }
+
+    #[inline]
+    fn read_data16(&mut self) -> u16 {
+        unsafe { DATA16_PTR.read_volatile() }
+    }
+}
+
+/// Writes a sequence of command bytes.
+pub fn write_commands(bus: &mut impl DisplayBus, cmd: DataFormat<'_>) -> Result<(), DisplayError> {
+    match cmd {
+        DataFormat::U8(bytes) => bytes.iter().for_each(|b| bus.write_command(*b)),
+        DataFormat::U8Iter(iter) => iter.for_each(|b| bus.write_command(b)),
+        DataFormat::U16(words) => words
+            .iter()
+            .flat_map(|w| w.to_ne_bytes())
+            .for_each(|b| bus.write_command(b)),
+        DataFormat::U16BE(words) => words
+            .iter()
+            .flat_map(|w| w.to_be_bytes())
+            .for_each(|b| bus.write_command(b)),
+        DataFormat::U16LE(words) => words
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .for_each(|b| bus.write_command(b)),
+        DataFormat::U16BEIter(iter) => iter
+            .flat_map(|w| w.to_be_bytes())
+            .for_each(|b| bus.write_command(b)),
+        DataFormat::U16LEIter(iter) => iter
+            .flat_map(|w| w.to_le_bytes())
+            .for_each(|b| bus.write_command(b)),
+        _ => return Err(DisplayError::DataFormatNotImplemented),
+    }
+    Ok(())
+}
+
+/// Writes data, see the [module docs](self) for the mapping of each format.
+pub fn write_data(bus: &mut impl DisplayBus, buf: DataFormat<'_>) -> Result<(), DisplayError> {
+    match buf {
+        DataFormat::U8(bytes) => bytes.iter().for_each(|b| bus.write_data8(*b)),
+        DataFormat::U8Iter(iter) => iter.for_each(|b| bus.write_data8(b)),
+        DataFormat::U16(words) => words.iter().for_each(|w| bus.write_data16(*w)),
+        DataFormat::U16BE(words) => words.iter().for_each(|w| bus.write_data16(*w)),
+        DataFormat::U16LE(words) => words.iter().for_each(|w| bus.write_data16(w.swap_bytes())),
+        DataFormat::U16BEIter(iter) => iter.for_each(|w| bus.write_data16(w)),
+        DataFormat::U16LEIter(iter) => iter.for_each(|w| bus.write_data16(w.swap_bytes())),
+        _ => return Err(DisplayError::DataFormatNotImplemented),
+    }
+    Ok(())
+}
+
+/// A bus that records every access, for host tests of the command sequences.
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::DisplayBus;
+    use std::collections::VecDeque;
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum BusOp {
+        Select(bool),
+        Command(u8),
+        Data8(u8),
+        Data16(u16),
+        Read,
+    }
+
+    #[derive(Default)]
+    pub struct RecordingBus {
+        pub ops: Vec<BusOp>,
+        /// Words returned by reads, in order. Reads return 0 once it is empty.
+        pub reads: VecDeque<u16>,
+    }
+
+    impl RecordingBus {
+        pub fn with_reads(reads: &[u16]) -> Self {
+            Self {
+                ops: Vec::new(),
+                reads: reads.iter().copied().collect(),
+            }
+        }
+
+        /// Returns the recorded accesses, leaving the log empty.
+        pub fn take(&mut self) -> Vec<BusOp> {
+            core::mem::take(&mut self.ops)
+        }
+    }
+
+    impl DisplayBus for RecordingBus {
+        fn select(&mut self, selected: bool) {
+            self.ops.push(BusOp::Select(selected));
+        }
+
+        fn write_command(&mut self, cmd: u8) {
+            self.ops.push(BusOp::Command(cmd));
+        }
+
+        fn write_data8(&mut self, data: u8) {
+            self.ops.push(BusOp::Data8(data));
+        }
+
+        fn write_data16(&mut self, data: u16) {
+            self.ops.push(BusOp::Data16(data));
+        }
+
+        fn read_data16(&mut self) -> u16 {
+            self.ops.push(BusOp::Read);
+            self.reads.pop_front().unwrap_or(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{BusOp::*, RecordingBus};
+    use super::*;
+
+    #[test]
+    fn commands_are_split_into_bytes() {
+        let mut bus = RecordingBus::default();
+        write_commands(&mut bus, DataFormat::U8(&[0x2a, 0x2b])).unwrap();
+        write_commands(&mut bus, DataFormat::U16BE(&mut [0x1234])).unwrap();
+        write_commands(&mut bus, DataFormat::U16LE(&mut [0x1234])).unwrap();
+        write_commands(&mut bus, DataFormat::U16BEIter(&mut [0xabcd].into_iter())).unwrap();
+        assert_eq!(
+            bus.ops,
+            [
+                Command(0x2a),
+                Command(0x2b),
+                Command(0x12),
+                Command(0x34),
+                Command(0x34),
+                Command(0x12),
+                Command(0xab),
+                Command(0xcd),
+            ]
+        );
+    }
+
+    #[test]
+    fn data_uses_the_full_bus_width() {
+        let mut bus = RecordingBus::default();
+        write_data(&mut bus, DataFormat::U8Iter(&mut [1, 2].into_iter())).unwrap();
+        write_data(&mut bus, DataFormat::U16(&[0x1234])).unwrap();
+        write_data(&mut bus, DataFormat::U16BE(&mut [0x1234])).unwrap();
+        write_data(&mut bus, DataFormat::U16LE(&mut [0x1234])).unwrap();
+        write_data(&mut bus, DataFormat::U16LEIter(&mut [0xabcd].into_iter())).unwrap();
+        assert_eq!(
+            bus.ops,
+            [
+                Data8(1),
+                Data8(2),
+                Data16(0x1234),
+                Data16(0x1234),
+                Data16(0x3412),
+                Data16(0xcdab),
+            ]
+        );
+    }
+}
@@ -0,0 +1,591 @@
+//! Scrolling text console, for debug output on the LCD.
+//!
+//! [`TextBuffer`] holds the terminal state: a grid of character cells, the cursor and the
+//! current colors. It handles line wrapping, scrolling and a subset of ANSI escape sequences,
+//! and has no hardware dependencies, so it can be exercised on the host.
+//!
+//! With the `graphics` feature, [`Console`] renders a buffer to any [`DrawTarget`] (usually an
+//! [`AlarmoScreen`](super::AlarmoScreen)) with the `FONT_6X10` font, like the panic screen.
+//! Both implement [`core::fmt::Write`], so they can be used with `write!` as a log sink.
+//!
+//! ## Supported control sequences
+//! * `\n` (new line), `\r` (carriage return), `\t` (tab stops every 8 columns), backspace
+//! * `ESC [ ... m` (SGR): `0` reset, `1` bold (bright foreground), `22` normal intensity, `7`
+//!   inverse, `27` not inverse, `30`-`37` / `90`-`97` foreground, `39` default foreground,
+//!   `40`-`47` / `100`-`107` background, `49` default background
+//! * `ESC [ 2 J` clears the screen, `ESC [ K` clears to the end of the line
+//!
+//! Other escape sequences are parsed and ignored. Characters outside of printable ASCII are
+//! shown as `?`.
+//!
+//! [`DrawTarget`]: embedded_graphics::draw_target::DrawTarget
+
+use core::fmt;
+
+/// Default foreground color (white)
+pub const DEFAULT_FG: u8 = 7;
+/// Default background color (black)
+pub const DEFAULT_BG: u8 = 0;
+
+const TAB_WIDTH: usize = 8;
+const MAX_PARAMS: usize = 8;
+const ESC: char = '\x1b';
+
+/// Colors of a character cell, as indices into the 16-color ANSI palette (0-7 normal, 8-15
+/// bright).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Attributes {
+    pub fg: u8,
+    pub bg: u8,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Cell {
+    /// Printable ASCII character
+    pub ch: u8,
+    pub attrs: Attributes,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ParseState {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// Terminal state for a console of `COLS` x `ROWS` characters.
+pub struct TextBuffer<const COLS: usize, const ROWS: usize> {
+    rows: [[Cell; COLS]; ROWS],
+    dirty: [bool; ROWS],
+    cursor_col: usize,
+    cursor_row: usize,
+    /// Set when a character was written to the last column. Wrapping is deferred until the
+    /// next character, so that a full line followed by `\n` does not produce an empty line.
+    wrap_pending: bool,
+    fg: u8,
+    bg: u8,
+    bold: bool,
+    inverse: bool,
+    state: ParseState,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+}
+
+impl Attributes {
+    pub const DEFAULT: Attributes = Attributes {
+        fg: DEFAULT_FG,
+        bg: DEFAULT_BG,
+    };
+}
+
+impl Cell {
+    pub const BLANK: Cell = Cell {
+        ch: b' ',
+        attrs: Attributes::DEFAULT,
+    };
+}
+
+impl<const COLS: usize, const ROWS: usize> TextBuffer<COLS, ROWS> {
+    /// Creates an empty buffer, with the cursor in the top left corner.
+    pub const fn new() -> Self {
+        Self {
+            rows: [[Cell::BLANK; COLS]; ROWS],
+            dirty: [true; ROWS],
+            cursor_col: 0,
+            cursor_row: 0,
+            wrap_pending: false,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            inverse: false,
+            state: ParseState::Ground,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+        }
+    }
+
+    /// Returns the cursor position, as `(column, row)`.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.cursor_col, self.cursor_row)
+    }
+
+    /// Returns the attributes applied to the next characters.
+    pub fn attributes(&self) -> Attributes {
+        let fg = if self.bold && self.fg < 8 {
+            self.fg + 8
+        } else {
+            self.fg
+        };
+        if self.inverse {
+            Attributes {
+                fg: self.bg,
+                bg: fg,
+            }
+        } else {
+            Attributes { fg, bg: self.bg }
+        }
+    }
+
+    pub fn row(&self, row: usize) -> &[Cell; COLS] {
+        &self.rows[row]
+    }
+
+    pub fn cell(&self, col: usize, row: usize) -> Cell {
+        self.rows[row][col]
+    }
+
+    /// Returns whether a row changed since it was last marked clean.
+    pub fn is_dirty(&self, row: usize) -> bool {
+        self.dirty[row]
+    }
+
+    pub fn mark_clean(&mut self, row: usize) {
+        self.dirty[row] = false;
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = [true; ROWS];
+    }
+
+    /// Clears the screen with the current background color and moves the cursor to the top
+    /// left corner.
+    pub fn clear(&mut self) {
+        let blank = self.blank();
+        self.rows = [[blank; COLS]; ROWS];
+        self.cursor_col = 0;
+        self.cursor_row = 0;
+        self.wrap_pending = false;
+        self.mark_all_dirty();
+    }
+
+    /// Processes a character, which may be part of an escape sequence.
+    pub fn put_char(&mut self, c: char) {
+        match self.state {
+            ParseState::Ground => self.put_ground(c),
+            ParseState::Escape => {
+                if c == '[' {
+                    self.params = [0; MAX_PARAMS];
+                    self.param_count = 0;
+                    self.state = ParseState::Csi;
+                } else {
+                    // Unsupported two-character sequence
+                    self.state = ParseState::Ground;
+                }
+            }
+            ParseState::Csi => self.put_csi(c),
+        }
+    }
+
+    fn put_ground(&mut self, c: char) {
+        match c {
+            ESC => self.state = ParseState::Escape,
+            '\n' => self.new_line(),
+            '\r' => {
+                self.cursor_col = 0;
+                self.wrap_pending = false;
+            }
+            '\t' => {
+                let next = (self.cursor_col / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.cursor_col < next.min(COLS - 1) && !self.wrap_pending {
+                    self.print(b' ');
+                }
+            }
+            '\x08' => {
+                self.cursor_col = self.cursor_col.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            ' '..='~' => self.print(c as u8),
+            c if c.is_control() => {}
+            _ => self.print(b'?'),
+        }
+    }
+
+    fn put_csi(&mut self, c: char) {
+        match c {
+            '0'..='9' => {
+                self.param_count = self.param_count.max(1);
+                let param = &mut self.params[self.param_count - 1];
+                *param = param
+                    .saturating_mul(10)
+                    .saturating_add(c as u16 - '0' as u16);
+            }
+            ';' => {
+                // An empty parameter before the separator still counts as 0
+                self.param_count = (self.param_count.max(1) + 1).min(MAX_PARAMS);
+            }
+            // Final byte, ends the sequence
+            '\x40'..='\x7e' => {
+                self.state = ParseState::Ground;
+                match c {
+                    'm' => self.select_graphic_rendition(),
+                    'J' if self.params[0] == 2 => self.clear(),
+                    'K' if self.params[0] == 0 => self.clear_to_end_of_line(),
+                    _ => {}
+                }
+            }
+            // Intermediate bytes and private markers are accepted and ignored
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        // ESC [ m is the same as ESC [ 0 m
+        let params = self.params;
+        for param in &params[..self.param_count.max(1)] {
+            match *param {
+                0 => {
+                    self.fg = DEFAULT_FG;
+                    self.bg = DEFAULT_BG;
+                    self.bold = false;
+                    self.inverse = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.inverse = true,
+                27 => self.inverse = false,
+                p @ 30..=37 => self.fg = (p - 30) as u8,
+                39 => self.fg = DEFAULT_FG,
+                p @ 40..=47 => self.bg = (p - 40) as u8,
+                49 => self.bg = DEFAULT_BG,
+                p @ 90..=97 => self.fg = (p - 90) as u8 + 8,
+                p @ 100..=107 => self.bg = (p - 100) as u8 + 8,
+                _ => {}
+            }
+        }
+    }
+
+    fn print(&mut self, ch: u8) {
+        if self.wrap_pending {
+            self.new_line();
+        }
+        self.rows[self.cursor_row][self.cursor_col] = Cell {
+            ch,
+            attrs: self.attributes(),
+        };
+        self.dirty[self.cursor_row] = true;
+        if self.cursor_col + 1 == COLS {
+            self.wrap_pending = true;
+        } else {
+            self.cursor_col += 1;
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.cursor_col = 0;
+        self.wrap_pending = false;
+        if self.cursor_row + 1 < ROWS {
+            self.cursor_row += 1;
+        } else {
+            self.scroll_up();
+        }
+    }
+
+    /// Moves every row up by one, clearing the last row.
+    fn scroll_up(&mut self) {
+        self.rows.rotate_left(1);
+        let blank = self.blank();
+        self.rows[ROWS - 1] = [blank; COLS];
+        self.mark_all_dirty();
+    }
+
+    fn clear_to_end_of_line(&mut self) {
+        let blank = self.blank();
+        self.rows[self.cursor_row][self.cursor_col..].fill(blank);
+        self.dirty[self.cursor_row] = true;
+    }
+
+    fn blank(&self) -> Cell {
+        Cell {
+            ch: b' ',
+            attrs: Attributes {
+                fg: DEFAULT_FG,
+                bg: self.attributes().bg,
+            },
+        }
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> Default for TextBuffer<COLS, ROWS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> fmt::Write for TextBuffer<COLS, ROWS> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.chars().for_each(|c| self.put_char(c));
+        Ok(())
+    }
+}
+
+#[cfg(feature = "graphics")]
+pub use renderer::{Console, ScreenConsole, PALETTE};
+
+#[cfg(feature = "graphics")]
+mod renderer {
+    use super::{Attributes, TextBuffer};
+    use core::fmt;
+    use embedded_graphics::draw_target::DrawTarget;
+    use embedded_graphics::geometry::Point;
+    use embedded_graphics::mono_font::ascii::FONT_6X10;
+    use embedded_graphics::mono_font::{MonoTextStyle, MonoTextStyleBuilder};
+    use embedded_graphics::pixelcolor::Rgb565;
+    use embedded_graphics::text::{Baseline, Text};
+    use embedded_graphics::Drawable;
+
+    const CHAR_WIDTH: usize = 6;
+    const CHAR_HEIGHT: usize = 10;
+
+    /// The 16-color ANSI palette (VGA colors), in RGB565.
+    pub const PALETTE: [Rgb565; 16] = [
+        Rgb565::new(0, 0, 0),
+        Rgb565::new(21, 0, 0),
+        Rgb565::new(0, 42, 0),
+        Rgb565::new(21, 21, 0),
+        Rgb565::new(0, 0, 21),
+        Rgb565::new(21, 0, 21),
+        Rgb565::new(0, 42, 21),
+        Rgb565::new(21, 42, 21),
+        Rgb565::new(10, 21, 10),
+        Rgb565::new(31, 21, 10),
+        Rgb565::new(10, 63, 10),
+        Rgb565::new(31, 63, 10),
+        Rgb565::new(10, 21, 31),
+        Rgb565::new(31, 21, 31),
+        Rgb565::new(10, 63, 31),
+        Rgb565::new(31, 63, 31),
+    ];
+
+    /// A console covering the whole 320x240 screen (53x24 characters).
+    pub type ScreenConsole<D> = Console<D, 53, 24>;
+
+    /// A [`TextBuffer`] drawn to a [`DrawTarget`] with the `FONT_6X10` font.
+    ///
+    /// Text written through [`fmt::Write`] is drawn immediately; only the rows that changed are
+    /// redrawn. Scrolling redraws the whole console.
+    pub struct Console<D, const COLS: usize, const ROWS: usize> {
+        target: D,
+        origin: Point,
+        buffer: TextBuffer<COLS, ROWS>,
+    }
+
+    impl<D, const COLS: usize, const ROWS: usize> Console<D, COLS, ROWS>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        /// Creates a console with its top left corner at the origin of the target.
+        pub fn new(target: D) -> Self {
+            Self::with_origin(target, Point::zero())
+        }
+
+        pub fn with_origin(target: D, origin: Point) -> Self {
+            Self {
+                target,
+                origin,
+                buffer: TextBuffer::new(),
+            }
+        }
+
+        pub fn buffer(&self) -> &TextBuffer<COLS, ROWS> {
+            &self.buffer
+        }
+
+        /// Returns the buffer for direct manipulation. Call [`flush`](Console::flush) to draw
+        /// the changes.
+        pub fn buffer_mut(&mut self) -> &mut TextBuffer<COLS, ROWS> {
+            &mut self.buffer
+        }
+
+        pub fn target_mut(&mut self) -> &mut D {
+            &mut self.target
+        }
+
+        pub fn into_inner(self) -> D {
+            self.target
+        }
+
+        pub fn clear(&mut self) -> Result<(), D::Error> {
+            self.buffer.clear();
+            self.flush()
+        }
+
+        /// Draws the rows that changed since the last flush.
+        pub fn flush(&mut self) -> Result<(), D::Error> {
+            for row in 0..ROWS {
+                if !self.buffer.is_dirty(row) {
+                    continue;
+                }
+                self.draw_row(row)?;
+                self.buffer.mark_clean(row);
+            }
+            Ok(())
+        }
+
+        /// Draws a row, one text run per sequence of cells with the same attributes.
+        fn draw_row(&mut self, row: usize) -> Result<(), D::Error> {
+            let cells = self.buffer.row(row);
+            let mut text = [0u8; COLS];
+            for (byte, cell) in text.iter_mut().zip(cells.iter()) {
+                *byte = cell.ch;
+            }
+
+            let y = self.origin.y + (row * CHAR_HEIGHT) as i32;
+            let mut start = 0;
+            while start < COLS {
+                let attrs = cells[start].attrs;
+                let end = cells[start..]
+                    .iter()
+                    .position(|cell| cell.attrs != attrs)
+                    .map_or(COLS, |len| start + len);
+                // Cells only hold printable ASCII
+                let run = core::str::from_utf8(&text[start..end]).unwrap_or("");
+                let x = self.origin.x + (start * CHAR_WIDTH) as i32;
+                Text::with_baseline(run, Point::new(x, y), style(attrs), Baseline::Top)
+                    .draw(&mut self.target)?;
+                start = end;
+            }
+            Ok(())
+        }
+    }
+
+    impl<D, const COLS: usize, const ROWS: usize> fmt::Write for Console<D, COLS, ROWS>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.buffer.write_str(s)?;
+            self.flush().map_err(|_| fmt::Error)
+        }
+    }
+
+    fn style(attrs: Attributes) -> MonoTextStyle<'static, Rgb565> {
+        MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(PALETTE[attrs.fg as usize & 0xf])
+            .background_color(PALETTE[attrs.bg as usize & 0xf])
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    fn text<const COLS: usize, const ROWS: usize>(
+        buf: &TextBuffer<COLS, ROWS>,
+        row: usize,
+    ) -> String {
+        buf.row(row).iter().map(|cell| cell.ch as char).collect()
+    }
+
+    fn attrs(fg: u8, bg: u8) -> Attributes {
+        Attributes { fg, bg }
+    }
+
+    #[test]
+    fn wrapping() {
+        let mut buf = TextBuffer::<4, 3>::new();
+        buf.write_str("abcdef").unwrap();
+        assert_eq!(text(&buf, 0), "abcd");
+        assert_eq!(text(&buf, 1), "ef  ");
+        assert_eq!(buf.cursor(), (2, 1));
+    }
+
+    #[test]
+    fn full_line_then_new_line() {
+        let mut buf = TextBuffer::<4, 3>::new();
+        // Wrapping is deferred, so this doesn't leave an empty line
+        buf.write_str("abcd\nef").unwrap();
+        assert_eq!(text(&buf, 1), "ef  ");
+        assert_eq!(text(&buf, 2), "    ");
+    }
+
+    #[test]
+    fn control_characters() {
+        let mut buf = TextBuffer::<12, 2>::new();
+        buf.write_str("abc\rX\x08Y\tZ").unwrap();
+        // Tabs write spaces over what's already there
+        assert_eq!(text(&buf, 0), "Y       Z   ");
+        // Tabs stop at the last column instead of wrapping
+        buf.write_str("\t\t").unwrap();
+        assert_eq!(buf.cursor(), (11, 0));
+        // Non-ASCII characters are replaced, other control characters ignored
+        buf.write_str("\n\u{e9}\x07!").unwrap();
+        assert_eq!(text(&buf, 1), "?!          ");
+    }
+
+    #[test]
+    fn scrolling() {
+        let mut buf = TextBuffer::<3, 2>::new();
+        buf.write_str("a\nb").unwrap();
+        for row in 0..2 {
+            buf.mark_clean(row);
+        }
+        buf.write_str("\nc").unwrap();
+        assert_eq!(text(&buf, 0), "b  ");
+        assert_eq!(text(&buf, 1), "c  ");
+        assert_eq!(buf.cursor(), (1, 1));
+        // Every row moved
+        assert!(buf.is_dirty(0) && buf.is_dirty(1));
+
+        // Wrapping on the last row scrolls too, but only once the next character comes
+        buf.write_str("de").unwrap();
+        assert_eq!(text(&buf, 0), "b  ");
+        assert_eq!(text(&buf, 1), "cde");
+        buf.write_str("f").unwrap();
+        assert_eq!(text(&buf, 0), "cde");
+        assert_eq!(text(&buf, 1), "f  ");
+    }
+
+    #[test]
+    fn sgr_colors() {
+        let mut buf = TextBuffer::<8, 1>::new();
+        buf.write_str("\x1b[31ma\x1b[1;44mb\x1b[22;97;103mc\x1b[39;49md")
+            .unwrap();
+        assert_eq!(buf.cell(0, 0).attrs, attrs(1, DEFAULT_BG));
+        assert_eq!(buf.cell(1, 0).attrs, attrs(9, 4));
+        assert_eq!(buf.cell(2, 0).attrs, attrs(15, 11));
+        assert_eq!(buf.cell(3, 0).attrs, Attributes::DEFAULT);
+        assert_eq!(text(&buf, 0), "abcd    ");
+    }
+
+    #[test]
+    fn sgr_inverse_and_reset() {
+        let mut buf = TextBuffer::<8, 1>::new();
+        buf.write_str("\x1b[32;7m").unwrap();
+        assert_eq!(buf.attributes(), attrs(DEFAULT_BG, 2));
+        buf.write_str("\x1b[27m").unwrap();
+        assert_eq!(buf.attributes(), attrs(2, DEFAULT_BG));
+        // Empty parameter lists reset everything
+        buf.write_str("\x1b[1;7;45m\x1b[m").unwrap();
+        assert_eq!(buf.attributes(), Attributes::DEFAULT);
+        buf.write_str("\x1b[1;;31m").unwrap();
+        assert_eq!(buf.attributes(), attrs(1, DEFAULT_BG));
+    }
+
+    #[test]
+    fn erase_sequences() {
+        let mut buf = TextBuffer::<4, 2>::new();
+        buf.write_str("abcd\nefgh\x1b[2D").unwrap();
+        // Cursor movement is ignored
+        assert_eq!(buf.cursor(), (3, 1));
+        buf.write_str("\r\x08\x1b[41m\x1b[K").unwrap();
+        assert_eq!(text(&buf, 1), "    ");
+        assert_eq!(buf.cell(0, 1).attrs, attrs(DEFAULT_FG, 1));
+        assert_eq!(text(&buf, 0), "abcd");
+
+        buf.write_str("\x1b[2J").unwrap();
+        assert_eq!(buf.cursor(), (0, 0));
+        assert_eq!(text(&buf, 0), "    ");
+        assert_eq!(buf.cell(3, 0).attrs, attrs(DEFAULT_FG, 1));
+    }
+
+    #[test]
+    fn unsupported_escapes() {
+        let mut buf = TextBuffer::<8, 1>::new();
+        // Two-character sequence, private mode, and a final byte that isn't handled
+        buf.write_str("\x1b7a\x1b[?25lb\x1b[1Jc").unwrap();
+        assert_eq!(text(&buf, 0), "abc     ");
+    }
+}
@@ -0,0 +1,302 @@
+//! Dirty rectangle tracking, used to only send changed parts of a frame to the LCD.
+//!
+//! This module does not depend on the hardware.
+
+/// Rectangular area of the screen. Both corners are inclusive.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Region {
+    pub x0: u16,
+    pub y0: u16,
+    pub x1: u16,
+    pub y1: u16,
+}
+
+/// A set of up to `N` regions that need to be redrawn.
+///
+/// Regions that overlap, or that can be merged without covering more area than the originals
+/// combined, are merged together. When the set is full, the new region is merged with the one
+/// that grows the least, so the set always covers every region that was added.
+#[derive(Clone, Debug)]
+pub struct DirtyRegions<const N: usize> {
+    regions: [Region; N],
+    len: usize,
+}
+
+impl Region {
+    pub fn new(x0: u16, y0: u16, x1: u16, y1: u16) -> Self {
+        debug_assert!(x0 <= x1 && y0 <= y1);
+        Self { x0, y0, x1, y1 }
+    }
+
+    /// Region covering a whole `width` x `height` screen.
+    ///
+    /// ## Panics
+    /// Panics if the screen is empty, since regions can't be.
+    pub fn full(width: u16, height: u16) -> Self {
+        assert!(width > 0 && height > 0, "empty screen");
+        Self::new(0, 0, width - 1, height - 1)
+    }
+
+    pub fn width(&self) -> u16 {
+        self.x1 - self.x0 + 1
+    }
+
+    pub fn height(&self) -> u16 {
+        self.y1 - self.y0 + 1
+    }
+
+    pub fn area(&self) -> u32 {
+        self.width() as u32 * self.height() as u32
+    }
+
+    pub fn union(&self, other: &Region) -> Region {
+        Region {
+            x0: self.x0.min(other.x0),
+            y0: self.y0.min(other.y0),
+            x1: self.x1.max(other.x1),
+            y1: self.y1.max(other.y1),
+        }
+    }
+
+    pub fn intersection(&self, other: &Region) -> Option<Region> {
+        let region = Region {
+            x0: self.x0.max(other.x0),
+            y0: self.y0.max(other.y0),
+            x1: self.x1.min(other.x1),
+            y1: self.y1.min(other.y1),
+        };
+        (region.x0 <= region.x1 && region.y0 <= region.y1).then_some(region)
+    }
+
+    pub fn contains(&self, other: &Region) -> bool {
+        self.x0 <= other.x0 && self.y0 <= other.y0 && self.x1 >= other.x1 && self.y1 >= other.y1
+    }
+
+    /// Overlapping regions are always merged. Otherwise, merging must not cover more area than
+    /// the two regions combined (e.g. for adjacent regions of the same height).
+    fn should_merge(&self, other: &Region) -> bool {
+        self.intersection(other).is_some() || self.union(other).area() <= self.area() + other.area()
+    }
+}
+
+impl<const N: usize> DirtyRegions<N> {
+    pub const fn new() -> Self {
+        assert!(N > 0, "at least one region is required");
+        Self {
+            regions: [Region {
+                x0: 0,
+                y0: 0,
+                x1: 0,
+                y1: 0,
+            }; N],
+            len: 0,
+        }
+    }
+
+    /// Marks a region as dirty.
+    pub fn add(&mut self, region: Region) {
+        let mut region = region;
+
+        // Absorb every region that can be merged, repeating since the grown region might now
+        // be mergeable with regions that were skipped before
+        let mut i = 0;
+        while i < self.len {
+            if region.should_merge(&self.regions[i]) {
+                region = region.union(&self.regions[i]);
+                self.remove(i);
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+
+        if self.len < N {
+            self.regions[self.len] = region;
+            self.len += 1;
+            return;
+        }
+
+        // Full, merge with the region that grows the least
+        let (best, _) = self.regions[..self.len]
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (i, r.union(&region).area() - r.area()))
+            .min_by_key(|&(_, growth)| growth)
+            .unwrap();
+        let merged = self.regions[best].union(&region);
+        self.remove(best);
+        self.add(merged);
+    }
+
+    /// Marks everything in `other` as dirty.
+    pub fn extend(&mut self, other: &DirtyRegions<N>) {
+        for region in other.iter() {
+            self.add(*region);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions[..self.len].iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Returns the smallest region covering all dirty regions.
+    pub fn bounds(&self) -> Option<Region> {
+        self.iter().copied().reduce(|a, b| a.union(&b))
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.regions.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+}
+
+impl<const N: usize> Default for DirtyRegions<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regions<const N: usize>(dirty: &DirtyRegions<N>) -> Vec<Region> {
+        dirty.iter().copied().collect()
+    }
+
+    #[test]
+    fn full() {
+        assert_eq!(Region::full(320, 240), Region::new(0, 0, 319, 239));
+        assert_eq!(Region::full(1, 1).area(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty screen")]
+    fn full_empty() {
+        Region::full(0, 0);
+    }
+
+    #[test]
+    fn intersection() {
+        let a = Region::new(0, 0, 9, 9);
+        assert_eq!(
+            a.intersection(&Region::new(5, 5, 20, 20)),
+            Some(Region::new(5, 5, 9, 9))
+        );
+        // Touching corners still share a pixel
+        assert_eq!(
+            a.intersection(&Region::new(9, 9, 10, 10)),
+            Some(Region::new(9, 9, 9, 9))
+        );
+        assert_eq!(a.intersection(&Region::new(10, 0, 19, 9)), None);
+        assert!(a.contains(&Region::new(2, 3, 9, 9)));
+        assert!(!a.contains(&Region::new(2, 3, 10, 9)));
+    }
+
+    #[test]
+    fn merge_overlapping() {
+        let mut dirty = DirtyRegions::<4>::new();
+        dirty.add(Region::new(0, 0, 9, 9));
+        dirty.add(Region::new(5, 5, 14, 14));
+        assert_eq!(regions(&dirty), [Region::new(0, 0, 14, 14)]);
+
+        // Contained regions don't change anything
+        dirty.add(Region::new(1, 1, 2, 2));
+        assert_eq!(regions(&dirty), [Region::new(0, 0, 14, 14)]);
+    }
+
+    #[test]
+    fn merge_adjacent() {
+        let mut dirty = DirtyRegions::<4>::new();
+        dirty.add(Region::new(0, 0, 9, 9));
+        // Same rows, right next to it: the union covers exactly both
+        dirty.add(Region::new(10, 0, 19, 9));
+        assert_eq!(regions(&dirty), [Region::new(0, 0, 19, 9)]);
+
+        // Diagonal neighbor: the union would also cover two untouched squares
+        dirty.add(Region::new(20, 10, 29, 19));
+        assert_eq!(dirty.len(), 2);
+    }
+
+    #[test]
+    fn merge_cascades() {
+        let mut dirty = DirtyRegions::<4>::new();
+        dirty.add(Region::new(0, 0, 9, 9));
+        dirty.add(Region::new(20, 0, 29, 9));
+        assert_eq!(dirty.len(), 2);
+        // Bridges the gap, and the grown region then absorbs both
+        dirty.add(Region::new(5, 0, 24, 9));
+        assert_eq!(regions(&dirty), [Region::new(0, 0, 29, 9)]);
+    }
+
+    #[test]
+    fn overflow_merges_least_growth() {
+        let mut dirty = DirtyRegions::<2>::new();
+        dirty.add(Region::new(0, 0, 9, 9));
+        dirty.add(Region::new(100, 100, 109, 109));
+        // Far from the first region, close to the second one
+        dirty.add(Region::new(100, 120, 109, 129));
+        assert_eq!(
+            regions(&dirty),
+            [Region::new(0, 0, 9, 9), Region::new(100, 100, 109, 129)]
+        );
+    }
+
+    #[test]
+    fn overflow_covers_everything() {
+        let mut dirty = DirtyRegions::<3>::new();
+        let added: Vec<_> = (0..20u16)
+            .map(|i| {
+                Region::new(
+                    i * 15 % 300,
+                    i * 37 % 200,
+                    i * 15 % 300 + 4,
+                    i * 37 % 200 + 4,
+                )
+            })
+            .collect();
+        for region in &added {
+            dirty.add(*region);
+            assert!(dirty.len() <= 3);
+        }
+        for region in &added {
+            assert!(dirty.iter().any(|r| r.contains(region)), "{region:?}");
+        }
+        // Kept regions never overlap, otherwise they would have been merged
+        let kept = regions(&dirty);
+        for (i, a) in kept.iter().enumerate() {
+            for b in &kept[i + 1..] {
+                assert_eq!(a.intersection(b), None);
+            }
+        }
+    }
+
+    #[test]
+    fn extend_bounds_clear() {
+        let mut a = DirtyRegions::<4>::new();
+        assert_eq!(a.bounds(), None);
+        a.add(Region::new(0, 0, 4, 4));
+
+        let mut b = DirtyRegions::<4>::new();
+        b.add(Region::new(50, 60, 54, 64));
+        a.extend(&b);
+        assert_eq!(a.len(), 2);
+        assert_eq!(a.bounds(), Some(Region::new(0, 0, 54, 64)));
+
+        a.clear();
+        assert!(a.is_empty());
+        assert_eq!(a.bounds(), None);
+    }
+}
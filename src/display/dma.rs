@@ -0,0 +1,284 @@
+//! Asynchronous pixel transfers to the LCD, using the MDMA controller.
+//!
+//! Streaming a full 320x240 frame with the CPU keeps the core busy for the whole transfer. The
+//! [`DisplayDma`] engine instead copies pixels from memory to the FMC in the background, while
+//! the CPU is free to prepare the next frame.
+//!
+//! Transfers complete by polling, or in the background with interrupts: the crate doesn't
+//! define the MDMA interrupt handler, so that applications can use the interrupt too. After
+//! [`DisplayDma::listen`], the application's handler must call [`DisplayDma::on_interrupt`]:
+//!
+//! ```no_run
+//! use alarmo::display::dma::DisplayDma;
+//! use stm32h7xx_hal::interrupt;
+//!
+//! #[interrupt]
+//! fn MDMA() {
+//!     DisplayDma::on_interrupt();
+//! }
+//! ```
+
+use super::bus::DATA16_PTR;
+use super::{AlarmoDisplay, RAMWR};
+use crate::input::ExtInterrupts;
+use crate::pac::mdma;
+use core::cell::RefCell;
+use cortex_m::interrupt::{CriticalSection, Mutex};
+use cortex_m::peripheral::NVIC;
+use embedded_dma::ReadBuffer;
+use stm32h7xx_hal::interrupt;
+use stm32h7xx_hal::pac::MDMA;
+use stm32h7xx_hal::rcc::rec::Mdma;
+use stm32h7xx_hal::rcc::ResetEnable;
+
+/// MDMA engine dedicated to LCD transfers.
+///
+/// See [`write_region`] for details.
+///
+/// [`write_region`]: DisplayDma::write_region
+pub struct DisplayDma {
+    _mdma: MDMA,
+}
+
+/// An in-progress DMA transfer to the LCD.
+///
+/// The transfer owns the pixel buffer, which is handed back by [`wait`](DmaTransfer::wait).
+/// Dropping the transfer blocks until it completes. The display stays borrowed until then.
+///
+/// **Note**: leaking the transfer (e.g. with [`core::mem::forget`]) also leaks the buffer, so
+/// it stays valid, but releases the display while the MDMA may still be writing to it.
+pub struct DmaTransfer<'a, B> {
+    display: &'a mut AlarmoDisplay,
+    _dma: &'a mut DisplayDma,
+    buf: Option<B>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DmaError {
+    /// The MDMA reported a bus error, e.g. because the buffer is in memory it can't access
+    TransferError,
+}
+
+struct DmaState {
+    /// Address of the next pixel to transfer
+    next: usize,
+    /// Number of pixels left to transfer after the current segment
+    remaining: usize,
+    active: bool,
+    error: bool,
+    handler: Option<fn(&CriticalSection)>,
+}
+
+static STATE: Mutex<RefCell<DmaState>> = Mutex::new(RefCell::new(DmaState {
+    next: 0,
+    remaining: 0,
+    active: false,
+    error: false,
+    handler: None,
+}));
+
+impl DisplayDma {
+    pub(crate) fn new(mdma: MDMA, rcc: Mdma) -> Self {
+        rcc.enable().reset();
+        Self { _mdma: mdma }
+    }
+
+    /// Registers a function to be called when a transfer completes (successfully or not), and
+    /// enables the MDMA interrupt.
+    ///
+    /// The invocation takes place in a critical section, in interrupt context. The MDMA
+    /// interrupt handler must call [`on_interrupt`](Self::on_interrupt), see the
+    /// [module documentation](self).
+    pub fn listen(&mut self, cfg: &mut ExtInterrupts, handler: fn(&CriticalSection)) {
+        cortex_m::interrupt::free(|cs| {
+            STATE.borrow(cs).borrow_mut().handler = Some(handler);
+        });
+
+        unsafe {
+            cfg.nvic.set_priority(interrupt::MDMA, 2);
+            NVIC::unmask(interrupt::MDMA);
+        }
+    }
+
+    /// Advances the current transfer, and calls the function registered with
+    /// [`listen`](Self::listen) when it's over. Must be called by the MDMA interrupt handler.
+    pub fn on_interrupt() {
+        cortex_m::interrupt::free(|cs| {
+            let mut state = STATE.borrow(cs).borrow_mut();
+            service(&mut state, cs);
+            if !state.active {
+                // Spurious interrupt or transfer over, make sure the flags are cleared
+                mdma::stop(unsafe { &*MDMA::ptr() });
+            }
+        })
+    }
+
+    /// Disables the MDMA interrupt. Transfers can still be completed by polling.
+    pub fn unlisten(&mut self) {
+        NVIC::mask(interrupt::MDMA);
+        cortex_m::interrupt::free(|cs| {
+            STATE.borrow(cs).borrow_mut().handler = None;
+        });
+    }
+
+    /// Starts streaming RGB565 pixels into a region of the display. Both corners are inclusive,
+    /// in the coordinates of the current memory access mode.
+    ///
+    /// The buffer can be anywhere the MDMA can read from, including external RAM. Dirty cache
+    /// lines covering the buffer are cleaned before the transfer starts. Since the MDMA keeps
+    /// reading from it in the background, the buffer must be owned (e.g. a `Box<[u16]>`) or
+    /// `'static`, and is returned when the transfer is over.
+    ///
+    /// Progress can be checked with [`DmaTransfer::is_done`], or by registering a completion
+    /// handler with [`listen`](DisplayDma::listen).
+    ///
+    /// ## Panics
+    /// Panics if `x0 > x1` or `y0 > y1`, or if `pixels` does not contain exactly as many pixels
+    /// as the region.
+    pub fn write_region<'a, B: ReadBuffer<Word = u16>>(
+        &'a mut self,
+        display: &'a mut AlarmoDisplay,
+        x0: u16,
+        y0: u16,
+        x1: u16,
+        y1: u16,
+        pixels: B,
+    ) -> DmaTransfer<'a, B> {
+        assert!(x0 <= x1 && y0 <= y1, "inverted region");
+        let width = (x1 - x0) as usize + 1;
+        let height = (y1 - y0) as usize + 1;
+        // Safety: `ReadBuffer` guarantees the pointer stays valid for as long as the buffer
+        // isn't dropped, which only happens once the transfer is over
+        let (ptr, len) = unsafe { pixels.read_buffer() };
+        assert_eq!(len, width * height, "region size mismatch");
+
+        display.set_address_window(x0, y0, x1, y1);
+        self.start(display, ptr, len);
+        DmaTransfer {
+            display,
+            _dma: self,
+            buf: Some(pixels),
+        }
+    }
+
+    /// Starts streaming a full frame. See [`write_region`](DisplayDma::write_region).
+    ///
+    /// ## Panics
+    /// Panics if `width` or `height` is 0, or if `frame` does not contain exactly
+    /// `width * height` pixels.
+    pub fn write_frame<'a, B: ReadBuffer<Word = u16>>(
+        &'a mut self,
+        display: &'a mut AlarmoDisplay,
+        width: u16,
+        height: u16,
+        frame: B,
+    ) -> DmaTransfer<'a, B> {
+        assert!(width > 0 && height > 0, "empty frame");
+        self.write_region(display, 0, 0, width - 1, height - 1, frame)
+    }
+
+    fn start(&mut self, display: &mut AlarmoDisplay, ptr: *const u16, len: usize) {
+        // The MDMA reads from memory, not from the cache
+        unsafe {
+            // The cache functions do not actually use self
+            cortex_m::Peripherals::steal()
+                .SCB
+                .clean_dcache_by_address(ptr as usize, len * 2);
+        }
+
+        display.write_command(RAMWR);
+
+        cortex_m::interrupt::free(|cs| {
+            let mut state = STATE.borrow(cs).borrow_mut();
+            state.next = ptr as usize;
+            state.remaining = len;
+            state.error = false;
+            state.active = len > 0;
+            if state.active {
+                unsafe { start_segment(&mut state) };
+            }
+        });
+    }
+}
+
+impl<B> DmaTransfer<'_, B> {
+    /// Returns whether the transfer is over, advancing it if interrupts are not enabled.
+    pub fn is_done(&self) -> bool {
+        cortex_m::interrupt::free(|cs| {
+            let mut state = STATE.borrow(cs).borrow_mut();
+            service(&mut state, cs);
+            !state.active
+        })
+    }
+
+    /// Blocks until the transfer is over, and returns the pixel buffer.
+    pub fn wait(mut self) -> (Result<(), DmaError>, B) {
+        while !self.is_done() {}
+        let error = cortex_m::interrupt::free(|cs| STATE.borrow(cs).borrow().error);
+        let buf = self.buf.take().unwrap();
+        // Deselects the display
+        drop(self);
+        let result = if error {
+            Err(DmaError::TransferError)
+        } else {
+            Ok(())
+        };
+        (result, buf)
+    }
+}
+
+impl<B> Drop for DmaTransfer<'_, B> {
+    fn drop(&mut self) {
+        while !self.is_done() {}
+        self.display.pin_select(false);
+    }
+}
+
+/// Starts the next part of the transfer. Large buffers need multiple MDMA transfers, as the
+/// maximum for a single one is 4096 blocks of 64 KiB, or a single block of arbitrary (even)
+/// size.
+unsafe fn start_segment(state: &mut DmaState) {
+    let bytes = state.remaining * 2;
+    let (block_bytes, blocks) = if bytes > mdma::MAX_BLOCK_BYTES {
+        (
+            mdma::MAX_BLOCK_BYTES,
+            (bytes / mdma::MAX_BLOCK_BYTES).min(mdma::MAX_BLOCK_REPEAT),
+        )
+    } else {
+        (bytes, 1)
+    };
+
+    mdma::start_to_fixed_half_word(
+        &*MDMA::ptr(),
+        state.next as u32,
+        DATA16_PTR as u32,
+        block_bytes,
+        blocks,
+        state.handler.is_some(),
+    );
+    state.next += block_bytes * blocks;
+    state.remaining -= block_bytes * blocks / 2;
+}
+
+fn service(state: &mut DmaState, cs: &CriticalSection) {
+    if !state.active {
+        return;
+    }
+    let regs = unsafe { &*MDMA::ptr() };
+    if mdma::has_error(regs) {
+        state.error = true;
+    } else if !mdma::is_complete(regs) {
+        return;
+    }
+    mdma::stop(regs);
+
+    if !state.error && state.remaining > 0 {
+        unsafe { start_segment(state) };
+        return;
+    }
+
+    state.active = false;
+    if let Some(handler) = state.handler {
+        handler(cs);
+    }
+}
@@ -0,0 +1,430 @@
+//! RGB565 frame buffers in RAM, flushed to the LCD by only sending the parts that changed.
+//!
+//! Buffers are allocated on the heap, which lives in external RAM (OCTOSPI2). A full 320x240
+//! buffer takes 150 KiB.
+
+use super::bus::DisplayBus;
+use super::dirty::{DirtyRegions, Region};
+use super::AlarmoDisplay;
+use alloc::boxed::Box;
+use alloc::vec;
+use core::convert::Infallible;
+use embedded_graphics::geometry::{Dimensions, OriginDimensions, Point, Size};
+use embedded_graphics::pixelcolor::{IntoStorage, Rgb565};
+use embedded_graphics::primitives::{PointsIter, Rectangle};
+use embedded_graphics::{draw_target::DrawTarget, Pixel};
+
+/// Maximum number of separate dirty regions tracked by a frame buffer
+pub const MAX_DIRTY_REGIONS: usize = 8;
+
+/// A single frame buffer, implementing [`DrawTarget`].
+///
+/// Drawing only updates memory. Call [`flush`] to send the changed regions to the LCD.
+///
+/// The buffer layout must match the screen's current orientation, e.g. 320x240 for the
+/// default landscape orientation of [`AlarmoScreen`].
+///
+/// [`flush`]: FrameBuffer::flush
+/// [`AlarmoScreen`]: super::AlarmoScreen
+pub struct FrameBuffer {
+    pixels: Box<[u16]>,
+    width: u16,
+    height: u16,
+    dirty: DirtyRegions<MAX_DIRTY_REGIONS>,
+}
+
+/// Two frame buffers: drawing goes to the back buffer, while the front buffer holds the last
+/// completed frame.
+///
+/// After [`swap`], the front buffer can be flushed while the next frame is drawn.
+///
+/// [`swap`]: DoubleFrameBuffer::swap
+pub struct DoubleFrameBuffer {
+    front: FrameBuffer,
+    back: FrameBuffer,
+}
+
+impl FrameBuffer {
+    /// Allocates a black frame buffer. The whole buffer starts out dirty, so the first flush
+    /// overwrites the screen.
+    ///
+    /// ## Panics
+    /// Panics if `width` or `height` is 0.
+    pub fn new(width: u16, height: u16) -> Self {
+        let mut dirty = DirtyRegions::new();
+        dirty.add(Region::full(width, height));
+        Self {
+            pixels: vec![0u16; width as usize * height as usize].into_boxed_slice(),
+            width,
+            height,
+            dirty,
+        }
+    }
+
+    /// Returns the raw RGB565 pixels, in row-major order.
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    /// Returns the raw RGB565 pixels for writing. Changes made this way must be reported with
+    /// [`mark_dirty`](FrameBuffer::mark_dirty).
+    pub fn pixels_mut(&mut self) -> &mut [u16] {
+        &mut self.pixels
+    }
+
+    pub fn mark_dirty(&mut self, region: Region) {
+        if let Some(region) = region.intersection(&Region::full(self.width, self.height)) {
+            self.dirty.add(region);
+        }
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.clear();
+        self.dirty.add(Region::full(self.width, self.height));
+    }
+
+    pub fn dirty_regions(&self) -> &DirtyRegions<MAX_DIRTY_REGIONS> {
+        &self.dirty
+    }
+
+    /// Sends all dirty regions to the LCD.
+    pub fn flush<B: DisplayBus>(&mut self, display: &mut AlarmoDisplay<B>) {
+        for region in self.dirty.iter() {
+            display.set_address_window(region.x0, region.y0, region.x1, region.y1);
+            let width = self.width as usize;
+            let rows = region.y0 as usize..=region.y1 as usize;
+            let cols = region.x0 as usize..=region.x1 as usize;
+            display.write_pixels(
+                rows.flat_map(|y| self.pixels[y * width..][cols.clone()].iter().copied()),
+            );
+        }
+        self.dirty.clear();
+    }
+
+    fn copy_region_from(&mut self, other: &FrameBuffer, region: &Region) {
+        let width = self.width as usize;
+        for y in region.y0 as usize..=region.y1 as usize {
+            let row = y * width + region.x0 as usize..=y * width + region.x1 as usize;
+            self.pixels[row.clone()].copy_from_slice(&other.pixels[row]);
+        }
+    }
+}
+
+impl DoubleFrameBuffer {
+    /// Allocates two black frame buffers.
+    ///
+    /// ## Panics
+    /// Panics if `width` or `height` is 0.
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            front: FrameBuffer::new(width, height),
+            back: FrameBuffer::new(width, height),
+        }
+    }
+
+    /// Returns the last completed frame.
+    pub fn front(&self) -> &FrameBuffer {
+        &self.front
+    }
+
+    /// Returns the frame being drawn.
+    pub fn back(&mut self) -> &mut FrameBuffer {
+        &mut self.back
+    }
+
+    /// Makes the back buffer the new front buffer.
+    ///
+    /// Regions drawn since the last swap become dirty in the front buffer (together with any
+    /// regions that were not flushed yet), and are copied into the new back buffer so that
+    /// drawing can continue incrementally.
+    pub fn swap(&mut self) {
+        core::mem::swap(&mut self.front, &mut self.back);
+
+        // The old front buffer may have regions that were never sent to the screen
+        let unflushed = self.back.dirty.clone();
+        self.front.dirty.extend(&unflushed);
+        self.back.dirty.clear();
+
+        let (front, back) = (&self.front, &mut self.back);
+        for region in front.dirty.iter() {
+            back.copy_region_from(front, region);
+        }
+    }
+
+    /// Sends the dirty regions of the front buffer to the LCD.
+    pub fn flush<B: DisplayBus>(&mut self, display: &mut AlarmoDisplay<B>) {
+        self.front.flush(display);
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let bounds = self.bounding_box();
+        // Report a single region per call, individual pixels would quickly fill the set
+        let mut touched: Option<Region> = None;
+        for Pixel(point, color) in pixels {
+            if bounds.contains(point) {
+                let (x, y) = (point.x as u16, point.y as u16);
+                self.pixels[y as usize * self.width as usize + x as usize] = color.into_storage();
+                let pixel = Region::new(x, y, x, y);
+                touched = Some(touched.map_or(pixel, |r| r.union(&pixel)));
+            }
+        }
+        if let Some(region) = touched {
+            self.dirty.add(region);
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let drawable = area.intersection(&self.bounding_box());
+        let Some(region) = to_region(&drawable) else {
+            return Ok(());
+        };
+        let width = self.width as usize;
+        for (point, color) in area.points().zip(colors) {
+            if drawable.contains(point) {
+                self.pixels[point.y as usize * width + point.x as usize] = color.into_storage();
+            }
+        }
+        self.dirty.add(region);
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let drawable = area.intersection(&self.bounding_box());
+        let Some(region) = to_region(&drawable) else {
+            return Ok(());
+        };
+        let width = self.width as usize;
+        for y in region.y0 as usize..=region.y1 as usize {
+            self.pixels[y * width + region.x0 as usize..=y * width + region.x1 as usize]
+                .fill(color.into_storage());
+        }
+        self.dirty.add(region);
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.pixels.fill(color.into_storage());
+        self.mark_all_dirty();
+        Ok(())
+    }
+}
+
+impl OriginDimensions for DoubleFrameBuffer {
+    fn size(&self) -> Size {
+        self.back.size()
+    }
+}
+
+impl DrawTarget for DoubleFrameBuffer {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.back.draw_iter(pixels)
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        self.back.fill_contiguous(area, colors)
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.back.fill_solid(area, color)
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.back.clear(color)
+    }
+}
+
+/// Converts a clipped rectangle into a region, or `None` if it is empty.
+fn to_region(rect: &Rectangle) -> Option<Region> {
+    let bottom_right: Point = rect.bottom_right()?;
+    Some(Region::new(
+        rect.top_left.x as u16,
+        rect.top_left.y as u16,
+        bottom_right.x as u16,
+        bottom_right.y as u16,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bus::mock::{BusOp, RecordingBus};
+    use super::super::{CASET, RAMWR, RASET};
+    use super::*;
+    use embedded_graphics::pixelcolor::RgbColor;
+    use embedded_graphics::Drawable;
+
+    const RED: u16 = 0xf800;
+    const BLUE: u16 = 0x001f;
+
+    /// Splits the bus log into the regions written, with their pixels.
+    fn writes(ops: &[BusOp]) -> Vec<(Region, Vec<u16>)> {
+        let mut writes = Vec::new();
+        let (mut command, mut columns, mut rows) = (0, Vec::new(), Vec::new());
+        for op in ops {
+            match *op {
+                BusOp::Command(cmd) => {
+                    command = cmd;
+                    match cmd {
+                        CASET => columns.clear(),
+                        RASET => rows.clear(),
+                        _ => {}
+                    }
+                    if cmd == RAMWR {
+                        let coord = |p: &[u8], i: usize| u16::from_be_bytes([p[i], p[i + 1]]);
+                        let region = Region::new(
+                            coord(&columns, 0),
+                            coord(&rows, 0),
+                            coord(&columns, 2),
+                            coord(&rows, 2),
+                        );
+                        writes.push((region, Vec::new()));
+                    }
+                }
+                BusOp::Data8(byte) if command == CASET => columns.push(byte),
+                BusOp::Data8(byte) if command == RASET => rows.push(byte),
+                BusOp::Data16(pixel) if command == RAMWR => {
+                    writes.last_mut().unwrap().1.push(pixel)
+                }
+                _ => {}
+            }
+        }
+        writes
+    }
+
+    fn display() -> AlarmoDisplay<RecordingBus> {
+        AlarmoDisplay::from_bus(RecordingBus::default())
+    }
+
+    #[test]
+    fn new() {
+        let buffer = FrameBuffer::new(40, 30);
+        assert_eq!(buffer.pixels().len(), 40 * 30);
+        assert!(buffer.pixels().iter().all(|&p| p == 0));
+        assert_eq!(
+            buffer.dirty_regions().iter().collect::<Vec<_>>(),
+            [&Region::full(40, 30)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "empty screen")]
+    fn new_zero_width() {
+        FrameBuffer::new(0, 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty screen")]
+    fn new_zero_height() {
+        DoubleFrameBuffer::new(40, 0);
+    }
+
+    #[test]
+    fn flush_dirty_regions() {
+        let mut display = display();
+        let mut buffer = FrameBuffer::new(40, 30);
+        buffer.flush(&mut display);
+        let full = writes(&display.bus_mut().take());
+        assert_eq!(full.len(), 1);
+        assert_eq!(full[0].0, Region::full(40, 30));
+        assert_eq!(full[0].1.len(), 40 * 30);
+
+        // Nothing left to send
+        buffer.flush(&mut display);
+        assert!(display.bus_mut().take().is_empty());
+
+        buffer
+            .fill_solid(
+                &Rectangle::new(Point::new(2, 1), Size::new(3, 2)),
+                Rgb565::RED,
+            )
+            .unwrap();
+        buffer
+            .fill_solid(
+                &Rectangle::new(Point::new(30, 20), Size::new(1, 1)),
+                Rgb565::BLUE,
+            )
+            .unwrap();
+        buffer.flush(&mut display);
+        let mut sent = writes(&display.bus_mut().take());
+        sent.sort_by_key(|(region, _)| region.x0);
+        assert_eq!(
+            sent,
+            [
+                (Region::new(2, 1, 4, 2), vec![RED; 6]),
+                (Region::new(30, 20, 30, 20), vec![BLUE]),
+            ]
+        );
+        assert!(buffer.dirty_regions().is_empty());
+    }
+
+    #[test]
+    fn swap() {
+        let mut display = display();
+        let mut buffers = DoubleFrameBuffer::new(40, 30);
+        buffers.swap();
+        buffers.flush(&mut display);
+        display.bus_mut().take();
+
+        // Drawing only changes the back buffer
+        Pixel(Point::new(1, 1), Rgb565::RED)
+            .draw(buffers.back())
+            .unwrap();
+        assert_eq!(buffers.front().pixels()[40 + 1], 0);
+        buffers.flush(&mut display);
+        assert!(display.bus_mut().take().is_empty());
+
+        // The new front buffer has the drawing, and it's copied to the new back buffer
+        buffers.swap();
+        assert_eq!(buffers.front().pixels()[40 + 1], RED);
+        assert_eq!(buffers.back().pixels()[40 + 1], RED);
+        assert_eq!(
+            buffers.front().dirty_regions().iter().collect::<Vec<_>>(),
+            [&Region::new(1, 1, 1, 1)]
+        );
+        assert!(buffers.back().dirty_regions().is_empty());
+
+        // Regions that were not flushed before the next swap are still sent
+        Pixel(Point::new(30, 20), Rgb565::BLUE)
+            .draw(buffers.back())
+            .unwrap();
+        buffers.swap();
+        assert_eq!(buffers.back().pixels()[20 * 40 + 30], BLUE);
+        assert_eq!(buffers.back().pixels()[40 + 1], RED);
+        buffers.flush(&mut display);
+        let mut sent = writes(&display.bus_mut().take());
+        sent.sort_by_key(|(region, _)| region.x0);
+        assert_eq!(
+            sent,
+            [
+                (Region::new(1, 1, 1, 1), vec![RED]),
+                (Region::new(30, 20, 30, 20), vec![BLUE]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Windows bitmap (BMP) decoder.
+//!
+//! Supports uncompressed images with a `BITMAPINFOHEADER` or later header: 1, 2, 4 and 8-bit
+//! palette images, and 16, 24 and 32-bit images (`BI_RGB` or `BI_BITFIELDS`), stored either
+//! bottom-up or top-down. Alpha channels are ignored.
+
+use super::{rgb888_to_rgb565, ImageDecoder, ImageError, MAX_WIDTH};
+
+const FILE_HEADER_LEN: usize = 14;
+const MIN_INFO_HEADER_LEN: usize = 40;
+/// Offset of the color masks for `BI_BITFIELDS`, right after a `BITMAPINFOHEADER`
+const MASKS_OFFSET: usize = FILE_HEADER_LEN + MIN_INFO_HEADER_LEN;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+/// A BMP image, decoded on the fly.
+pub struct Bmp<'a> {
+    pixels: &'a [u8],
+    palette: &'a [u8],
+    width: u16,
+    height: u16,
+    top_down: bool,
+    bits_per_pixel: u16,
+    /// Red, green and blue masks for 16 and 32-bit images
+    masks: [u32; 3],
+    stride: usize,
+}
+
+impl<'a> Bmp<'a> {
+    /// Parses the headers of a BMP file.
+    pub fn new(data: &'a [u8]) -> Result<Self, ImageError> {
+        if data.get(0..2) != Some(b"BM") {
+            return Err(ImageError::BadSignature);
+        }
+        if data.len() < MASKS_OFFSET {
+            return Err(ImageError::Truncated);
+        }
+        let pixels_offset = u32_le(data, 10) as usize;
+        let info_len = u32_le(data, 14) as usize;
+        if info_len < MIN_INFO_HEADER_LEN {
+            // OS/2 headers
+            return Err(ImageError::Unsupported);
+        }
+        let width = u32_le(data, 18) as i32;
+        let height = u32_le(data, 22) as i32;
+        let bits_per_pixel = u16_le(data, 28);
+        let compression = u32_le(data, 30);
+        let colors_used = u32_le(data, 46) as usize;
+
+        if width <= 0 || width as usize > MAX_WIDTH || height == 0 || height.unsigned_abs() > 0xffff
+        {
+            return Err(ImageError::BadDimensions);
+        }
+        let width = width as u16;
+        let top_down = height < 0;
+        let height = height.unsigned_abs() as u16;
+
+        let masks = match (compression, bits_per_pixel) {
+            (BI_RGB, 1 | 2 | 4 | 8 | 24) => [0; 3],
+            (BI_RGB, 16) => [0x7c00, 0x03e0, 0x001f],
+            (BI_RGB, 32) => [0xff0000, 0x00ff00, 0x0000ff],
+            (BI_BITFIELDS | BI_ALPHABITFIELDS, 16 | 32) => {
+                if data.len() < MASKS_OFFSET + 12 {
+                    return Err(ImageError::Truncated);
+                }
+                [
+                    u32_le(data, MASKS_OFFSET),
+                    u32_le(data, MASKS_OFFSET + 4),
+                    u32_le(data, MASKS_OFFSET + 8),
+                ]
+            }
+            _ => return Err(ImageError::Unsupported),
+        };
+
+        let palette = if bits_per_pixel <= 8 {
+            let entries = match colors_used {
+                0 => 1 << bits_per_pixel,
+                n => n.min(1 << bits_per_pixel),
+            };
+            data.get(FILE_HEADER_LEN..)
+                .and_then(|d| d.get(info_len..))
+                .and_then(|d| d.get(..entries * 4))
+                .ok_or(ImageError::Truncated)?
+        } else {
+            &[]
+        };
+
+        let stride = (width as usize * bits_per_pixel as usize).div_ceil(32) * 4;
+        let pixels = data
+            .get(pixels_offset..)
+            .and_then(|d| d.get(..stride * height as usize))
+            .ok_or(ImageError::Truncated)?;
+
+        Ok(Self {
+            pixels,
+            palette,
+            width,
+            height,
+            top_down,
+            bits_per_pixel,
+            masks,
+            stride,
+        })
+    }
+
+    fn pixel(&self, row: &[u8], x: usize) -> u16 {
+        match self.bits_per_pixel {
+            24 => rgb888_to_rgb565(row[x * 3 + 2], row[x * 3 + 1], row[x * 3]),
+            16 => self.masked_rgb565(u16_le(row, x * 2) as u32),
+            32 => self.masked_rgb565(u32_le(row, x * 4)),
+            bits => {
+                let bit = x * bits as usize;
+                let shift = 8 - bits as usize - bit % 8;
+                let index = (row[bit / 8] >> shift) as usize & ((1 << bits) - 1);
+                match self.palette.get(index * 4..index * 4 + 3) {
+                    Some(&[b, g, r]) => rgb888_to_rgb565(r, g, b),
+                    _ => 0,
+                }
+            }
+        }
+    }
+
+    fn masked_rgb565(&self, value: u32) -> u16 {
+        let [r, g, b] = self.masks;
+        (scale_channel(value, r, 5) << 11)
+            | (scale_channel(value, g, 6) << 5)
+            | scale_channel(value, b, 5)
+    }
+}
+
+impl ImageDecoder for Bmp<'_> {
+    fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn for_each_row(&self, f: &mut dyn FnMut(u16, &[u16])) -> Result<(), ImageError> {
+        let mut buf = [0u16; MAX_WIDTH];
+        let out = &mut buf[..self.width as usize];
+        for y in 0..self.height {
+            let stored = if self.top_down {
+                y
+            } else {
+                self.height - 1 - y
+            };
+            let start = stored as usize * self.stride;
+            let row = &self.pixels[start..start + self.stride];
+            for (x, pixel) in out.iter_mut().enumerate() {
+                *pixel = self.pixel(row, x);
+            }
+            f(y, out);
+        }
+        Ok(())
+    }
+}
+
+/// Extracts a channel with `mask` from `value`, scaled to `bits` bits.
+fn scale_channel(value: u32, mask: u32, bits: u32) -> u16 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = mask >> shift;
+    let width = u32::BITS - max.leading_zeros();
+    let channel = (value & mask) >> shift;
+    if width >= bits {
+        // Same as truncating 8-bit channels in rgb888_to_rgb565
+        (channel >> (width - bits)) as u16
+    } else {
+        (channel * ((1 << bits) - 1) / max) as u16
+    }
+}
+
+fn u16_le(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_le(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::image::tests::{decode, fixture};
+
+    fn check(data: &[u8], expected: Vec<u16>) {
+        let bmp = Bmp::new(data).unwrap();
+        assert_eq!(bmp.size(), (7, 5));
+        assert_eq!(decode(&bmp).unwrap(), expected);
+    }
+
+    #[test]
+    fn palette() {
+        let (data, expected) = fixture!("bmp_1.bmp");
+        check(data, expected);
+        let (data, expected) = fixture!("bmp_2.bmp");
+        check(data, expected);
+        let (data, expected) = fixture!("bmp_4.bmp");
+        check(data, expected);
+        let (data, expected) = fixture!("bmp_8.bmp");
+        check(data, expected);
+        // Palette shorter than 256 entries
+        let (data, expected) = fixture!("bmp_8_colors_used.bmp");
+        check(data, expected);
+    }
+
+    #[test]
+    fn true_color() {
+        let (data, expected) = fixture!("bmp_16.bmp");
+        check(data, expected);
+        let (data, expected) = fixture!("bmp_16_bitfields.bmp");
+        check(data, expected);
+        let (data, expected) = fixture!("bmp_24.bmp");
+        check(data, expected);
+        let (data, expected) = fixture!("bmp_32.bmp");
+        check(data, expected);
+        // 10-bit channels
+        let (data, expected) = fixture!("bmp_32_bitfields.bmp");
+        check(data, expected);
+    }
+
+    #[test]
+    fn top_down() {
+        let (data, expected) = fixture!("bmp_24_top_down.bmp");
+        check(data, expected.clone());
+        let (bottom_up, _) = fixture!("bmp_24.bmp");
+        assert_eq!(decode(&Bmp::new(bottom_up).unwrap()).unwrap(), expected);
+    }
+
+    fn patched(data: &[u8], offset: usize, bytes: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        data
+    }
+
+    #[test]
+    fn errors() {
+        let (data, _) = fixture!("bmp_24.bmp");
+        assert_eq!(
+            Bmp::new(&patched(data, 0, b"MB")).err(),
+            Some(ImageError::BadSignature)
+        );
+        assert_eq!(Bmp::new(&data[..40]).err(), Some(ImageError::Truncated));
+        assert_eq!(
+            Bmp::new(&data[..data.len() - 1]).err(),
+            Some(ImageError::Truncated)
+        );
+        // OS/2 header
+        assert_eq!(
+            Bmp::new(&patched(data, 14, &12u32.to_le_bytes())).err(),
+            Some(ImageError::Unsupported)
+        );
+        // RLE compression
+        let (palette, _) = fixture!("bmp_8.bmp");
+        assert_eq!(
+            Bmp::new(&patched(palette, 30, &1u32.to_le_bytes())).err(),
+            Some(ImageError::Unsupported)
+        );
+        // Palette cut short
+        assert_eq!(
+            Bmp::new(&palette[..MASKS_OFFSET + 100]).err(),
+            Some(ImageError::Truncated)
+        );
+
+        for (offset, value) in [(18, 0), (18, MAX_WIDTH as i32 + 1), (18, -7), (22, 0)] {
+            assert_eq!(
+                Bmp::new(&patched(data, offset, &value.to_le_bytes())).err(),
+                Some(ImageError::BadDimensions)
+            );
+        }
+    }
+}
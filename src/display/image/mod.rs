@@ -0,0 +1,216 @@
+//! Decoding of BMP, QOI and PNG images to RGB565, e.g. for splash screens and icons stored on
+//! the eMMC.
+//!
+//! Decoders work on images already read into memory, and have no hardware dependencies.
+//! BMP and QOI images are decoded one row at a time, without a heap. PNG images (feature `png`)
+//! need to be decompressed as a whole, so they are decoded into a heap buffer up front.
+//!
+//! Decoded images are drawn with [`blit`], straight to the LCD, or with [`draw`] (feature
+//! `graphics`) to any [`embedded_graphics`] target, such as a
+//! [`FrameBuffer`](super::framebuffer::FrameBuffer).
+//!
+//! ```no_run
+//! # use alarmo::display::AlarmoDisplay;
+//! # use alarmo::display::image::{self, Qoi};
+//! # fn show(display: &mut AlarmoDisplay, asset: &[u8]) -> Result<(), image::ImageError> {
+//! let splash = Qoi::new(asset)?;
+//! image::blit(display, 0, 0, &splash)?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod bmp;
+#[cfg(feature = "png")]
+pub mod png;
+pub mod qoi;
+
+pub use bmp::Bmp;
+#[cfg(feature = "png")]
+pub use png::Png;
+pub use qoi::Qoi;
+
+use super::bus::DisplayBus;
+use super::AlarmoDisplay;
+
+/// Maximum width of BMP and QOI images, which are decoded into a row buffer on the stack
+pub const MAX_WIDTH: usize = 320;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ImageError {
+    /// The data does not start with the signature of the format
+    BadSignature,
+    /// The data ends before the end of the image
+    Truncated,
+    /// The image uses a feature that is not supported, e.g. a compression method or bit depth
+    Unsupported,
+    /// The image is empty, or too large to be decoded
+    BadDimensions,
+    /// The compressed data is invalid
+    Corrupted,
+}
+
+/// An image that can be decoded to RGB565, one row at a time.
+pub trait ImageDecoder {
+    /// Returns the dimensions of the image, as `(width, height)`.
+    fn size(&self) -> (u16, u16);
+
+    /// Decodes the image from top to bottom, calling `f` with the index and the pixels of each
+    /// row.
+    fn for_each_row(&self, f: &mut dyn FnMut(u16, &[u16])) -> Result<(), ImageError>;
+}
+
+/// Draws an image on the LCD, with its top left corner at `(x, y)` in the coordinates of the
+/// current memory access mode. The image must fit on the screen.
+///
+/// Rows are sent as soon as they are decoded, so part of the image may already be drawn if
+/// decoding fails.
+pub fn blit<B: DisplayBus>(
+    display: &mut AlarmoDisplay<B>,
+    x: u16,
+    y: u16,
+    image: &impl ImageDecoder,
+) -> Result<(), ImageError> {
+    let (width, _) = image.size();
+    image.for_each_row(&mut |row, pixels| {
+        display.set_address_window(x, y + row, x + width - 1, y + row);
+        display.write_pixels(pixels.iter().copied());
+    })
+}
+
+#[cfg(feature = "graphics")]
+pub use graphics::{draw, DrawError};
+
+#[cfg(feature = "graphics")]
+mod graphics {
+    use super::{ImageDecoder, ImageError};
+    use embedded_graphics::draw_target::DrawTarget;
+    use embedded_graphics::geometry::{Point, Size};
+    use embedded_graphics::pixelcolor::raw::RawU16;
+    use embedded_graphics::pixelcolor::Rgb565;
+    use embedded_graphics::primitives::Rectangle;
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum DrawError<E> {
+        Image(ImageError),
+        Target(E),
+    }
+
+    /// Draws an image to an [`embedded_graphics`] target, with its top left corner at
+    /// `top_left`. Pixels outside of the target are clipped by the target.
+    ///
+    /// See [`blit`](super::blit) for drawing straight to the LCD.
+    pub fn draw<D: DrawTarget<Color = Rgb565>>(
+        target: &mut D,
+        top_left: Point,
+        image: &impl ImageDecoder,
+    ) -> Result<(), DrawError<D::Error>> {
+        let (width, _) = image.size();
+        let mut target_error = None;
+        image
+            .for_each_row(&mut |row, pixels| {
+                if target_error.is_some() {
+                    return;
+                }
+                let area = Rectangle::new(
+                    top_left + Point::new(0, row as i32),
+                    Size::new(width as u32, 1),
+                );
+                let colors = pixels.iter().map(|p| Rgb565::from(RawU16::new(*p)));
+                if let Err(e) = target.fill_contiguous(&area, colors) {
+                    target_error = Some(e);
+                }
+            })
+            .map_err(DrawError::Image)?;
+        match target_error {
+            Some(e) => Err(DrawError::Target(e)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Converts 8-bit channels to RGB565.
+fn rgb888_to_rgb565(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+/// Helpers for the decoder tests. The fixtures in `testdata` are generated by `gen.py`, which
+/// also writes the expected RGB565 pixels of each image.
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::display::bus::mock::{BusOp, RecordingBus};
+
+    /// Loads a fixture and its expected pixels.
+    macro_rules! fixture {
+        ($name:literal) => {
+            (
+                &include_bytes!(concat!("testdata/", $name))[..],
+                crate::display::image::tests::expected(include_bytes!(concat!(
+                    "testdata/",
+                    $name,
+                    ".rgb565"
+                ))),
+            )
+        };
+    }
+    pub(crate) use fixture;
+
+    pub fn expected(bytes: &[u8]) -> Vec<u16> {
+        bytes
+            .chunks_exact(2)
+            .map(|p| u16::from_le_bytes([p[0], p[1]]))
+            .collect()
+    }
+
+    /// Decodes a whole image, checking that rows come in order.
+    pub fn decode(image: &impl ImageDecoder) -> Result<Vec<u16>, ImageError> {
+        let (width, height) = image.size();
+        let mut pixels = Vec::new();
+        let mut next_row = 0;
+        image.for_each_row(&mut |row, data| {
+            assert_eq!(row, next_row);
+            assert_eq!(data.len(), width as usize);
+            pixels.extend_from_slice(data);
+            next_row += 1;
+        })?;
+        assert_eq!(next_row, height);
+        Ok(pixels)
+    }
+
+    #[test]
+    fn blit_rows() {
+        let (data, pixels) = fixture!("image.qoi");
+        let image = Qoi::new(data).unwrap();
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        blit(&mut display, 10, 20, &image).unwrap();
+
+        let ops = display.bus_mut().take();
+        // Each row goes to its own window, 7 pixels wide
+        let windows: Vec<_> = ops
+            .split(|op| *op == BusOp::Command(0x2b))
+            .skip(1)
+            .map(|op| [op[0], op[1], op[2], op[3]])
+            .collect();
+        let rows: Vec<_> = (20u16..25)
+            .map(|y| {
+                let [hi, lo] = y.to_be_bytes();
+                [
+                    BusOp::Data8(hi),
+                    BusOp::Data8(lo),
+                    BusOp::Data8(hi),
+                    BusOp::Data8(lo),
+                ]
+            })
+            .collect();
+        assert_eq!(windows, rows);
+
+        let written: Vec<_> = ops
+            .iter()
+            .filter_map(|op| match op {
+                BusOp::Data16(pixel) => Some(*pixel),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(written, pixels);
+    }
+}
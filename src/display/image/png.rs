@@ -0,0 +1,385 @@
+//! PNG decoder.
+//!
+//! Supports non-interlaced images of every color type and bit depth. Transparency (alpha
+//! channels and `tRNS`) is ignored, and 16-bit samples are truncated to 8 bits. Chunk CRCs are
+//! not checked, but the zlib checksum of the image data is.
+
+use super::{rgb888_to_rgb565, ImageDecoder, ImageError};
+use alloc::vec;
+use alloc::vec::Vec;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const COLOR_GRAY: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_PALETTE: u8 = 3;
+const COLOR_GRAY_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+/// A PNG image, decoded to RGB565 into a heap buffer.
+pub struct Png {
+    pixels: Vec<u16>,
+    width: u16,
+    height: u16,
+}
+
+/// Image header (`IHDR`)
+struct Header {
+    width: u16,
+    height: u16,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl Png {
+    /// Decodes a PNG file.
+    pub fn decode(data: &[u8]) -> Result<Self, ImageError> {
+        if data.get(..SIGNATURE.len()) != Some(&SIGNATURE) {
+            return Err(ImageError::BadSignature);
+        }
+
+        let mut header = None;
+        let mut palette: &[u8] = &[];
+        let mut compressed = Vec::new();
+        let mut pos = SIGNATURE.len();
+        loop {
+            let len = data
+                .get(pos..pos + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+                .ok_or(ImageError::Truncated)?;
+            let kind = data.get(pos + 4..pos + 8).ok_or(ImageError::Truncated)?;
+            let body = data
+                .get(pos + 8..)
+                .and_then(|d| d.get(..len))
+                .ok_or(ImageError::Truncated)?;
+            // Skip the CRC
+            pos += 12 + len;
+
+            match kind {
+                b"IHDR" => header = Some(Header::parse(body)?),
+                b"PLTE" => palette = body,
+                b"IDAT" => compressed.extend_from_slice(body),
+                b"IEND" => break,
+                // Unknown critical chunk
+                _ if kind[0].is_ascii_uppercase() => return Err(ImageError::Unsupported),
+                _ => {}
+            }
+        }
+
+        let header = header.ok_or(ImageError::Truncated)?;
+        if header.color_type == COLOR_PALETTE && palette.is_empty() {
+            return Err(ImageError::Truncated);
+        }
+        let stride = header.stride();
+        let raw_len = (stride + 1)
+            .checked_mul(header.height as usize)
+            .ok_or(ImageError::BadDimensions)?;
+        let raw = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(&compressed, raw_len)
+            .map_err(|_| ImageError::Corrupted)?;
+        if raw.len() < raw_len {
+            return Err(ImageError::Truncated);
+        }
+        drop(compressed);
+
+        let mut pixels = Vec::with_capacity(header.width as usize * header.height as usize);
+        let mut previous = vec![0u8; stride];
+        let mut current = vec![0u8; stride];
+        for line in raw.chunks_exact(stride + 1) {
+            current.copy_from_slice(&line[1..]);
+            unfilter(line[0], header.filter_distance(), &previous, &mut current)?;
+            pixels.extend((0..header.width as usize).map(|x| header.pixel(&current, palette, x)));
+            core::mem::swap(&mut previous, &mut current);
+        }
+
+        Ok(Self {
+            pixels,
+            width: header.width,
+            height: header.height,
+        })
+    }
+
+    /// Returns the decoded RGB565 pixels, in row-major order.
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+}
+
+impl ImageDecoder for Png {
+    fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn for_each_row(&self, f: &mut dyn FnMut(u16, &[u16])) -> Result<(), ImageError> {
+        for (y, row) in self.pixels.chunks_exact(self.width as usize).enumerate() {
+            f(y as u16, row);
+        }
+        Ok(())
+    }
+}
+
+impl Header {
+    fn parse(body: &[u8]) -> Result<Self, ImageError> {
+        let [w0, w1, w2, w3, h0, h1, h2, h3, bit_depth, color_type, compression, filter, interlace] =
+            *body
+        else {
+            return Err(ImageError::Truncated);
+        };
+        let width = u32::from_be_bytes([w0, w1, w2, w3]);
+        let height = u32::from_be_bytes([h0, h1, h2, h3]);
+        if width == 0 || width > 0xffff || height == 0 || height > 0xffff {
+            return Err(ImageError::BadDimensions);
+        }
+
+        let valid_depth = match color_type {
+            COLOR_GRAY => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+            COLOR_PALETTE => matches!(bit_depth, 1 | 2 | 4 | 8),
+            COLOR_RGB | COLOR_GRAY_ALPHA | COLOR_RGBA => matches!(bit_depth, 8 | 16),
+            _ => false,
+        };
+        if !valid_depth || compression != 0 || filter != 0 || interlace != 0 {
+            return Err(ImageError::Unsupported);
+        }
+
+        Ok(Self {
+            width: width as u16,
+            height: height as u16,
+            bit_depth,
+            color_type,
+        })
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_RGB => 3,
+            COLOR_GRAY_ALPHA => 2,
+            COLOR_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// Bytes per row, without the filter type
+    fn stride(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// Distance between the bytes compared by filters, i.e. bytes per pixel (at least 1)
+    fn filter_distance(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    /// Reads channel `channel` of pixel `x`, scaled to 8 bits (except for palette indices).
+    fn sample(&self, row: &[u8], x: usize, channel: usize) -> u8 {
+        let index = x * self.channels() + channel;
+        match self.bit_depth {
+            8 => row[index],
+            // Big endian, keep the most significant byte
+            16 => row[index * 2],
+            bits => {
+                let bit = index * bits as usize;
+                let shift = 8 - bits as usize - bit % 8;
+                let value = (row[bit / 8] >> shift) & ((1 << bits) - 1);
+                if self.color_type == COLOR_PALETTE {
+                    value
+                } else {
+                    (value as u16 * 255 / ((1 << bits) - 1)) as u8
+                }
+            }
+        }
+    }
+
+    fn pixel(&self, row: &[u8], palette: &[u8], x: usize) -> u16 {
+        match self.color_type {
+            COLOR_RGB | COLOR_RGBA => rgb888_to_rgb565(
+                self.sample(row, x, 0),
+                self.sample(row, x, 1),
+                self.sample(row, x, 2),
+            ),
+            COLOR_PALETTE => {
+                let index = self.sample(row, x, 0) as usize * 3;
+                match palette.get(index..index + 3) {
+                    Some(&[r, g, b]) => rgb888_to_rgb565(r, g, b),
+                    _ => 0,
+                }
+            }
+            // Grayscale, with or without alpha
+            _ => {
+                let gray = self.sample(row, x, 0);
+                rgb888_to_rgb565(gray, gray, gray)
+            }
+        }
+    }
+}
+
+/// Reverses the filter of a row in place, see the PNG specification, section 9.
+fn unfilter(
+    filter: u8,
+    distance: usize,
+    previous: &[u8],
+    row: &mut [u8],
+) -> Result<(), ImageError> {
+    match filter {
+        // None
+        0 => {}
+        // Sub
+        1 => {
+            for i in distance..row.len() {
+                row[i] = row[i].wrapping_add(row[i - distance]);
+            }
+        }
+        // Up
+        2 => {
+            for (byte, up) in row.iter_mut().zip(previous) {
+                *byte = byte.wrapping_add(*up);
+            }
+        }
+        // Average
+        3 => {
+            for i in 0..row.len() {
+                let left = if i >= distance { row[i - distance] } else { 0 };
+                let average = (left as u16 + previous[i] as u16) / 2;
+                row[i] = row[i].wrapping_add(average as u8);
+            }
+        }
+        // Paeth
+        4 => {
+            for i in 0..row.len() {
+                let (left, up_left) = if i >= distance {
+                    (row[i - distance], previous[i - distance])
+                } else {
+                    (0, 0)
+                };
+                row[i] = row[i].wrapping_add(paeth(left, previous[i], up_left));
+            }
+        }
+        _ => return Err(ImageError::Corrupted),
+    }
+    Ok(())
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let distance_left = (estimate - left as i16).abs();
+    let distance_up = (estimate - up as i16).abs();
+    let distance_up_left = (estimate - up_left as i16).abs();
+    if distance_left <= distance_up && distance_left <= distance_up_left {
+        left
+    } else if distance_up <= distance_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::image::tests::{decode, fixture};
+
+    fn check((data, expected): (&[u8], Vec<u16>)) {
+        let png = Png::decode(data).unwrap();
+        assert_eq!(png.size(), (7, 5));
+        assert_eq!(decode(&png).unwrap(), expected);
+        assert_eq!(png.pixels(), expected);
+    }
+
+    // Each fixture uses every filter type, one per row
+
+    #[test]
+    fn grayscale() {
+        check(fixture!("png_c0_d1.png"));
+        check(fixture!("png_c0_d2.png"));
+        check(fixture!("png_c0_d4.png"));
+        check(fixture!("png_c0_d8.png"));
+        check(fixture!("png_c0_d16.png"));
+    }
+
+    #[test]
+    fn rgb() {
+        check(fixture!("png_c2_d8.png"));
+        check(fixture!("png_c2_d16.png"));
+    }
+
+    #[test]
+    fn palette() {
+        check(fixture!("png_c3_d1.png"));
+        check(fixture!("png_c3_d2.png"));
+        check(fixture!("png_c3_d4.png"));
+        check(fixture!("png_c3_d8.png"));
+    }
+
+    #[test]
+    fn alpha() {
+        check(fixture!("png_c4_d8.png"));
+        check(fixture!("png_c4_d16.png"));
+        check(fixture!("png_c6_d8.png"));
+        check(fixture!("png_c6_d16.png"));
+    }
+
+    /// Returns the offset of the first chunk of the given kind.
+    fn chunk(data: &[u8], kind: &[u8; 4]) -> usize {
+        data.windows(4).position(|w| w == kind).unwrap() - 4
+    }
+
+    #[test]
+    fn header_errors() {
+        let (data, _) = fixture!("png_c2_d8.png");
+        let ihdr = chunk(data, b"IHDR") + 8;
+        let patched = |offset: usize, value: u8| {
+            let mut data = data.to_vec();
+            data[offset] = value;
+            Png::decode(&data).err()
+        };
+
+        assert_eq!(patched(0, 0x88), Some(ImageError::BadSignature));
+        // Zero width
+        assert_eq!(patched(ihdr + 3, 0), Some(ImageError::BadDimensions));
+        // 8-bit palette images are fine, RGB ones are not
+        assert_eq!(patched(ihdr + 8, 4), Some(ImageError::Unsupported));
+        assert_eq!(patched(ihdr + 9, 5), Some(ImageError::Unsupported));
+        // Interlacing
+        assert_eq!(patched(ihdr + 12, 1), Some(ImageError::Unsupported));
+        // The ancillary tEXt chunk made critical
+        assert_eq!(
+            patched(chunk(data, b"tEXt") + 4, b'T'),
+            Some(ImageError::Unsupported)
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let (data, _) = fixture!("png_c2_d8.png");
+        // Missing IEND, in the middle of a chunk, and in the middle of a chunk header
+        for len in [data.len() - 12, data.len() - 20, chunk(data, b"IHDR") + 6] {
+            assert_eq!(Png::decode(&data[..len]).err(), Some(ImageError::Truncated));
+        }
+
+        // Palette image without its palette
+        let (data, _) = fixture!("png_c3_d2.png");
+        let plte = chunk(data, b"PLTE");
+        let plte_len = u32::from_be_bytes(data[plte..plte + 4].try_into().unwrap()) as usize;
+        let mut without = data[..plte].to_vec();
+        without.extend_from_slice(&data[plte + 12 + plte_len..]);
+        assert_eq!(Png::decode(&without).err(), Some(ImageError::Truncated));
+    }
+
+    #[test]
+    fn corrupted() {
+        let (data, _) = fixture!("png_c2_d8.png");
+        // Last byte of the zlib checksum, at the end of the second IDAT chunk
+        let iend = chunk(data, b"IEND");
+        let mut bad_checksum = data.to_vec();
+        bad_checksum[iend - 5] ^= 1;
+        assert_eq!(
+            Png::decode(&bad_checksum).err(),
+            Some(ImageError::Corrupted)
+        );
+
+        // Filter type 5 on the third row
+        let bad_filter = include_bytes!("testdata/png_bad_filter.png");
+        assert_eq!(Png::decode(bad_filter).err(), Some(ImageError::Corrupted));
+    }
+}
@@ -0,0 +1,171 @@
+//! "Quite OK Image" (QOI) decoder.
+//!
+//! QOI compresses about as well as PNG for typical UI graphics, but decodes in a single pass
+//! with 256 bytes of state, which makes it the best fit for assets on the Alarmo. Alpha is
+//! ignored.
+
+use super::{rgb888_to_rgb565, ImageDecoder, ImageError, MAX_WIDTH};
+
+const MAGIC: &[u8; 4] = b"qoif";
+const HEADER_LEN: usize = 14;
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const OP_MASK: u8 = 0xc0;
+
+/// A QOI image, decoded on the fly.
+pub struct Qoi<'a> {
+    data: &'a [u8],
+    width: u16,
+    height: u16,
+}
+
+/// Decoder state
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    index: [[u8; 4]; 64],
+    pixel: [u8; 4],
+    run: u8,
+}
+
+impl<'a> Qoi<'a> {
+    /// Parses the header of a QOI file.
+    pub fn new(data: &'a [u8]) -> Result<Self, ImageError> {
+        if data.get(0..4) != Some(MAGIC) {
+            return Err(ImageError::BadSignature);
+        }
+        if data.len() < HEADER_LEN {
+            return Err(ImageError::Truncated);
+        }
+        let width = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let height = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+        if width == 0 || width as usize > MAX_WIDTH || height == 0 || height > 0xffff {
+            return Err(ImageError::BadDimensions);
+        }
+        Ok(Self {
+            data,
+            width: width as u16,
+            height: height as u16,
+        })
+    }
+}
+
+impl ImageDecoder for Qoi<'_> {
+    fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn for_each_row(&self, f: &mut dyn FnMut(u16, &[u16])) -> Result<(), ImageError> {
+        let mut decoder = Decoder {
+            data: self.data,
+            pos: HEADER_LEN,
+            index: [[0; 4]; 64],
+            pixel: [0, 0, 0, 255],
+            run: 0,
+        };
+        let mut buf = [0u16; MAX_WIDTH];
+        let out = &mut buf[..self.width as usize];
+        for y in 0..self.height {
+            for pixel in out.iter_mut() {
+                let [r, g, b, _] = decoder.next()?;
+                *pixel = rgb888_to_rgb565(r, g, b);
+            }
+            f(y, out);
+        }
+        Ok(())
+    }
+}
+
+impl Decoder<'_> {
+    fn next(&mut self) -> Result<[u8; 4], ImageError> {
+        if self.run > 0 {
+            self.run -= 1;
+            return Ok(self.pixel);
+        }
+
+        let op = self.byte()?;
+        match op {
+            OP_RGB => {
+                self.pixel[0] = self.byte()?;
+                self.pixel[1] = self.byte()?;
+                self.pixel[2] = self.byte()?;
+            }
+            OP_RGBA => {
+                self.pixel = [self.byte()?, self.byte()?, self.byte()?, self.byte()?];
+            }
+            _ => match op & OP_MASK {
+                OP_INDEX => self.pixel = self.index[op as usize],
+                OP_DIFF => {
+                    self.pixel[0] = self.pixel[0].wrapping_add((op >> 4) & 0x3).wrapping_sub(2);
+                    self.pixel[1] = self.pixel[1].wrapping_add((op >> 2) & 0x3).wrapping_sub(2);
+                    self.pixel[2] = self.pixel[2].wrapping_add(op & 0x3).wrapping_sub(2);
+                }
+                OP_LUMA => {
+                    let dg = (op & 0x3f).wrapping_sub(32);
+                    let next = self.byte()?;
+                    let dr = dg.wrapping_add(next >> 4).wrapping_sub(8);
+                    let db = dg.wrapping_add(next & 0xf).wrapping_sub(8);
+                    self.pixel[0] = self.pixel[0].wrapping_add(dr);
+                    self.pixel[1] = self.pixel[1].wrapping_add(dg);
+                    self.pixel[2] = self.pixel[2].wrapping_add(db);
+                }
+                // Run of the previous pixel, which counts as the first one
+                _ => self.run = op & 0x3f,
+            },
+        }
+
+        let [r, g, b, a] = self.pixel;
+        let hash = (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64;
+        self.index[hash] = self.pixel;
+        Ok(self.pixel)
+    }
+
+    fn byte(&mut self) -> Result<u8, ImageError> {
+        let byte = *self.data.get(self.pos).ok_or(ImageError::Truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::image::tests::{decode, fixture};
+
+    #[test]
+    fn every_op() {
+        // Runs, index, diff, luma, RGB and RGBA chunks
+        let (data, expected) = fixture!("image.qoi");
+        let qoi = Qoi::new(data).unwrap();
+        assert_eq!(qoi.size(), (7, 5));
+        assert_eq!(decode(&qoi).unwrap(), expected);
+    }
+
+    #[test]
+    fn header_errors() {
+        let (data, _) = fixture!("image.qoi");
+        assert_eq!(Qoi::new(b"qoix").err(), Some(ImageError::BadSignature));
+        assert_eq!(Qoi::new(&data[..10]).err(), Some(ImageError::Truncated));
+
+        for (offset, value) in [(4, 0), (4, MAX_WIDTH as u32 + 1), (8, 0), (8, 0x10000)] {
+            let mut data = data.to_vec();
+            data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+            assert_eq!(Qoi::new(&data).err(), Some(ImageError::BadDimensions));
+        }
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let (data, _) = fixture!("image.qoi");
+        // Cut in the middle of the last row, in an RGBA chunk
+        let qoi = Qoi::new(&data[..data.len() - 8 - 3]).unwrap();
+        let mut rows = 0;
+        let result = qoi.for_each_row(&mut |_, _| rows += 1);
+        assert_eq!(result, Err(ImageError::Truncated));
+        assert_eq!(rows, 4);
+    }
+}
@@ -0,0 +1,238 @@
+#!/usr/bin/env python3
+"""Generates the image decoder test fixtures in this directory.
+
+Every image is 7x5 pixels, so rows don't end on a byte boundary for small bit depths. Each
+`<name>` image comes with `<name>.rgb565`, the expected decoder output as little-endian u16s.
+The encoders here are written from the format specifications, independently of the decoders.
+
+Run from this directory: python3 gen.py
+"""
+
+import struct
+import zlib
+
+W, H = 7, 5
+
+
+def rgb565(r, g, b):
+    return ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3)
+
+
+def color(x, y):
+    """RGBA test pattern, with gradients so that every filter has something to predict."""
+    return ((x * 37 + y * 11) & 0xFF, (x * 5 + y * 53 + 100) & 0xFF, (255 - x * 29 - y * 7) & 0xFF, (x * 40 + y) & 0xFF)
+
+
+def palette(n):
+    return [((i * 67) & 0xFF, (i * 131 + 7) & 0xFF, (255 - i * 17) & 0xFF) for i in range(n)]
+
+
+def write(name, data, expected):
+    with open(name, "wb") as f:
+        f.write(data)
+    with open(name + ".rgb565", "wb") as f:
+        f.write(b"".join(struct.pack("<H", p) for p in expected))
+
+
+# PNG
+
+
+def png_chunk(kind, body):
+    return struct.pack(">I", len(body)) + kind + body + struct.pack(">I", zlib.crc32(kind + body))
+
+
+def paeth(a, b, c):
+    p = a + b - c
+    pa, pb, pc = abs(p - a), abs(p - b), abs(p - c)
+    if pa <= pb and pa <= pc:
+        return a
+    return b if pb <= pc else c
+
+
+def png_filter(kind, row, prev, bpp):
+    out = bytearray()
+    for i, x in enumerate(row):
+        a = row[i - bpp] if i >= bpp else 0
+        b = prev[i]
+        c = prev[i - bpp] if i >= bpp else 0
+        pred = [0, a, b, (a + b) // 2, paeth(a, b, c)][kind]
+        out.append((x - pred) & 0xFF)
+    return bytes(out)
+
+
+def pack_bits(values, bits):
+    out, acc, n = bytearray(), 0, 0
+    for v in values:
+        acc = (acc << bits) | v
+        n += bits
+        if n == 8:
+            out.append(acc)
+            acc, n = 0, 0
+    if n:
+        out.append(acc << (8 - n))
+    return bytes(out)
+
+
+def png(color_type, depth, filters=range(5), bad_filter=False):
+    channels = {0: 1, 2: 3, 3: 1, 4: 2, 6: 4}[color_type]
+    bpp = max(1, channels * depth // 8)
+    pal = palette(1 << depth) if color_type == 3 else None
+    rows, expected = [], []
+    for y in range(H):
+        samples = []
+        for x in range(W):
+            r, g, b, a = color(x, y)
+            if color_type == 3:
+                index = (x + 2 * y) % len(pal)
+                samples.append(index)
+                expected.append(rgb565(*pal[index]))
+            elif color_type in (0, 4):
+                level = (x * 3 + y * 5) % (1 << depth) if depth < 8 else (x * 9001 + y * 4099) % (1 << depth)
+                gray = level * 255 // ((1 << depth) - 1) if depth < 8 else level >> (depth - 8)
+                samples += [level] if color_type == 0 else [level, (a << (depth - 8)) | a if depth == 16 else a]
+                expected.append(rgb565(gray, gray, gray))
+            else:
+                wide = [(c << 8) | (c ^ 0x5A) for c in (r, g, b, a)] if depth == 16 else [r, g, b, a]
+                samples += wide[:channels]
+                expected.append(rgb565(r, g, b))
+        if depth < 8:
+            rows.append(pack_bits(samples, depth))
+        else:
+            rows.append(b"".join(s.to_bytes(depth // 8, "big") for s in samples))
+
+    raw, prev = bytearray(), bytes(len(rows[0]))
+    for y, row in enumerate(rows):
+        kind = filters[y % len(filters)]
+        raw.append(5 if bad_filter and y == 2 else kind)
+        raw += png_filter(kind, row, prev, bpp)
+        prev = row
+    compressed = zlib.compress(bytes(raw), 9)
+
+    data = b"\x89PNG\r\n\x1a\n"
+    data += png_chunk(b"IHDR", struct.pack(">IIBBBBB", W, H, depth, color_type, 0, 0, 0))
+    # Ancillary chunk, must be skipped
+    data += png_chunk(b"tEXt", b"Comment\x00alarmo")
+    if pal:
+        data += png_chunk(b"PLTE", b"".join(bytes(c) for c in pal))
+    # Image data split over two chunks
+    half = len(compressed) // 2
+    data += png_chunk(b"IDAT", compressed[:half]) + png_chunk(b"IDAT", compressed[half:])
+    data += png_chunk(b"IEND", b"")
+    return data, expected
+
+
+for color_type, depths in [(0, [1, 2, 4, 8, 16]), (2, [8, 16]), (3, [1, 2, 4, 8]), (4, [8, 16]), (6, [8, 16])]:
+    for depth in depths:
+        write(f"png_c{color_type}_d{depth}.png", *png(color_type, depth))
+with open("png_bad_filter.png", "wb") as f:
+    f.write(png(2, 8, bad_filter=True)[0])
+
+
+# BMP
+
+
+def bmp(bits, compression=0, masks=None, top_down=False, colors_used=0):
+    stride = (W * bits + 31) // 32 * 4
+    pal = palette(colors_used or (1 << bits)) if bits <= 8 else []
+    rows, expected = [], []
+    for y in range(H):
+        values = []
+        for x in range(W):
+            r, g, b, a = color(x, y)
+            if bits <= 8:
+                index = (x * 3 + y) % len(pal)
+                values.append(index)
+                expected.append(rgb565(*pal[index]))
+            elif bits == 24:
+                values.append(bytes((b, g, r)))
+                expected.append(rgb565(r, g, b))
+            elif bits == 16 and masks is None:
+                # X1R5G5B5
+                r5, g5, b5 = r >> 3, g >> 3, b >> 3
+                values.append(struct.pack("<H", (r5 << 10) | (g5 << 5) | b5))
+                expected.append((r5 << 11) | ((g5 * 63 // 31) << 5) | b5)
+            elif bits == 16:
+                # R5G6B5 bitfields
+                values.append(struct.pack("<H", rgb565(r, g, b)))
+                expected.append(rgb565(r, g, b))
+            elif masks is None:
+                values.append(bytes((b, g, r, a)))
+                expected.append(rgb565(r, g, b))
+            else:
+                # 10 bits per channel, as B10G10R10 with 2 bits of padding
+                r10, g10, b10 = (r << 2) | (r >> 6), (g << 2) | (g >> 6), (b << 2) | (b >> 6)
+                values.append(struct.pack("<I", (r10 << 20) | (g10 << 10) | b10))
+                expected.append(((r10 >> 5) << 11) | ((g10 >> 4) << 5) | (b10 >> 5))
+        row = pack_bits(values, bits) if bits <= 8 else b"".join(values)
+        rows.append(row + bytes(stride - len(row)))
+
+    stored = rows if top_down else rows[::-1]
+    mask_bytes = struct.pack("<III", *masks) if masks else b""
+    pal_bytes = b"".join(bytes((b, g, r, 0)) for r, g, b in pal)
+    offset = 14 + 40 + len(mask_bytes) + len(pal_bytes)
+    pixels = b"".join(stored)
+    info = struct.pack(
+        "<IiiHHIIiiII", 40, W, -H if top_down else H, 1, bits, compression, len(pixels), 2835, 2835, colors_used, 0
+    )
+    header = b"BM" + struct.pack("<IHHI", offset + len(pixels), 0, 0, offset)
+    return header + info + mask_bytes + pal_bytes + pixels, expected
+
+
+for bits in [1, 2, 4, 8]:
+    write(f"bmp_{bits}.bmp", *bmp(bits))
+write("bmp_8_colors_used.bmp", *bmp(8, colors_used=20))
+write("bmp_16.bmp", *bmp(16))
+write("bmp_16_bitfields.bmp", *bmp(16, 3, (0xF800, 0x07E0, 0x001F)))
+write("bmp_24.bmp", *bmp(24))
+write("bmp_24_top_down.bmp", *bmp(24, top_down=True))
+write("bmp_32.bmp", *bmp(32))
+write("bmp_32_bitfields.bmp", *bmp(32, 3, (0x3FF00000, 0x000FFC00, 0x000003FF)))
+
+
+# QOI, with the reference encoder
+
+
+def qoi(pixels):
+    out = bytearray(b"qoif" + struct.pack(">IIBB", W, H, 4, 0))
+    index = [(0, 0, 0, 0)] * 64
+    prev, run = (0, 0, 0, 255), 0
+    for i, px in enumerate(pixels):
+        if px == prev:
+            run += 1
+            if run == 62 or i == len(pixels) - 1:
+                out.append(0xC0 | (run - 1))
+                run = 0
+            continue
+        if run:
+            out.append(0xC0 | (run - 1))
+            run = 0
+        h = (px[0] * 3 + px[1] * 5 + px[2] * 7 + px[3] * 11) % 64
+        if index[h] == px:
+            out.append(h)
+        else:
+            index[h] = px
+            if px[3] == prev[3]:
+                dr, dg, db = ((px[c] - prev[c] + 128) % 256 - 128 for c in range(3))
+                if -2 <= dr <= 1 and -2 <= dg <= 1 and -2 <= db <= 1:
+                    out.append(0x40 | ((dr + 2) << 4) | ((dg + 2) << 2) | (db + 2))
+                elif -32 <= dg <= 31 and -8 <= dr - dg <= 7 and -8 <= db - dg <= 7:
+                    out += bytes((0x80 | (dg + 32), ((dr - dg + 8) << 4) | (db - dg + 8)))
+                else:
+                    out += bytes((0xFE, px[0], px[1], px[2]))
+            else:
+                out += bytes((0xFF, *px))
+        prev = px
+    return bytes(out + b"\x00" * 7 + b"\x01")
+
+
+# Row 0: runs, row 1: small differences, row 2: luma differences, row 3: repeats from the
+# index, row 4: new colors with changing alpha
+qoi_pixels = (
+    [(10, 20, 30, 255)] * W
+    + [(10 + x, 20 - x % 2, 30 + x % 2, 255) for x in range(W)]
+    + [(40 + 20 * x, 50 + 20 * x, 45 + 20 * x, 255) for x in range(W)]
+    + [(10, 20, 30, 255), (40, 50, 45, 255)] * 3
+    + [(10, 20, 30, 255)]
+    + [(*color(x, 4)[:3], 200 + x) for x in range(W)]
+)
+write("image.qoi", qoi(qoi_pixels), [rgb565(*px[:3]) for px in qoi_pixels])
@@ -0,0 +1,459 @@
+//! Provides a display interface (see crate [`display_interface`]) to send data and commands
+//! to the LCD on the Alarmo.
+
+pub mod bus;
+pub mod console;
+pub mod dirty;
+pub mod dma;
+#[cfg(all(feature = "graphics", feature = "alloc"))]
+pub mod framebuffer;
+pub mod image;
+pub mod power;
+pub mod readback;
+#[cfg(feature = "graphics")]
+mod screen;
+pub mod scroll;
+
+#[cfg(feature = "graphics")]
+pub use screen::{AlarmoScreen, Orientation};
+
+pub use readback::ReadWriteDataCommand;
+
+use bus::{DisplayBus, FmcBus};
+use core::cell::RefCell;
+use cortex_m::prelude::_embedded_hal_blocking_delay_DelayMs;
+use display_interface::{DataFormat, DisplayError, WriteOnlyDataCommand};
+use stm32h7xx_hal::delay::Delay;
+use stm32h7xx_hal::device::TIM3;
+use stm32h7xx_hal::pwm::{ComplementaryImpossible, Pwm};
+use stm32h7xx_hal::{
+    gpio::{Output, Pin, PushPull},
+    prelude::_embedded_hal_PwmPin,
+};
+
+// ST7789 commands
+const SLPIN: u8 = 0x10;
+const SLPOUT: u8 = 0x11;
+const DISPOFF: u8 = 0x28;
+const DISPON: u8 = 0x29;
+const CASET: u8 = 0x2a;
+const RASET: u8 = 0x2b;
+const RAMWR: u8 = 0x2c;
+const TEOFF: u8 = 0x34;
+const TEON: u8 = 0x35;
+const GSCAN: u8 = 0x45;
+
+/// Upper bound for scanline polls while waiting for a new frame. A frame takes around 16ms,
+/// and a scanline poll takes a few hundred nanoseconds.
+const VBLANK_MAX_POLLS: u32 = 200_000;
+
+pub(crate) type SelectPin = Pin<'C', 7, Output<PushPull>>;
+pub(crate) type ResetPin = Pin<'G', 4, Output<PushPull>>;
+
+/// Tearing effect output mode, see [`AlarmoDisplay::set_tearing_effect`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TearingEffect {
+    /// The TE signal only reports vertical blanking
+    VBlank,
+    /// The TE signal reports both vertical and horizontal blanking
+    VAndHBlank,
+}
+
+/// LCD peripheral.
+///
+/// ## Getting started
+/// **Make sure to [`hard_reset`] the display!** Commands won't be accepted until you do.
+/// You also need to set a **backlight** with [`set_backlight`] to actually get an image on the screen.
+///
+/// This struct implements a display interface according to the [`display_interface`] crate.
+/// With the `graphics` feature, [`AlarmoScreen`] wraps it into a ready-to-use
+/// [`embedded_graphics`] draw target. Panel identification, status and GRAM contents can be
+/// read back, see the [`readback`] module.
+///
+/// See the [LCD example] for a working implementation.
+///
+/// The display is generic over the [`DisplayBus`] it sends commands through. On the Alarmo,
+/// this is always the [`FmcBus`]; [`from_bus`](AlarmoDisplay::from_bus) takes any other bus,
+/// e.g. a mock that records the command sequence in host tests.
+///
+/// [`set_backlight`]: AlarmoDisplay::set_backlight
+/// [`hard_reset`]: AlarmoDisplay::hard_reset
+/// [LCD example]: https://github.com/roccodev/alarmo-rs/blob/master/examples/lcd.rs
+pub struct AlarmoDisplay<B: DisplayBus = FmcBus> {
+    bus: B,
+    board: Option<Board>,
+    brightness: f32,
+}
+
+/// Backlight, reset line and delays of the Alarmo's LCD, which are not part of the bus
+struct Board {
+    backlight_timer: Pwm<TIM3, 3, ComplementaryImpossible>,
+    reset_pin: ResetPin,
+    delay: &'static RefCell<Delay>,
+}
+
+impl AlarmoDisplay {
+    pub(crate) fn new(
+        backlight_timer: Pwm<TIM3, 3, ComplementaryImpossible>,
+        select_pin: SelectPin,
+        reset_pin: Pin<'G', 4>,
+        delay: &'static RefCell<Delay>,
+    ) -> Self {
+        Self {
+            bus: FmcBus::new(select_pin),
+            board: Some(Board {
+                backlight_timer,
+                reset_pin: reset_pin.into_push_pull_output(),
+                delay,
+            }),
+            brightness: 0.0,
+        }
+    }
+}
+
+impl<B: DisplayBus> AlarmoDisplay<B> {
+    /// Creates a display that only has a bus, without the Alarmo's backlight and reset line.
+    ///
+    /// [`hard_reset`](AlarmoDisplay::hard_reset) only deselects the display,
+    /// [`set_backlight`](AlarmoDisplay::set_backlight) only remembers the brightness, and
+    /// delays (e.g. after waking the controller) are skipped.
+    pub fn from_bus(bus: B) -> Self {
+        Self {
+            bus,
+            board: None,
+            brightness: 0.0,
+        }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Hard resets the display
+    pub fn hard_reset(&mut self) {
+        self.pin_select(false);
+
+        if let Some(board) = &mut self.board {
+            board.reset_pin.set_low();
+            board.reset_pin.set_high();
+        }
+
+        self.delay_ms(120);
+    }
+
+    /// Sets the backlight brightness, from `0.0` (off) to `1.0` (maximum). Values outside of
+    /// that range are clamped.
+    ///
+    /// See the [`power`] module for gradual changes.
+    pub fn set_backlight(&mut self, brightness: f32) {
+        let brightness = brightness.clamp(0.0, 1.0);
+        if let Some(board) = &mut self.board {
+            let timer = &mut board.backlight_timer;
+            timer.set_duty((timer.get_max_duty() as f32 * brightness) as u16);
+            timer.enable();
+        }
+        self.brightness = brightness;
+    }
+
+    /// Returns the last brightness set with [`set_backlight`](AlarmoDisplay::set_backlight).
+    pub fn backlight(&self) -> f32 {
+        self.brightness
+    }
+
+    /// Enables or disables the tearing effect output of the LCD controller.
+    ///
+    /// The TE line does not seem to be routed to the MCU, so frame synchronization is done by
+    /// polling the current scanline instead (see [`wait_for_vblank`]). This is still useful
+    /// for panels that stop updating the scanline counter while TE is off.
+    ///
+    /// [`wait_for_vblank`]: AlarmoDisplay::wait_for_vblank
+    pub fn set_tearing_effect(&mut self, mode: Option<TearingEffect>) {
+        match mode {
+            Some(mode) => {
+                self.write_command(TEON);
+                self.write_params(&[(mode == TearingEffect::VAndHBlank) as u8]);
+            }
+            None => self.write_command(TEOFF),
+        }
+        self.pin_select(false);
+    }
+
+    /// Returns the scanline currently being refreshed by the LCD controller.
+    ///
+    /// Scanlines are counted along the panel's native (portrait) orientation, regardless of the
+    /// memory access mode, so the value ranges from 0 to 319 while visible lines are refreshed.
+    pub fn scanline(&mut self) -> u16 {
+        self.write_command(GSCAN);
+        let _dummy = self.read_param();
+        let high = self.read_param() as u16;
+        let low = self.read_param() as u16;
+        self.pin_select(false);
+        ((high << 8) | low) & 0x3ff
+    }
+
+    /// Blocks until the LCD controller starts refreshing a new frame.
+    ///
+    /// Writing a full frame through the FMC is faster than the panel refresh, so starting right
+    /// after the refresh wraps around to the first line avoids tearing, in the panel's native
+    /// orientation only: see [`present_frame`](Self::present_frame).
+    ///
+    /// Returns `false` if the controller did not report a new frame in time (e.g. because it is
+    /// asleep or was not initialized).
+    pub fn wait_for_vblank(&mut self) -> bool {
+        let mut last = self.scanline();
+        for _ in 0..VBLANK_MAX_POLLS {
+            let line = self.scanline();
+            if line < last {
+                return true;
+            }
+            last = line;
+        }
+        false
+    }
+
+    /// Sets the GRAM area that subsequent pixel writes go to, in the coordinates of the
+    /// current memory access mode (i.e. after rotation). Both corners are inclusive.
+    pub fn set_address_window(&mut self, x0: u16, y0: u16, x1: u16, y1: u16) {
+        let [x0h, x0l] = x0.to_be_bytes();
+        let [x1h, x1l] = x1.to_be_bytes();
+        let [y0h, y0l] = y0.to_be_bytes();
+        let [y1h, y1l] = y1.to_be_bytes();
+        self.write_command(CASET);
+        self.write_params(&[x0h, x0l, x1h, x1l]);
+        self.write_command(RASET);
+        self.write_params(&[y0h, y0l, y1h, y1l]);
+        self.pin_select(false);
+    }
+
+    /// Streams RGB565 pixels into the current address window.
+    pub fn write_pixels(&mut self, pixels: impl IntoIterator<Item = u16>) {
+        self.write_command(RAMWR);
+        for pixel in pixels {
+            self.bus.write_data16(pixel);
+        }
+        self.pin_select(false);
+    }
+
+    /// Presents a full frame of RGB565 pixels, synchronized to the panel refresh.
+    ///
+    /// `width` and `height` are the dimensions of the frame in the current memory access mode,
+    /// e.g. 320x240 for the landscape orientation used in the examples. An empty frame is not
+    /// sent at all.
+    ///
+    /// Waiting for [`wait_for_vblank`](Self::wait_for_vblank) only prevents tearing when the
+    /// frame is written along the panel's native portrait lines. In landscape orientations, which
+    /// exchange rows and columns (MV), the panel keeps refreshing portrait lines while the frame
+    /// is written across them, so the refresh catches up with the write partway through the
+    /// frame and fast motion can still show a tear.
+    ///
+    /// Returns `false` if the wait for the refresh timed out, as reported by
+    /// [`wait_for_vblank`](Self::wait_for_vblank). The frame is written anyway, but may tear.
+    ///
+    /// ## Panics
+    /// Panics if `frame` does not contain exactly `width * height` pixels.
+    pub fn present_frame(&mut self, width: u16, height: u16, frame: &[u16]) -> bool {
+        assert_eq!(
+            frame.len(),
+            width as usize * height as usize,
+            "frame size mismatch"
+        );
+        if frame.is_empty() {
+            return true;
+        }
+        self.set_address_window(0, 0, width - 1, height - 1);
+        let synced = self.wait_for_vblank();
+        self.write_pixels(frame.iter().copied());
+        synced
+    }
+
+    fn write_command(&mut self, cmd: u8) {
+        self.pin_select(true);
+        self.bus.write_command(cmd);
+    }
+
+    fn write_params(&mut self, params: &[u8]) {
+        for param in params {
+            self.bus.write_data8(*param);
+        }
+    }
+
+    fn read_param(&mut self) -> u8 {
+        // Parameters are returned on the lower 8 lines of the bus
+        self.bus.read_data16() as u8
+    }
+
+    fn pin_select(&mut self, select: bool) {
+        self.bus.select(select);
+    }
+
+    fn delay_ms(&self, ms: u32) {
+        if let Some(board) = &self.board {
+            board.delay.borrow_mut().delay_ms(ms);
+        }
+    }
+}
+
+impl<B: DisplayBus> WriteOnlyDataCommand for AlarmoDisplay<B> {
+    fn send_commands(&mut self, cmd: DataFormat<'_>) -> Result<(), DisplayError> {
+        self.pin_select(true);
+        bus::write_commands(&mut self.bus, cmd)
+    }
+
+    fn send_data(&mut self, buf: DataFormat<'_>) -> Result<(), DisplayError> {
+        let result = bus::write_data(&mut self.bus, buf);
+        self.pin_select(false);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bus::mock::{BusOp::*, RecordingBus};
+    use super::*;
+
+    #[test]
+    fn address_window() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        display.set_address_window(0x12, 0, 0x13f, 0xef);
+        assert_eq!(
+            display.bus_mut().take(),
+            [
+                Select(true),
+                Command(CASET),
+                Data8(0x00),
+                Data8(0x12),
+                Data8(0x01),
+                Data8(0x3f),
+                Select(true),
+                Command(RASET),
+                Data8(0x00),
+                Data8(0x00),
+                Data8(0x00),
+                Data8(0xef),
+                Select(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_pixels() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        display.write_pixels([0xf800, 0x001f]);
+        assert_eq!(
+            display.bus_mut().take(),
+            [
+                Select(true),
+                Command(RAMWR),
+                Data16(0xf800),
+                Data16(0x001f),
+                Select(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn scanline() {
+        // Dummy read, then the high and low bytes on the lower 8 lines
+        let bus = RecordingBus::with_reads(&[0xffff, 0xff01, 0xff3f]);
+        let mut display = AlarmoDisplay::from_bus(bus);
+        assert_eq!(display.scanline(), 0x13f);
+        assert_eq!(
+            display.bus_mut().take(),
+            [
+                Select(true),
+                Command(GSCAN),
+                Read,
+                Read,
+                Read,
+                Select(false)
+            ]
+        );
+    }
+
+    #[test]
+    fn wait_for_vblank() {
+        let lines: Vec<u16> = [200u16, 250, 310, 3]
+            .iter()
+            .flat_map(|line| [0, line >> 8, line & 0xff])
+            .collect();
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::with_reads(&lines));
+        assert!(display.wait_for_vblank());
+        assert!(display.bus().reads.is_empty());
+    }
+
+    #[test]
+    fn present_frame() {
+        let lines: Vec<u16> = [300u16, 2].iter().flat_map(|line| [0, 0, *line]).collect();
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::with_reads(&lines));
+        assert!(display.present_frame(2, 1, &[0xf800, 0x001f]));
+        let ops = display.bus_mut().take();
+        assert_eq!(ops[1..3], [Command(CASET), Data8(0)]);
+        assert_eq!(
+            ops[ops.len() - 5..],
+            [
+                Select(true),
+                Command(RAMWR),
+                Data16(0xf800),
+                Data16(0x001f),
+                Select(false),
+            ]
+        );
+
+        // Nothing is sent for an empty frame
+        for (width, height) in [(0, 0), (0, 240), (320, 0)] {
+            assert!(display.present_frame(width, height, &[]));
+            assert_eq!(display.bus_mut().take(), []);
+        }
+
+        // The scanline never wraps around, the frame is still written
+        assert!(!display.present_frame(1, 1, &[0xffff]));
+        let ops = display.bus_mut().take();
+        assert_eq!(
+            ops[ops.len() - 4..],
+            [Select(true), Command(RAMWR), Data16(0xffff), Select(false)]
+        );
+    }
+
+    #[test]
+    fn tearing_effect() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        display.set_tearing_effect(Some(TearingEffect::VAndHBlank));
+        display.set_tearing_effect(None);
+        assert_eq!(
+            display.bus_mut().take(),
+            [
+                Select(true),
+                Command(TEON),
+                Data8(1),
+                Select(false),
+                Select(true),
+                Command(TEOFF),
+                Select(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn display_interface() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        display.send_commands(DataFormat::U8(&[RAMWR])).unwrap();
+        display.send_data(DataFormat::U16(&[0x1234])).unwrap();
+        assert_eq!(
+            display.bus_mut().take(),
+            [Select(true), Command(RAMWR), Data16(0x1234), Select(false)]
+        );
+    }
+
+    #[test]
+    fn without_board() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        display.hard_reset();
+        display.set_backlight(1.5);
+        assert_eq!(display.backlight(), 1.0);
+        assert_eq!(display.bus_mut().take(), [Select(false)]);
+    }
+}
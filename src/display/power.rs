@@ -0,0 +1,311 @@
+//! Power management for the LCD: sleep modes, backlight fading and automatic dimming.
+//!
+//! Timed operations are driven by the [`Clock`](crate::clock::Clock): they take the current
+//! [`Instant`] and must be updated periodically, e.g. from the main loop.
+
+use super::bus::DisplayBus;
+use super::{AlarmoDisplay, DISPOFF, DISPON, SLPIN, SLPOUT};
+use crate::clock::Instant;
+
+/// Interval between backlight updates for blocking fades
+const FADE_STEP_MS: u32 = 10;
+
+/// A gradual change of the backlight brightness.
+#[derive(Copy, Clone, Debug)]
+pub struct BacklightFade {
+    from: f32,
+    to: f32,
+    start: Instant,
+    duration_ms: u32,
+}
+
+/// Configuration for [`AutoDim`].
+#[derive(Copy, Clone, Debug)]
+pub struct AutoDimConfig {
+    /// Backlight brightness while the device is in use
+    pub active_brightness: f32,
+    /// Backlight brightness after `dim_after_ms` without activity
+    pub dim_brightness: f32,
+    pub dim_after_ms: u32,
+    /// If set, the backlight is turned off and the LCD is put to sleep after this many
+    /// milliseconds without activity
+    pub sleep_after_ms: Option<u32>,
+    /// Duration of the transitions between states
+    pub fade_ms: u32,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PowerState {
+    Active,
+    Dim,
+    Asleep,
+}
+
+/// Inactivity policy: dims the backlight (and optionally puts the LCD to sleep) when there was
+/// no user activity for a while.
+///
+/// Activity must be reported with [`activity`]. When using button interrupts, the press handler
+/// can set a flag that the main loop then forwards to [`activity`], since the handler does not
+/// have access to the display.
+///
+/// [`activity`]: AutoDim::activity
+pub struct AutoDim {
+    config: AutoDimConfig,
+    state: PowerState,
+    last_activity: Instant,
+    fade: Option<BacklightFade>,
+    panel_asleep: bool,
+}
+
+impl<B: DisplayBus> AlarmoDisplay<B> {
+    /// Puts the LCD controller into sleep mode, stopping the panel refresh. GRAM contents are
+    /// retained. The backlight is not affected.
+    pub fn sleep(&mut self) {
+        self.write_command(SLPIN);
+        self.pin_select(false);
+        self.delay_ms(5);
+    }
+
+    /// Wakes the LCD controller from sleep mode.
+    ///
+    /// This blocks for 120ms, the minimum time before the controller can go back to sleep.
+    pub fn wake(&mut self) {
+        self.write_command(SLPOUT);
+        self.pin_select(false);
+        self.delay_ms(120);
+    }
+
+    /// Blanks the panel without entering sleep mode. GRAM can still be written to.
+    pub fn display_off(&mut self) {
+        self.write_command(DISPOFF);
+        self.pin_select(false);
+    }
+
+    pub fn display_on(&mut self) {
+        self.write_command(DISPON);
+        self.pin_select(false);
+    }
+
+    /// Gradually changes the backlight brightness, blocking for `duration_ms` milliseconds.
+    ///
+    /// See [`BacklightFade`] for a non-blocking version.
+    pub fn fade_backlight(&mut self, brightness: f32, duration_ms: u32) {
+        let from = self.brightness;
+        let steps = (duration_ms / FADE_STEP_MS).max(1);
+        for step in 1..=steps {
+            self.set_backlight(lerp(from, brightness, step as f32 / steps as f32));
+            if step != steps {
+                self.delay_ms(FADE_STEP_MS);
+            }
+        }
+    }
+}
+
+impl BacklightFade {
+    /// Starts a fade from the current brightness of the display.
+    pub fn new<B: DisplayBus>(
+        display: &AlarmoDisplay<B>,
+        brightness: f32,
+        now: Instant,
+        duration_ms: u32,
+    ) -> Self {
+        Self {
+            from: display.backlight(),
+            to: brightness.clamp(0.0, 1.0),
+            start: now,
+            duration_ms,
+        }
+    }
+
+    pub fn target(&self) -> f32 {
+        self.to
+    }
+
+    /// Updates the backlight for the current time. Returns `true` once the fade is complete.
+    pub fn update<B: DisplayBus>(&self, display: &mut AlarmoDisplay<B>, now: Instant) -> bool {
+        let elapsed = now.millis_since(self.start);
+        if elapsed >= self.duration_ms {
+            display.set_backlight(self.to);
+            return true;
+        }
+        display.set_backlight(lerp(
+            self.from,
+            self.to,
+            elapsed as f32 / self.duration_ms as f32,
+        ));
+        false
+    }
+}
+
+impl AutoDim {
+    /// Creates the policy, fading the backlight to the active brightness.
+    pub fn new<B: DisplayBus>(
+        config: AutoDimConfig,
+        display: &AlarmoDisplay<B>,
+        now: Instant,
+    ) -> Self {
+        Self {
+            config,
+            state: PowerState::Active,
+            last_activity: now,
+            fade: Some(BacklightFade::new(
+                display,
+                config.active_brightness,
+                now,
+                config.fade_ms,
+            )),
+            panel_asleep: false,
+        }
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    pub fn config(&self) -> &AutoDimConfig {
+        &self.config
+    }
+
+    /// Reports user activity, e.g. a button press, bringing the display back to full brightness.
+    ///
+    /// Returns `true` if the display was dimmed or asleep. Applications typically ignore the
+    /// input that woke the display up.
+    pub fn activity<B: DisplayBus>(
+        &mut self,
+        display: &mut AlarmoDisplay<B>,
+        now: Instant,
+    ) -> bool {
+        self.last_activity = now;
+        if self.state == PowerState::Active {
+            return false;
+        }
+
+        if self.panel_asleep {
+            display.wake();
+            display.display_on();
+            self.panel_asleep = false;
+        }
+        self.state = PowerState::Active;
+        self.fade = Some(BacklightFade::new(
+            display,
+            self.config.active_brightness,
+            now,
+            self.config.fade_ms,
+        ));
+        true
+    }
+
+    /// Advances fades and applies the inactivity timeouts. Call this periodically.
+    pub fn update<B: DisplayBus>(&mut self, display: &mut AlarmoDisplay<B>, now: Instant) {
+        if let Some(fade) = self.fade {
+            if fade.update(display, now) {
+                self.fade = None;
+                if self.state == PowerState::Asleep && !self.panel_asleep {
+                    display.display_off();
+                    display.sleep();
+                    self.panel_asleep = true;
+                }
+            }
+        }
+
+        let idle_ms = now.millis_since(self.last_activity);
+        let next = match self.state {
+            PowerState::Active if idle_ms >= self.config.dim_after_ms => {
+                (PowerState::Dim, self.config.dim_brightness)
+            }
+            PowerState::Dim
+                if self
+                    .config
+                    .sleep_after_ms
+                    .is_some_and(|sleep_after| idle_ms >= sleep_after) =>
+            {
+                (PowerState::Asleep, 0.0)
+            }
+            _ => return,
+        };
+        self.state = next.0;
+        self.fade = Some(BacklightFade::new(
+            display,
+            next.1,
+            now,
+            self.config.fade_ms,
+        ));
+    }
+}
+
+impl Default for AutoDimConfig {
+    fn default() -> Self {
+        Self {
+            active_brightness: 1.0,
+            dim_brightness: 0.1,
+            dim_after_ms: 30_000,
+            sleep_after_ms: None,
+            fade_ms: 500,
+        }
+    }
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::bus::mock::{BusOp, RecordingBus};
+
+    fn at(ms: u32) -> Instant {
+        Instant::from_ticks(0).add_millis(ms)
+    }
+
+    #[test]
+    fn fade() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        display.set_backlight(0.2);
+        let fade = BacklightFade::new(&display, 1.2, at(100), 400);
+        assert_eq!(fade.target(), 1.0);
+        assert!(!fade.update(&mut display, at(300)));
+        assert!((display.backlight() - 0.6).abs() < 1e-6);
+        assert!(fade.update(&mut display, at(600)));
+        assert_eq!(display.backlight(), 1.0);
+    }
+
+    #[test]
+    fn auto_dim() {
+        let config = AutoDimConfig {
+            active_brightness: 1.0,
+            dim_brightness: 0.25,
+            dim_after_ms: 1000,
+            sleep_after_ms: Some(5000),
+            fade_ms: 100,
+        };
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        let mut dim = AutoDim::new(config, &display, at(0));
+        dim.update(&mut display, at(100));
+        assert_eq!(display.backlight(), 1.0);
+
+        dim.update(&mut display, at(1000));
+        assert_eq!(dim.state(), PowerState::Dim);
+        dim.update(&mut display, at(1100));
+        assert_eq!(display.backlight(), 0.25);
+
+        dim.update(&mut display, at(5000));
+        assert_eq!(dim.state(), PowerState::Asleep);
+        display.bus_mut().take();
+        dim.update(&mut display, at(5100));
+        assert_eq!(display.backlight(), 0.0);
+        let ops = display.bus_mut().take();
+        assert!(ops.contains(&BusOp::Command(DISPOFF)));
+        assert!(ops.contains(&BusOp::Command(SLPIN)));
+
+        // Waking up doesn't count as a new press once active
+        assert!(dim.activity(&mut display, at(6000)));
+        assert!(!dim.activity(&mut display, at(6001)));
+        assert_eq!(dim.state(), PowerState::Active);
+        let ops = display.bus_mut().take();
+        assert!(ops.contains(&BusOp::Command(SLPOUT)));
+        assert!(ops.contains(&BusOp::Command(DISPON)));
+        dim.update(&mut display, at(6100));
+        assert_eq!(display.backlight(), 1.0);
+    }
+}
@@ -0,0 +1,241 @@
+//! Reading back from the LCD controller: identification, status and GRAM contents.
+//!
+//! Reads go through the same FMC bank as writes, using the slower read timings configured at
+//! startup.
+
+use super::bus::DisplayBus;
+use super::AlarmoDisplay;
+use display_interface::{DisplayError, WriteOnlyDataCommand};
+
+// ST7789 read commands
+const RDDID: u8 = 0x04;
+const RDDST: u8 = 0x09;
+const RAMRD: u8 = 0x2e;
+
+/// Number of pixels checked by [`AlarmoDisplay::self_test`]
+const SELF_TEST_PIXELS: usize = 4;
+const SELF_TEST_PATTERN: [u16; SELF_TEST_PIXELS] = [0xf800, 0x07e0, 0x001f, 0xa5a5];
+
+/// Counterpart of [`WriteOnlyDataCommand`] for interfaces that can also read from the display.
+pub trait ReadWriteDataCommand: WriteOnlyDataCommand {
+    /// Sends a read command and reads its parameters into `buf`. The dummy read that precedes
+    /// the parameters is skipped.
+    fn read_command(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), DisplayError>;
+
+    /// Reads raw 16-bit words following a command sent with
+    /// [`send_commands`](WriteOnlyDataCommand::send_commands), e.g. a GRAM read. The display is
+    /// deselected afterwards, like with [`send_data`](WriteOnlyDataCommand::send_data).
+    fn read_data(&mut self, buf: &mut [u16]) -> Result<(), DisplayError>;
+}
+
+/// Identification of the LCD controller, as returned by `RDDID`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PanelId {
+    pub manufacturer: u8,
+    pub version: u8,
+    pub driver: u8,
+}
+
+/// Display status, as returned by `RDDST`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DisplayStatus(pub u32);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SelfTestError {
+    /// The controller returned an invalid ID (all bits low or high), it is most likely not
+    /// responding
+    NoResponse,
+    /// Pixels read back from GRAM do not match what was written
+    GramMismatch,
+}
+
+impl<B: DisplayBus> ReadWriteDataCommand for AlarmoDisplay<B> {
+    fn read_command(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), DisplayError> {
+        self.write_command(cmd);
+        let _dummy = self.read_param();
+        for byte in buf.iter_mut() {
+            *byte = self.read_param();
+        }
+        self.pin_select(false);
+        Ok(())
+    }
+
+    fn read_data(&mut self, buf: &mut [u16]) -> Result<(), DisplayError> {
+        for word in buf.iter_mut() {
+            *word = self.bus.read_data16();
+        }
+        self.pin_select(false);
+        Ok(())
+    }
+}
+
+impl<B: DisplayBus> AlarmoDisplay<B> {
+    pub fn read_id(&mut self) -> PanelId {
+        let mut id = [0u8; 3];
+        self.read_command(RDDID, &mut id).ok();
+        PanelId {
+            manufacturer: id[0],
+            version: id[1],
+            driver: id[2],
+        }
+    }
+
+    pub fn read_status(&mut self) -> DisplayStatus {
+        let mut status = [0u8; 4];
+        self.read_command(RDDST, &mut status).ok();
+        DisplayStatus(u32::from_be_bytes(status))
+    }
+
+    /// Reads a region of GRAM as RGB565 pixels. Both corners are inclusive, in the coordinates
+    /// of the current memory access mode.
+    ///
+    /// The controller always returns pixels in 18-bit format (6 bits per channel, packed as
+    /// `R1G1`, `B1R2`, `G2B2` on the 16-bit bus); they are converted back to RGB565.
+    ///
+    /// ## Panics
+    /// Panics if `x0 > x1` or `y0 > y1`, or if `buf` does not contain exactly as many pixels as
+    /// the region.
+    pub fn read_pixels(&mut self, x0: u16, y0: u16, x1: u16, y1: u16, buf: &mut [u16]) {
+        assert!(x0 <= x1 && y0 <= y1, "inverted region");
+        let width = (x1 - x0) as usize + 1;
+        let height = (y1 - y0) as usize + 1;
+        assert_eq!(buf.len(), width * height, "region size mismatch");
+
+        self.set_address_window(x0, y0, x1, y1);
+        self.write_command(RAMRD);
+        let _dummy = self.bus.read_data16();
+
+        // Two pixels span three bus words, keep the second half of a word for the next channel
+        let mut pending = None;
+        for pixel in buf.iter_mut() {
+            let mut channels = [0u8; 3];
+            for channel in channels.iter_mut() {
+                *channel = match pending.take() {
+                    Some(byte) => byte,
+                    None => {
+                        let [high, low] = self.bus.read_data16().to_be_bytes();
+                        pending = Some(low);
+                        high
+                    }
+                };
+            }
+            *pixel = rgb666_to_rgb565(channels);
+        }
+        self.pin_select(false);
+    }
+
+    /// Checks that the LCD controller responds, and that GRAM can be written to and read back.
+    ///
+    /// A few pixels in the top left corner are overwritten with a test pattern, then restored.
+    /// The controller must be out of sleep mode and configured for 16-bit pixels (see
+    /// [`AlarmoScreen`](super::AlarmoScreen)).
+    pub fn self_test(&mut self) -> Result<PanelId, SelfTestError> {
+        let id = self.read_id();
+        let id_bytes = [id.manufacturer, id.version, id.driver];
+        if id_bytes == [0x00; 3] || id_bytes == [0xff; 3] {
+            return Err(SelfTestError::NoResponse);
+        }
+
+        let last = SELF_TEST_PIXELS as u16 - 1;
+        let mut original = [0u16; SELF_TEST_PIXELS];
+        self.read_pixels(0, 0, last, 0, &mut original);
+
+        let mut pattern = [0u16; SELF_TEST_PIXELS];
+        self.set_address_window(0, 0, last, 0);
+        self.write_pixels(SELF_TEST_PATTERN);
+        self.read_pixels(0, 0, last, 0, &mut pattern);
+
+        self.set_address_window(0, 0, last, 0);
+        self.write_pixels(original.iter().copied());
+
+        if pattern != SELF_TEST_PATTERN {
+            return Err(SelfTestError::GramMismatch);
+        }
+        Ok(id)
+    }
+}
+
+impl DisplayStatus {
+    /// Whether the controller is out of sleep mode
+    pub fn is_awake(&self) -> bool {
+        self.0 & (1 << 17) != 0
+    }
+
+    /// Whether the controller is in normal (not partial) display mode
+    pub fn is_normal_mode(&self) -> bool {
+        self.0 & (1 << 16) != 0
+    }
+
+    pub fn is_inverted(&self) -> bool {
+        self.0 & (1 << 13) != 0
+    }
+
+    pub fn is_display_on(&self) -> bool {
+        self.0 & (1 << 10) != 0
+    }
+
+    pub fn is_tearing_effect_on(&self) -> bool {
+        self.0 & (1 << 9) != 0
+    }
+}
+
+/// Converts left-aligned 6-bit channels to RGB565.
+fn rgb666_to_rgb565([r, g, b]: [u8; 3]) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::bus::mock::{BusOp, RecordingBus};
+
+    #[test]
+    fn read_id() {
+        let bus = RecordingBus::with_reads(&[0xffff, 0x0085, 0x0085, 0x0052]);
+        let mut display = AlarmoDisplay::from_bus(bus);
+        assert_eq!(
+            display.read_id(),
+            PanelId {
+                manufacturer: 0x85,
+                version: 0x85,
+                driver: 0x52,
+            }
+        );
+        assert_eq!(display.bus().ops[1], BusOp::Command(RDDID));
+    }
+
+    #[test]
+    fn read_pixels() {
+        // Dummy word, then R1G1 B1R2 G2B2 with 6-bit channels in the upper bits of each byte
+        let bus = RecordingBus::with_reads(&[0x0000, 0xfc00, 0xfc80, 0x84fc]);
+        let mut display = AlarmoDisplay::from_bus(bus);
+        let mut pixels = [0u16; 2];
+        display.read_pixels(0, 0, 1, 0, &mut pixels);
+        assert_eq!(pixels, [0xf81f, 0x843f]);
+        assert!(display.bus().reads.is_empty());
+        assert_eq!(display.bus().ops.last(), Some(&BusOp::Select(false)));
+    }
+
+    #[test]
+    #[should_panic(expected = "inverted region")]
+    fn read_pixels_inverted() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        display.read_pixels(0, 1, 0, 0, &mut []);
+    }
+
+    #[test]
+    fn self_test_no_response() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        assert_eq!(display.self_test(), Err(SelfTestError::NoResponse));
+    }
+
+    #[test]
+    fn status_bits() {
+        let status = DisplayStatus(0x0003_0600);
+        assert!(status.is_awake());
+        assert!(status.is_normal_mode());
+        assert!(!status.is_inverted());
+        assert!(status.is_display_on());
+        assert!(status.is_tearing_effect_on());
+    }
+}
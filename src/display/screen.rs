@@ -0,0 +1,319 @@
+use super::bus::{DisplayBus, FmcBus};
+use super::{AlarmoDisplay, DISPON, SLPOUT};
+use core::convert::Infallible;
+use core::iter;
+use embedded_graphics::geometry::{Dimensions, OriginDimensions, Size};
+use embedded_graphics::pixelcolor::{IntoStorage, Rgb565};
+use embedded_graphics::primitives::{PointsIter, Rectangle};
+use embedded_graphics::{draw_target::DrawTarget, Pixel};
+
+// ST7789 configuration commands
+const NORON: u8 = 0x13;
+const INVON: u8 = 0x21;
+const MADCTL: u8 = 0x36;
+const COLMOD: u8 = 0x3a;
+
+// MADCTL bits
+const MADCTL_MY: u8 = 1 << 7;
+const MADCTL_MX: u8 = 1 << 6;
+const MADCTL_MV: u8 = 1 << 5;
+
+/// 16 bits per pixel (RGB565) on both the RGB and MCU interfaces
+const COLMOD_RGB565: u8 = 0x55;
+
+/// The LCD panel is 240x320 in its native orientation.
+const NATIVE_WIDTH: u32 = 240;
+const NATIVE_HEIGHT: u32 = 320;
+
+/// Orientation of the screen contents.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Orientation {
+    /// 320x240, the orientation used by the stock firmware
+    #[default]
+    Landscape,
+    /// 320x240, upside down
+    LandscapeFlipped,
+    /// 240x320, the panel's native orientation
+    Portrait,
+    /// 240x320, upside down
+    PortraitFlipped,
+}
+
+/// [`embedded_graphics`] draw target for the Alarmo's LCD.
+///
+/// Unlike going through a generic driver such as `mipidsi`, this is already configured for the
+/// Alarmo's panel (color inversion, RGB565, landscape orientation), and fills rectangles by
+/// streaming pixels into a GRAM window instead of addressing each pixel.
+///
+/// The backlight is left untouched, see [`AlarmoDisplay::set_backlight`].
+pub struct AlarmoScreen<B: DisplayBus = FmcBus> {
+    display: AlarmoDisplay<B>,
+    orientation: Orientation,
+}
+
+impl<B: DisplayBus> AlarmoScreen<B> {
+    /// Resets and initializes the LCD in the default (landscape) orientation.
+    pub fn new(display: AlarmoDisplay<B>) -> Self {
+        Self::with_orientation(display, Orientation::default())
+    }
+
+    /// Resets and initializes the LCD.
+    pub fn with_orientation(mut display: AlarmoDisplay<B>, orientation: Orientation) -> Self {
+        display.hard_reset();
+
+        display.write_command(SLPOUT);
+        display.delay_ms(10);
+        display.write_command(MADCTL);
+        display.write_params(&[orientation.madctl()]);
+        // The Alarmo's panel has inverted colors
+        display.write_command(INVON);
+        display.write_command(COLMOD);
+        display.write_params(&[COLMOD_RGB565]);
+        display.write_command(NORON);
+        display.write_command(DISPON);
+        display.pin_select(false);
+        display.delay_ms(120);
+
+        Self {
+            display,
+            orientation,
+        }
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Changes the orientation of the screen. Content already on the screen is not moved.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.display.write_command(MADCTL);
+        self.display.write_params(&[orientation.madctl()]);
+        self.display.pin_select(false);
+        self.orientation = orientation;
+    }
+
+    /// Returns the underlying display interface, e.g. to change the backlight.
+    pub fn display_mut(&mut self) -> &mut AlarmoDisplay<B> {
+        &mut self.display
+    }
+
+    pub fn into_inner(self) -> AlarmoDisplay<B> {
+        self.display
+    }
+
+    fn set_window(&mut self, area: &Rectangle) {
+        let bottom_right = area.top_left + area.size - Size::new(1, 1);
+        self.display.set_address_window(
+            area.top_left.x as u16,
+            area.top_left.y as u16,
+            bottom_right.x as u16,
+            bottom_right.y as u16,
+        );
+    }
+}
+
+impl Orientation {
+    fn madctl(self) -> u8 {
+        match self {
+            Orientation::Landscape => MADCTL_MY | MADCTL_MV,
+            Orientation::LandscapeFlipped => MADCTL_MX | MADCTL_MV,
+            Orientation::Portrait => 0,
+            Orientation::PortraitFlipped => MADCTL_MX | MADCTL_MY,
+        }
+    }
+}
+
+impl<B: DisplayBus> OriginDimensions for AlarmoScreen<B> {
+    fn size(&self) -> Size {
+        match self.orientation {
+            Orientation::Landscape | Orientation::LandscapeFlipped => {
+                Size::new(NATIVE_HEIGHT, NATIVE_WIDTH)
+            }
+            Orientation::Portrait | Orientation::PortraitFlipped => {
+                Size::new(NATIVE_WIDTH, NATIVE_HEIGHT)
+            }
+        }
+    }
+}
+
+impl<B: DisplayBus> DrawTarget for AlarmoScreen<B> {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let bounds = self.bounding_box();
+        for Pixel(point, color) in pixels {
+            if bounds.contains(point) {
+                self.set_window(&Rectangle::new(point, Size::new(1, 1)));
+                self.display.write_pixels(iter::once(color.into_storage()));
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let drawable = area.intersection(&self.bounding_box());
+        if drawable.is_zero_sized() {
+            return Ok(());
+        }
+        self.set_window(&drawable);
+
+        // Both the GRAM window and the rectangle points are in row-major order
+        let colors = area.points().zip(colors);
+        if drawable == *area {
+            self.display
+                .write_pixels(colors.map(|(_, color)| color.into_storage()));
+        } else {
+            self.display.write_pixels(
+                colors
+                    .filter(|(point, _)| drawable.contains(*point))
+                    .map(|(_, color)| color.into_storage()),
+            );
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let drawable = area.intersection(&self.bounding_box());
+        if drawable.is_zero_sized() {
+            return Ok(());
+        }
+        self.set_window(&drawable);
+        self.display.write_pixels(
+            iter::repeat(color.into_storage())
+                .take(drawable.size.width as usize * drawable.size.height as usize),
+        );
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_solid(&self.bounding_box(), color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::bus::mock::{BusOp, RecordingBus};
+    use crate::display::{CASET, RAMWR, RASET};
+    use embedded_graphics::prelude::{Point, RgbColor};
+
+    fn screen(orientation: Orientation) -> AlarmoScreen<RecordingBus> {
+        let display = AlarmoDisplay::from_bus(RecordingBus::default());
+        let mut screen = AlarmoScreen::with_orientation(display, orientation);
+        screen.display_mut().bus_mut().take();
+        screen
+    }
+
+    /// Groups the recorded accesses by command, with the parameters or pixels that follow.
+    fn commands(screen: &mut AlarmoScreen<RecordingBus>) -> Vec<(u8, Vec<u16>)> {
+        let ops = screen.display_mut().bus_mut().take();
+        assert_eq!(ops.last(), Some(&BusOp::Select(false)));
+        let mut commands = Vec::new();
+        for op in ops {
+            match op {
+                BusOp::Command(cmd) => commands.push((cmd, Vec::new())),
+                BusOp::Data8(data) => commands.last_mut().unwrap().1.push(data as u16),
+                BusOp::Data16(data) => commands.last_mut().unwrap().1.push(data),
+                BusOp::Select(_) | BusOp::Read => {}
+            }
+        }
+        commands
+    }
+
+    /// The commands that set the GRAM window, with big-endian coordinates.
+    fn window(x0: u16, y0: u16, x1: u16, y1: u16) -> [(u8, Vec<u16>); 2] {
+        let params = |start: u16, end: u16| {
+            [start.to_be_bytes(), end.to_be_bytes()]
+                .concat()
+                .into_iter()
+                .map(u16::from)
+                .collect()
+        };
+        [(CASET, params(x0, x1)), (RASET, params(y0, y1))]
+    }
+
+    #[test]
+    fn init_sequence() {
+        for (orientation, madctl, size) in [
+            (Orientation::Landscape, 0xa0, Size::new(320, 240)),
+            (Orientation::LandscapeFlipped, 0x60, Size::new(320, 240)),
+            (Orientation::Portrait, 0x00, Size::new(240, 320)),
+            (Orientation::PortraitFlipped, 0xc0, Size::new(240, 320)),
+        ] {
+            let display = AlarmoDisplay::from_bus(RecordingBus::default());
+            let mut screen = AlarmoScreen::with_orientation(display, orientation);
+            assert_eq!(
+                commands(&mut screen),
+                [
+                    (SLPOUT, vec![]),
+                    (MADCTL, vec![madctl]),
+                    (INVON, vec![]),
+                    (COLMOD, vec![0x55]),
+                    (NORON, vec![]),
+                    (DISPON, vec![]),
+                ],
+                "{orientation:?}"
+            );
+            assert_eq!(screen.size(), size, "{orientation:?}");
+        }
+    }
+
+    #[test]
+    fn set_orientation() {
+        let mut screen = screen(Orientation::Landscape);
+        screen.set_orientation(Orientation::PortraitFlipped);
+        assert_eq!(commands(&mut screen), [(MADCTL, vec![0xc0])]);
+        assert_eq!(screen.size(), Size::new(240, 320));
+    }
+
+    #[test]
+    fn fill_solid() {
+        let mut screen = screen(Orientation::Landscape);
+        let area = Rectangle::new(Point::new(10, 20), Size::new(3, 2));
+        screen.fill_solid(&area, Rgb565::RED).unwrap();
+        let [caset, raset] = window(10, 20, 12, 21);
+        assert_eq!(
+            commands(&mut screen),
+            [caset, raset, (RAMWR, vec![0xf800; 6])]
+        );
+
+        // Clipped to the screen
+        let area = Rectangle::new(Point::new(318, 238), Size::new(4, 4));
+        screen.fill_solid(&area, Rgb565::BLUE).unwrap();
+        let [caset, raset] = window(318, 238, 319, 239);
+        assert_eq!(
+            commands(&mut screen),
+            [caset, raset, (RAMWR, vec![0x001f; 4])]
+        );
+
+        // Nothing to draw
+        let area = Rectangle::new(Point::new(320, 0), Size::new(4, 4));
+        screen.fill_solid(&area, Rgb565::BLUE).unwrap();
+        assert!(screen.display_mut().bus_mut().take().is_empty());
+    }
+
+    #[test]
+    fn fill_contiguous() {
+        let colors = || (0..6).map(|i| Rgb565::new(i, 0, 0));
+        let mut screen = screen(Orientation::Portrait);
+        let area = Rectangle::new(Point::new(237, 318), Size::new(3, 2));
+        screen.fill_contiguous(&area, colors()).unwrap();
+        let pixels = (0..6).map(|i| i << 11).collect();
+        let [caset, raset] = window(237, 318, 239, 319);
+        assert_eq!(commands(&mut screen), [caset, raset, (RAMWR, pixels)]);
+
+        // The colors of points outside of the screen are skipped
+        let area = Rectangle::new(Point::new(-1, 0), Size::new(3, 2));
+        screen.fill_contiguous(&area, colors()).unwrap();
+        let pixels = [1, 2, 4, 5].map(|i| i << 11).to_vec();
+        let [caset, raset] = window(0, 0, 1, 1);
+        assert_eq!(commands(&mut screen), [caset, raset, (RAMWR, pixels)]);
+    }
+}
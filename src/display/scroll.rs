@@ -0,0 +1,172 @@
+//! Hardware vertical scrolling, using the scroll area of the LCD controller.
+//!
+//! The controller can show GRAM starting from any line of a scroll area, so scrolling only
+//! takes a single command. New content then only needs to be drawn into the lines that
+//! scrolled into view, instead of sending the whole frame again.
+//!
+//! Scrolling always happens along the panel's native 320-line axis, regardless of the memory
+//! access mode. In [`Portrait`] orientation, lines are GRAM rows and the content moves
+//! vertically; in the landscape orientations, lines are GRAM columns and the content moves
+//! horizontally.
+//!
+//! [`Portrait`]: super::Orientation::Portrait
+
+use super::bus::DisplayBus;
+use super::AlarmoDisplay;
+
+// ST7789 scrolling commands
+const VSCRDEF: u8 = 0x33;
+const VSCRSADD: u8 = 0x37;
+
+/// Number of lines along the scrolling axis
+pub const NATIVE_LINES: u16 = 320;
+
+/// A scroll area, between two optional fixed areas.
+///
+/// The fixed areas (e.g. a title bar and a status bar) are not affected by scrolling. Lines
+/// are counted from the start of the panel's native orientation.
+pub struct VerticalScroll {
+    top_fixed: u16,
+    height: u16,
+    offset: u16,
+}
+
+impl VerticalScroll {
+    /// Defines the scroll area, leaving `top_fixed` and `bottom_fixed` lines untouched at either
+    /// end. The scroll offset starts at 0.
+    ///
+    /// ## Panics
+    /// Panics if the fixed areas leave no lines to scroll.
+    pub fn new<B: DisplayBus>(
+        display: &mut AlarmoDisplay<B>,
+        top_fixed: u16,
+        bottom_fixed: u16,
+    ) -> Self {
+        assert!(
+            (top_fixed as u32 + bottom_fixed as u32) < NATIVE_LINES as u32,
+            "fixed areas too large"
+        );
+        let height = NATIVE_LINES - top_fixed - bottom_fixed;
+
+        let [tfa_h, tfa_l] = top_fixed.to_be_bytes();
+        let [vsa_h, vsa_l] = height.to_be_bytes();
+        let [bfa_h, bfa_l] = bottom_fixed.to_be_bytes();
+        display.write_command(VSCRDEF);
+        display.write_params(&[tfa_h, tfa_l, vsa_h, vsa_l, bfa_h, bfa_l]);
+        display.pin_select(false);
+
+        let mut scroll = Self {
+            top_fixed,
+            height,
+            offset: 0,
+        };
+        scroll.set_offset(display, 0);
+        scroll
+    }
+
+    /// Returns the number of lines in the scroll area.
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    /// Shows the scroll area starting from its `offset`-th line. Offsets wrap around the
+    /// height of the area.
+    pub fn set_offset<B: DisplayBus>(&mut self, display: &mut AlarmoDisplay<B>, offset: u16) {
+        self.offset = offset % self.height;
+        let [vsp_h, vsp_l] = (self.top_fixed + self.offset).to_be_bytes();
+        display.write_command(VSCRSADD);
+        display.write_params(&[vsp_h, vsp_l]);
+        display.pin_select(false);
+    }
+
+    /// Moves the content by `lines`. Positive values move it towards the start of the area,
+    /// making room at the end, like a log view.
+    pub fn scroll_by<B: DisplayBus>(&mut self, display: &mut AlarmoDisplay<B>, lines: i32) {
+        let offset = (self.offset as i32 + lines).rem_euclid(self.height as i32);
+        self.set_offset(display, offset as u16);
+    }
+
+    /// Returns the GRAM line that is currently shown as the `line`-th line of the scroll area.
+    ///
+    /// After scrolling by `n` lines, the last `n` lines of the area (up to `height() - 1`)
+    /// show stale content and must be redrawn at these GRAM lines.
+    pub fn gram_line(&self, line: u16) -> u16 {
+        self.top_fixed + (self.offset + line % self.height) % self.height
+    }
+
+    /// Makes the whole panel scrollable again, with no offset.
+    pub fn reset<B: DisplayBus>(self, display: &mut AlarmoDisplay<B>) {
+        Self::new(display, 0, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::bus::mock::{BusOp::*, RecordingBus};
+
+    #[test]
+    fn scroll_area() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        let mut scroll = VerticalScroll::new(&mut display, 10, 20);
+        assert_eq!(scroll.height(), 290);
+        assert_eq!(
+            display.bus_mut().take(),
+            [
+                Select(true),
+                Command(VSCRDEF),
+                Data8(0),
+                Data8(10),
+                Data8(1),
+                Data8(34),
+                Data8(0),
+                Data8(20),
+                Select(false),
+                Select(true),
+                Command(VSCRSADD),
+                Data8(0),
+                Data8(10),
+                Select(false),
+            ]
+        );
+
+        // Scrolling back wraps around to the end of the area
+        scroll.scroll_by(&mut display, -5);
+        assert_eq!(scroll.offset(), 285);
+        assert_eq!(
+            display.bus_mut().take(),
+            [
+                Select(true),
+                Command(VSCRSADD),
+                Data8(1),
+                Data8(39),
+                Select(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn gram_lines() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        let mut scroll = VerticalScroll::new(&mut display, 10, 20);
+        assert_eq!(scroll.gram_line(0), 10);
+        scroll.set_offset(&mut display, 290 + 5);
+        assert_eq!(scroll.offset(), 5);
+        assert_eq!(scroll.gram_line(0), 15);
+        // The last lines of the area come from the start of it
+        assert_eq!(scroll.gram_line(284), 299);
+        assert_eq!(scroll.gram_line(285), 10);
+        assert_eq!(scroll.gram_line(289), 14);
+    }
+
+    #[test]
+    #[should_panic(expected = "fixed areas too large")]
+    fn no_scroll_area() {
+        let mut display = AlarmoDisplay::from_bus(RecordingBus::default());
+        VerticalScroll::new(&mut display, 300, 20);
+    }
+}
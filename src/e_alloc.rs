@@ -1,6 +1,7 @@
 use embedded_alloc::LlffHeap as Heap;
 
-#[global_allocator]
+// Host unit tests use the system allocator
+#[cfg_attr(not(test), global_allocator)]
 static HEAP: Heap = Heap::empty();
 
 pub fn init_heap(size: usize) {
@@ -12,3 +13,9 @@ pub fn init_heap(size: usize) {
     );
     unsafe { HEAP.init(start_addr, size) }
 }
+
+/// Returns the number of heap bytes in use and free.
+#[cfg(feature = "panic")]
+pub(crate) fn stats() -> (usize, usize) {
+    (HEAP.used(), HEAP.free())
+}
@@ -0,0 +1,279 @@
+//! Timing of the FMC bus that drives the LCD.
+//!
+//! The LCD controller specifies its bus timings in nanoseconds, while the FMC counts kernel
+//! clock cycles. [`LcdTiming`] holds the former, and [`LcdTiming::compute`] converts them for
+//! a given kernel clock, checking that they fit in the FMC's timing registers.
+//!
+//! When [`AlarmoOptions::lcd_timing`](crate::AlarmoOptions::lcd_timing) is set,
+//! [`Alarmo::init_with_options`](crate::Alarmo::init_with_options) does this with the clock
+//! selected by [`AlarmoOptions::fmc_clock`](crate::AlarmoOptions::fmc_clock), so the bus stays
+//! within spec (and as fast as allowed) for every clock configuration. Otherwise, it uses fixed
+//! timings tuned for the default 64 MHz `per_ck`.
+
+use stm32h7xx_hal::rcc::CoreClocks;
+use stm32h7xx_hal::time::Hertz;
+
+/// Largest value of the address setup and bus turnaround fields, in cycles
+const MAX_SHORT_PHASE: u32 = 15;
+/// Largest value of the data setup field, in cycles
+const MAX_DATA_SETUP: u32 = 255;
+
+/// Kernel clock for the FMC.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum FmcClock {
+    /// `per_ck`, which runs from HSI (64 MHz) by default and does not depend on `sys_ck`
+    #[default]
+    PerCk,
+    /// `hclk3`, the AHB clock. It scales with `sys_ck`, so it can be used to speed up the bus
+    /// when the system clock is raised.
+    Hclk,
+}
+
+/// Timing requirements for one kind of bus access, in nanoseconds.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AccessTiming {
+    /// Time the strobe (`NWE`/`NOE`) stays high before each access
+    pub address_setup_ns: u32,
+    /// Time the strobe stays low, i.e. the data setup time
+    pub data_setup_ns: u32,
+    /// Idle time after each access
+    pub bus_turnaround_ns: u32,
+    /// Minimum duration of a whole access
+    pub cycle_ns: u32,
+}
+
+/// Timing requirements of the LCD controller.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LcdTiming {
+    /// Reads, including GRAM reads (the slowest kind of access)
+    pub read: AccessTiming,
+    pub write: AccessTiming,
+}
+
+/// Register values for one kind of access, in FMC kernel clock cycles.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FmcCycles {
+    pub address_setup: u8,
+    pub data_setup: u8,
+    pub bus_turnaround: u8,
+}
+
+/// Register values for the LCD bank, see [`LcdTiming::compute`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FmcTimings {
+    pub read: FmcCycles,
+    pub write: FmcCycles,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimingError {
+    /// The kernel clock frequency is zero
+    NoClock,
+    /// A phase needs more cycles than the FMC supports at this clock frequency
+    OutOfRange,
+}
+
+impl LcdTiming {
+    /// Minimum timings from the ST7789 datasheet. Reads use the frame memory (GRAM) timings,
+    /// which also satisfy register reads.
+    pub const ST7789: LcdTiming = LcdTiming {
+        read: AccessTiming {
+            address_setup_ns: 90,
+            data_setup_ns: 355,
+            bus_turnaround_ns: 0,
+            cycle_ns: 450,
+        },
+        write: AccessTiming {
+            address_setup_ns: 15,
+            data_setup_ns: 15,
+            bus_turnaround_ns: 0,
+            cycle_ns: 66,
+        },
+    };
+
+    /// Converts the timings to FMC cycles for the given kernel clock.
+    ///
+    /// Every phase is rounded up to whole cycles, and the data setup phase is extended until
+    /// the whole access lasts at least `cycle_ns`. The address setup field only holds up to 15
+    /// cycles: at high clock frequencies, the rest is moved to the bus turnaround phase, which
+    /// also keeps the strobe high.
+    pub fn compute(&self, kernel_clock: Hertz) -> Result<FmcTimings, TimingError> {
+        let hz = kernel_clock.raw();
+        if hz == 0 {
+            return Err(TimingError::NoClock);
+        }
+        Ok(FmcTimings {
+            read: self.read.to_cycles(hz)?,
+            write: self.write.to_cycles(hz)?,
+        })
+    }
+}
+
+impl Default for LcdTiming {
+    fn default() -> Self {
+        Self::ST7789
+    }
+}
+
+impl AccessTiming {
+    fn to_cycles(self, hz: u32) -> Result<FmcCycles, TimingError> {
+        let mut address_setup = ns_to_cycles(self.address_setup_ns, hz);
+        let mut bus_turnaround = ns_to_cycles(self.bus_turnaround_ns, hz);
+        if address_setup > MAX_SHORT_PHASE {
+            bus_turnaround = bus_turnaround.max(address_setup - MAX_SHORT_PHASE);
+            address_setup = MAX_SHORT_PHASE;
+        }
+
+        // The FMC adds one cycle to each access, and requires at least one data setup cycle
+        let total = ns_to_cycles(self.cycle_ns, hz);
+        let data_setup = ns_to_cycles(self.data_setup_ns, hz)
+            .max(1)
+            .max(total.saturating_sub(address_setup + bus_turnaround + 1));
+
+        if bus_turnaround > MAX_SHORT_PHASE || data_setup > MAX_DATA_SETUP {
+            return Err(TimingError::OutOfRange);
+        }
+        Ok(FmcCycles {
+            address_setup: address_setup as u8,
+            data_setup: data_setup as u8,
+            bus_turnaround: bus_turnaround as u8,
+        })
+    }
+}
+
+impl FmcCycles {
+    /// Returns the duration of a whole access at the given kernel clock, in nanoseconds.
+    pub fn cycle_ns(&self, kernel_clock: Hertz) -> u32 {
+        let cycles =
+            self.address_setup as u64 + self.data_setup as u64 + self.bus_turnaround as u64 + 1;
+        (cycles * 1_000_000_000).div_ceil(kernel_clock.raw().max(1) as u64) as u32
+    }
+}
+
+impl FmcTimings {
+    /// The timings used when no [`LcdTiming`] is given, tuned for the default 64 MHz `per_ck`.
+    /// The panic handler also uses these, as it resets the clocks to their defaults.
+    pub(crate) const DEFAULT_PER_CK: FmcTimings = FmcTimings {
+        read: FmcCycles {
+            address_setup: 15,
+            data_setup: 30,
+            bus_turnaround: 0,
+        },
+        write: FmcCycles {
+            address_setup: 2,
+            data_setup: 2,
+            bus_turnaround: 0,
+        },
+    };
+}
+
+impl FmcClock {
+    /// Returns the frequency of this clock.
+    ///
+    /// ## Panics
+    /// Panics if the clock is not running.
+    pub fn frequency(&self, clocks: &CoreClocks) -> Hertz {
+        match self {
+            FmcClock::PerCk => clocks.per_ck().expect("per_ck must run"),
+            FmcClock::Hclk => clocks.hclk(),
+        }
+    }
+}
+
+fn ns_to_cycles(ns: u32, hz: u32) -> u32 {
+    (ns as u64 * hz as u64).div_ceil(1_000_000_000) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cycles(address_setup: u8, data_setup: u8, bus_turnaround: u8) -> FmcCycles {
+        FmcCycles {
+            address_setup,
+            data_setup,
+            bus_turnaround,
+        }
+    }
+
+    #[test]
+    fn per_ck_default() {
+        // 15.625 ns per cycle
+        let timings = LcdTiming::ST7789.compute(Hertz::MHz(64)).unwrap();
+        assert_eq!(timings.read, cycles(6, 23, 0));
+        // The data setup is extended to reach the 66 ns cycle time
+        assert_eq!(timings.write, cycles(1, 3, 0));
+        assert_eq!(timings.write.cycle_ns(Hertz::MHz(64)), 79);
+    }
+
+    #[test]
+    fn fixed_per_ck_within_spec() {
+        let spec = LcdTiming::ST7789;
+        let clock = Hertz::MHz(64);
+        let fixed = FmcTimings::DEFAULT_PER_CK;
+        for (access, cycles) in [(spec.read, fixed.read), (spec.write, fixed.write)] {
+            let ns = |cycles: u8| cycles as u32 * 1000 / 64;
+            assert!(cycles.cycle_ns(clock) >= access.cycle_ns);
+            assert!(ns(cycles.data_setup) >= access.data_setup_ns);
+            assert!(ns(cycles.address_setup + cycles.bus_turnaround) >= access.address_setup_ns);
+        }
+    }
+
+    #[test]
+    fn fast_hclk() {
+        // The read address setup needs 25 cycles, 10 of them move to the bus turnaround
+        let timings = LcdTiming::ST7789.compute(Hertz::MHz(275)).unwrap();
+        assert_eq!(timings.read, cycles(15, 98, 10));
+        assert_eq!(timings.write, cycles(5, 13, 0));
+    }
+
+    #[test]
+    fn slow_clock() {
+        // Every phase fits in a single cycle, but data setup can't be 0
+        let timings = LcdTiming::ST7789.compute(Hertz::MHz(1)).unwrap();
+        assert_eq!(timings.read, cycles(1, 1, 0));
+        assert_eq!(timings.write, cycles(1, 1, 0));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            LcdTiming::ST7789.compute(Hertz::from_raw(0)),
+            Err(TimingError::NoClock)
+        );
+        // The read address setup would need 39 turnaround cycles
+        assert_eq!(
+            LcdTiming::ST7789.compute(Hertz::MHz(600)),
+            Err(TimingError::OutOfRange)
+        );
+        let slow_data = LcdTiming {
+            read: AccessTiming {
+                data_setup_ns: 5000,
+                ..LcdTiming::ST7789.read
+            },
+            ..LcdTiming::ST7789
+        };
+        assert_eq!(
+            slow_data.compute(Hertz::MHz(64)),
+            Err(TimingError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn within_spec() {
+        let spec = LcdTiming::ST7789;
+        for mhz in (8..=300).step_by(7) {
+            let clock = Hertz::MHz(mhz);
+            let timings = spec.compute(clock).unwrap();
+            for (access, cycles) in [(spec.read, timings.read), (spec.write, timings.write)] {
+                let ns = |cycles: u8| cycles as u32 * 1000 / mhz;
+                assert!(cycles.cycle_ns(clock) >= access.cycle_ns, "{mhz} MHz");
+                assert!(ns(cycles.data_setup) >= access.data_setup_ns, "{mhz} MHz");
+                assert!(
+                    ns(cycles.address_setup + cycles.bus_turnaround) >= access.address_setup_ns,
+                    "{mhz} MHz"
+                );
+            }
+        }
+    }
+}
@@ -1,5 +1,5 @@
 #![doc =  include_str!("../README.md")]
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 use crate::input::{Buttons, ExtInterrupts};
 use core::cell::RefCell;
@@ -13,14 +13,19 @@ use stm32h7xx_hal::{
     rcc::{CoreClocks, ResetEnable},
 };
 
-#[cfg(all(feature = "alloc", feature = "panic"))]
-extern crate alloc; // For panic formatting
+#[cfg(feature = "alloc")]
+extern crate alloc; // For frame buffers and PNG decoding
 
+pub mod clock;
+mod crc;
 pub mod delay;
 pub mod dial;
+pub mod fmc;
 mod hal_sys;
 pub mod input;
 mod pac;
+pub mod recording;
+pub mod storage;
 
 #[cfg(feature = "display")]
 pub mod display;
@@ -31,6 +36,9 @@ mod e_alloc;
 #[cfg(feature = "panic")]
 pub mod panic;
 
+#[cfg(feature = "display")]
+pub mod screenshot;
+
 static mut DELAY: Option<RefCell<Delay>> = None;
 
 /// Singleton that allows access to the Alarmo's peripherals.
@@ -47,12 +55,15 @@ static mut DELAY: Option<RefCell<Delay>> = None;
 /// Peripherals are accessed through public fields, allowing for more flexible lifetime constraints.
 pub struct Alarmo {
     pub clocks: CoreClocks,
+    pub clock: clock::Clock,
     pub delay: &'static RefCell<Delay>,
     pub dial: Dial,
     pub ext_interrupts: ExtInterrupts,
     pub buttons: Buttons,
     #[cfg(feature = "display")]
     pub display: display::AlarmoDisplay,
+    #[cfg(feature = "display")]
+    pub display_dma: display::dma::DisplayDma,
     #[cfg(feature = "usb")]
     pub usb1: stm32h7xx_hal::usb_hs::USB1,
     #[cfg(feature = "emmc")]
@@ -77,6 +88,26 @@ pub struct AlarmoOptions {
     ///
     /// The default value, `None`, uses the value from 2ndloader.
     pub sys_ck: Option<Hertz>,
+    /// The kernel clock of the FMC, which drives the LCD bus.
+    ///
+    /// The default, [`FmcClock::PerCk`](fmc::FmcClock::PerCk), does not depend on `sys_ck`.
+    pub fmc_clock: fmc::FmcClock,
+    /// Timing requirements of the LCD bus. If set, the FMC timings are computed from these and
+    /// the frequency of `fmc_clock`, e.g. [`LcdTiming::ST7789`](fmc::LcdTiming::ST7789) for
+    /// the fastest bus within the LCD controller's spec.
+    ///
+    /// The default value, `None`, uses fixed timings tuned for the default 64 MHz `per_ck`, and
+    /// requires `fmc_clock` to be [`FmcClock::PerCk`](fmc::FmcClock::PerCk).
+    pub lcd_timing: Option<fmc::LcdTiming>,
+    #[cfg(feature = "emmc")]
+    /// The width of the eMMC data bus, which [`Sdmmc::init`](stm32h7xx_hal::sdmmc::Sdmmc::init)
+    /// switches to. The 8-bit bus also uses PB8, PB9, PG13 and PG14.
+    ///
+    /// [`EmmcStorage::verify_bus_width`](storage::EmmcStorage::verify_bus_width) checks that
+    /// the 8-bit bus reads the same data as the 4-bit bus.
+    ///
+    /// The default is [`EmmcBusWidth::Four`](storage::EmmcBusWidth::Four).
+    pub emmc_bus_width: storage::EmmcBusWidth,
 }
 
 impl Alarmo {
@@ -98,6 +129,10 @@ impl Alarmo {
 
         cortex.SCB.enable_icache();
         cortex.SCB.enable_dcache(&mut cortex.CPUID);
+        #[cfg(feature = "panic")]
+        panic::crash::init();
+        #[cfg(feature = "fault-handlers")]
+        panic::fault::enable(&mut cortex.SCB);
         cortex_m::interrupt::enable();
 
         let peripherals = Stm32Peripherals::take().unwrap();
@@ -153,6 +188,11 @@ impl Alarmo {
             gpiog.pg10,
             gpiog.pg11,
             gpiob.pb4.into_analog(),
+            gpiob.pb8,
+            gpiob.pb9,
+            gpiog.pg13,
+            gpiog.pg14,
+            options.emmc_bus_width,
             peripherals.SDMMC2,
             ccdr.peripheral.SDMMC2,
             &ccdr.clocks,
@@ -171,6 +211,13 @@ impl Alarmo {
             ccdr.peripheral.TIM3,
         );
 
+        let uptime_timer = pac::timers::uptime_timer(
+            &ccdr.clocks,
+            peripherals.TIM2,
+            ccdr.peripheral.TIM2,
+            clock::TICKS_PER_SECOND,
+        );
+
         // Split buttons
         let exti = ExtInterrupts {
             syscfg: peripherals.SYSCFG,
@@ -180,11 +227,27 @@ impl Alarmo {
         let buttons = Buttons::split(gpiog.pg5, gpiog.pg6, gpioc.pc5);
 
         // Enable GPIO for FMC and init SRAM
-        let disp_pin = unsafe { pac::sram::init(peripherals.FMC, gpioc.pc7) };
+        let fmc_timings = match options.lcd_timing {
+            Some(timing) => timing
+                .compute(options.fmc_clock.frequency(&ccdr.clocks))
+                .expect("LCD timings out of range for the FMC clock"),
+            None => {
+                assert_eq!(
+                    options.fmc_clock,
+                    fmc::FmcClock::PerCk,
+                    "lcd_timing must be set to run the FMC from hclk"
+                );
+                fmc::FmcTimings::DEFAULT_PER_CK
+            }
+        };
+        let disp_pin = unsafe { pac::sram::init(peripherals.FMC, gpioc.pc7, &fmc_timings) };
         // Enable the FMC clocks for SRAM
         ccdr.peripheral
             .FMC
-            .kernel_clk_mux(stm32h7xx_hal::pac::rcc::d1ccipr::FMCSEL_A::Per)
+            .kernel_clk_mux(match options.fmc_clock {
+                fmc::FmcClock::PerCk => stm32h7xx_hal::pac::rcc::d1ccipr::FMCSEL_A::Per,
+                fmc::FmcClock::Hclk => stm32h7xx_hal::pac::rcc::d1ccipr::FMCSEL_A::RccHclk3,
+            })
             .enable();
 
         DELAY = Some(RefCell::new(Delay::new(cortex.SYST, ccdr.clocks)));
@@ -203,6 +266,7 @@ impl Alarmo {
         Alarmo {
             delay: DELAY.as_ref().unwrap(),
             clocks: ccdr.clocks,
+            clock: clock::Clock::new(uptime_timer),
             dial: Dial::new(dial_timers, adc, cortex.SCB),
             ext_interrupts: exti,
             buttons,
@@ -213,6 +277,8 @@ impl Alarmo {
                 gpiog.pg4,
                 DELAY.as_ref().unwrap(),
             ),
+            #[cfg(feature = "display")]
+            display_dma: display::dma::DisplayDma::new(peripherals.MDMA, ccdr.peripheral.MDMA),
             #[cfg(feature = "usb")]
             usb1,
             #[cfg(feature = "emmc")]
@@ -227,6 +293,10 @@ impl Default for AlarmoOptions {
             #[cfg(feature = "alloc")]
             heap_size: 0x1000000, // 16 MiB
             sys_ck: None,
+            fmc_clock: fmc::FmcClock::default(),
+            lcd_timing: None,
+            #[cfg(feature = "emmc")]
+            emmc_bus_width: storage::EmmcBusWidth::default(),
         }
     }
 }
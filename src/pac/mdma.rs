@@ -0,0 +1,105 @@
+//! Minimal MDMA driver, used for memory-to-FMC transfers on channel 0.
+
+use stm32h7xx_hal::pac::mdma::RegisterBlock;
+
+/// Maximum size of a single block (CBNDTR.BNDT is 17 bits wide)
+pub const MAX_BLOCK_BYTES: usize = 0x10000;
+/// Maximum number of repetitions of a block (CBNDTR.BRC is 12 bits wide)
+pub const MAX_BLOCK_REPEAT: usize = 0x1000;
+
+const ISR_TEIF: u32 = 1 << 0;
+const ISR_CTCIF: u32 = 1 << 1;
+const IFCR_ALL: u32 = 0x1f;
+
+const CR_EN: u32 = 1 << 0;
+const CR_TEIE: u32 = 1 << 1;
+const CR_CTCIE: u32 = 1 << 2;
+const CR_PL_HIGH: u32 = 0b10 << 6;
+const CR_SWRQ: u32 = 1 << 16;
+
+const TCR_SINC_INCREMENT: u32 = 0b10;
+const TCR_DINC_FIXED: u32 = 0b00 << 2;
+const TCR_SSIZE_HALF_WORD: u32 = 0b01 << 4;
+const TCR_DSIZE_HALF_WORD: u32 = 0b01 << 6;
+const TCR_SINCOS_HALF_WORD: u32 = 0b01 << 8;
+const TCR_DINCOS_HALF_WORD: u32 = 0b01 << 10;
+const TCR_TLEN_POS: u32 = 18;
+const TCR_TRGM_REPEATED_BLOCK: u32 = 0b10 << 28;
+const TCR_SWRM: u32 = 1 << 30;
+
+const BNDTR_BRC_POS: u32 = 20;
+const TBR_SBUS_AHB: u32 = 1 << 16;
+
+/// Starts a software-triggered transfer of `blocks` blocks of `block_bytes` bytes each, from
+/// an incrementing source address to a fixed half-word destination.
+///
+/// ## Safety
+/// The source range must stay valid (and must not be written to) until the transfer completes.
+/// The channel must not be in use.
+pub unsafe fn start_to_fixed_half_word(
+    mdma: &RegisterBlock,
+    src: u32,
+    dst: u32,
+    block_bytes: usize,
+    blocks: usize,
+    interrupt: bool,
+) {
+    debug_assert!(block_bytes > 0 && block_bytes <= MAX_BLOCK_BYTES && block_bytes % 2 == 0);
+    debug_assert!(blocks > 0 && blocks <= MAX_BLOCK_REPEAT);
+
+    mdma.ch0.cr.write(|w| w.bits(0));
+    mdma.ch0.ifcr.write(|w| w.bits(IFCR_ALL));
+
+    mdma.ch0.tcr.write(|w| {
+        w.bits(
+            TCR_SINC_INCREMENT
+                | TCR_DINC_FIXED
+                | TCR_SSIZE_HALF_WORD
+                | TCR_DSIZE_HALF_WORD
+                | TCR_SINCOS_HALF_WORD
+                | TCR_DINCOS_HALF_WORD
+                // 128-byte internal buffer
+                | (127 << TCR_TLEN_POS)
+                | TCR_TRGM_REPEATED_BLOCK
+                | TCR_SWRM,
+        )
+    });
+    mdma.ch0
+        .bndtr
+        .write(|w| w.bits(block_bytes as u32 | (((blocks - 1) as u32) << BNDTR_BRC_POS)));
+    mdma.ch0.sar.write(|w| w.bits(src));
+    mdma.ch0.dar.write(|w| w.bits(dst));
+    mdma.ch0.brur.write(|w| w.bits(0));
+    mdma.ch0.lar.write(|w| w.bits(0));
+    // DTCM is only reachable through the AHB bus, everything else (AXI SRAM, OCTOSPI, FMC)
+    // through AXI
+    let src_ahb = (0x2000_0000..0x2002_0000).contains(&src);
+    mdma.ch0
+        .tbr
+        .write(|w| w.bits(if src_ahb { TBR_SBUS_AHB } else { 0 }));
+
+    let mut cr = CR_EN | CR_PL_HIGH;
+    if interrupt {
+        cr |= CR_TEIE | CR_CTCIE;
+    }
+    mdma.ch0.cr.write(|w| w.bits(cr));
+    mdma.ch0.cr.modify(|r, w| w.bits(r.bits() | CR_SWRQ));
+}
+
+/// Returns whether the last transfer completed.
+pub fn is_complete(mdma: &RegisterBlock) -> bool {
+    mdma.ch0.isr.read().bits() & ISR_CTCIF != 0
+}
+
+/// Returns whether the last transfer was aborted because of a bus error.
+pub fn has_error(mdma: &RegisterBlock) -> bool {
+    mdma.ch0.isr.read().bits() & ISR_TEIF != 0
+}
+
+/// Disables the channel and clears all of its flags.
+pub fn stop(mdma: &RegisterBlock) {
+    unsafe {
+        mdma.ch0.cr.write(|w| w.bits(0));
+        mdma.ch0.ifcr.write(|w| w.bits(IFCR_ALL));
+    }
+}
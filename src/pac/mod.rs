@@ -2,6 +2,9 @@ pub mod adc;
 pub mod sram;
 pub mod timers;
 
+#[cfg(feature = "display")]
+pub mod mdma;
+
 #[cfg(feature = "usb")]
 pub mod usb;
 
@@ -1,8 +1,24 @@
-use stm32h7xx_hal::gpio::{Speed, PB4, PD6, PD7, PG10, PG11, PG9};
-use stm32h7xx_hal::pac::SDMMC2;
+use crate::storage::EmmcBusWidth;
+use stm32h7xx_hal::gpio::{Speed, PB4, PB8, PB9, PD6, PD7, PG10, PG11, PG13, PG14, PG9};
+use stm32h7xx_hal::pac::{delay_block_sdmmc1, DELAY_BLOCK_SDMMC2, SDMMC2};
 use stm32h7xx_hal::rcc::rec::Sdmmc2;
 use stm32h7xx_hal::rcc::CoreClocks;
-use stm32h7xx_hal::sdmmc::{Buswidth, Emmc, Sdmmc, SdmmcExt};
+use stm32h7xx_hal::sdmmc::{Emmc, Sdmmc, SdmmcExt};
+
+/// Number of delay cells in the delay block
+const DLYB_MAX_SELECT: u8 = 12;
+/// Number of settings of the delay of one cell
+const DLYB_MAX_UNIT: u8 = 128;
+/// LNG bits of the first 11 delay cells
+const LNG_10_0: u16 = 0x7ff;
+/// LNG bits of the last 2 delay cells
+const LNG_11_10: u16 = 0xc00;
+/// Polls of the length valid flag before giving up
+const DLYB_TIMEOUT: u32 = 10_000;
+/// Receive clock selection: the bus clock, as fed back through the pad
+const SELCLKRX_IO_IN: u8 = 0b00;
+/// Receive clock selection: the bus clock, delayed by the delay block
+const SELCLKRX_DELAYED: u8 = 0b10;
 
 pub fn split_emmc(
     pd6: PD6,
@@ -11,25 +27,131 @@ pub fn split_emmc(
     pg10: PG10,
     pg11: PG11,
     pb4: PB4,
+    pb8: PB8,
+    pb9: PB9,
+    pg13: PG13,
+    pg14: PG14,
+    bus_width: EmmcBusWidth,
     peripheral: SDMMC2,
     rcc: Sdmmc2,
     clocks: &CoreClocks,
 ) -> Sdmmc<SDMMC2, Emmc> {
+    // The eMMC expects pull-ups on CMD and the data lines, to keep them high while nothing
+    // drives them
     let _ = (
         pd6.into_alternate::<11>().speed(Speed::VeryHigh),
-        pd7.into_alternate::<11>().speed(Speed::VeryHigh),
-        pg9.into_alternate::<11>().speed(Speed::VeryHigh),
-        pg10.into_alternate::<11>().speed(Speed::VeryHigh),
-        pg11.into_alternate::<10>().speed(Speed::VeryHigh),
-        pb4.into_alternate::<9>().speed(Speed::VeryHigh),
-        // Other pins for 8-bus SDMMC. I tried enabling them, but the data gets corrupted
-        // (some bits are flipped)
-        // pb8.into_alternate::<9>().speed(Speed::VeryHigh),
-        // pb9.into_alternate::<9>().speed(Speed::VeryHigh),
-        // pg13.into_alternate::<10>().speed(Speed::VeryHigh),
-        // pg14.into_alternate::<10>().speed(Speed::VeryHigh),
+        pd7.into_alternate::<11>()
+            .speed(Speed::VeryHigh)
+            .internal_pull_up(true),
+        pg9.into_alternate::<11>()
+            .speed(Speed::VeryHigh)
+            .internal_pull_up(true),
+        pg10.into_alternate::<11>()
+            .speed(Speed::VeryHigh)
+            .internal_pull_up(true),
+        pg11.into_alternate::<10>()
+            .speed(Speed::VeryHigh)
+            .internal_pull_up(true),
+        pb4.into_alternate::<9>()
+            .speed(Speed::VeryHigh)
+            .internal_pull_up(true),
     );
+    if bus_width == EmmcBusWidth::Eight {
+        // D4 to D7. D4 and D5 are AF10 on PB8 and PB9, AF9 is FDCAN1: with it, the two lines
+        // were left undriven and bits of the data got flipped
+        let _ = (
+            pb8.into_alternate::<10>()
+                .speed(Speed::VeryHigh)
+                .internal_pull_up(true),
+            pb9.into_alternate::<10>()
+                .speed(Speed::VeryHigh)
+                .internal_pull_up(true),
+            pg13.into_alternate::<10>()
+                .speed(Speed::VeryHigh)
+                .internal_pull_up(true),
+            pg14.into_alternate::<10>()
+                .speed(Speed::VeryHigh)
+                .internal_pull_up(true),
+        );
+    }
     // Official firmware also does this, doesn't seem to be needed though
     // pe1.into_push_pull_output().set_high();
-    peripheral.sdmmc_unchecked(Buswidth::Four, rcc, &clocks)
+    peripheral.sdmmc_unchecked(bus_width.into(), rcc, clocks)
+}
+
+fn delay_block() -> &'static delay_block_sdmmc1::RegisterBlock {
+    // SAFETY: `Alarmo::init` doesn't hand out the delay block of SDMMC2, it's only accessed
+    // through the functions below, which borrow SDMMC2 mutably
+    unsafe { &*DELAY_BLOCK_SDMMC2::ptr() }
+}
+
+/// Waits until no command or data transfer is active, so the clock settings can be changed.
+fn wait_idle(sdmmc: &SDMMC2) {
+    while sdmmc.star.read().dpsmact().bit_is_set() || sdmmc.star.read().cpsmact().bit_is_set() {}
+}
+
+/// Measures one period of the bus clock with the delay block, and configures it to delay by
+/// that much. Returns the number of delay cells in one period, i.e. the number of sampling
+/// phases that [`set_sampling_phase`] accepts.
+///
+/// The bus clock must be running at its final frequency.
+pub fn calibrate_sampling_delay(sdmmc: &mut SDMMC2) -> Option<u8> {
+    wait_idle(sdmmc);
+    let dlyb = delay_block();
+    dlyb.cr.write(|w| w.den().set_bit().sen().set_bit());
+
+    // Increase the delay of the cells until the line spans one period of the clock: at least
+    // one of LNG[10:0] is set, and LNG[11:10] are not both set. Same as ST's LL_DLYB driver
+    let mut found = None;
+    for unit in 0..DLYB_MAX_UNIT {
+        dlyb.cfgr
+            .write(|w| unsafe { w.sel().bits(DLYB_MAX_SELECT).unit().bits(unit) });
+        if !(0..DLYB_TIMEOUT).any(|_| dlyb.cfgr.read().lngf().bit_is_set()) {
+            break;
+        }
+        let lng = dlyb.cfgr.read().lng().bits();
+        if lng & LNG_10_0 != 0 && lng & LNG_11_10 != LNG_11_10 {
+            found = Some((unit, lng));
+            break;
+        }
+    }
+
+    // The last cell that saw the clock edge gives the number of cells in one period
+    let taps = found.and_then(|(unit, lng)| {
+        let taps = (1..=10).rev().find(|&cell| lng >> cell != 0)?;
+        Some((taps, unit))
+    });
+    let Some((taps, unit)) = taps else {
+        dlyb.cr.reset();
+        return None;
+    };
+    dlyb.cr.reset();
+    dlyb.cr.write(|w| w.den().set_bit().sen().set_bit());
+    dlyb.cfgr
+        .write(|w| unsafe { w.sel().bits(taps).unit().bits(unit) });
+    dlyb.cr.write(|w| w.den().set_bit());
+    Some(taps)
+}
+
+/// Samples received data with the bus clock delayed by `phase` delay cells, calibrated by
+/// [`calibrate_sampling_delay`], or directly with the bus clock if `phase` is `None`.
+pub fn set_sampling_phase(sdmmc: &mut SDMMC2, phase: Option<u8>) {
+    wait_idle(sdmmc);
+    let dlyb = delay_block();
+    match phase {
+        Some(phase) => {
+            dlyb.cr.write(|w| w.den().set_bit().sen().set_bit());
+            dlyb.cfgr.modify(|_, w| unsafe { w.sel().bits(phase) });
+            dlyb.cr.write(|w| w.den().set_bit());
+            sdmmc
+                .clkcr
+                .modify(|_, w| unsafe { w.selclkrx().bits(SELCLKRX_DELAYED) });
+        }
+        None => {
+            sdmmc
+                .clkcr
+                .modify(|_, w| unsafe { w.selclkrx().bits(SELCLKRX_IO_IN) });
+            dlyb.cr.reset();
+        }
+    }
 }
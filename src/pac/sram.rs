@@ -1,10 +1,15 @@
+use crate::fmc::{FmcCycles, FmcTimings};
 use crate::hal_sys;
 use stm32h7xx_hal::{
     gpio::{Output, Pin, PushPull},
     pac::{self, FMC},
 };
 
-pub unsafe fn init(fmc: FMC, pc7: Pin<'C', 7>) -> Pin<'C', 7, Output<PushPull>> {
+pub unsafe fn init(
+    fmc: FMC,
+    pc7: Pin<'C', 7>,
+    timings: &FmcTimings,
+) -> Pin<'C', 7, Output<PushPull>> {
     init_gpio('A', &[4], 12);
     init_gpio('F', &[12], 12);
     init_gpio('B', &[14, 15], 12);
@@ -16,8 +21,8 @@ pub unsafe fn init(fmc: FMC, pc7: Pin<'C', 7>) -> Pin<'C', 7, Output<PushPull>>
     pc7.set_high();
 
     fmc_norsram_init(&fmc);
-    fmc_norsram_timing_init(&fmc);
-    fmc_norsram_extended_timing_init(&fmc);
+    fmc_norsram_timing_init(&fmc, &timings.read);
+    fmc_norsram_extended_timing_init(&fmc, &timings.write);
 
     // Enable FMC
     fmc.bcr1
@@ -38,8 +43,9 @@ unsafe fn fmc_norsram_init(fmc: &FMC) {
     let flash_access = hal_sys::FMC_NORSRAM_FLASH_ACCESS_ENABLE;
 
     // zeroed fields:
-    // ExtendedMode, AsynchronousWait, WriteBurst, ContinuousClock, WriteFifo, PageSize
+    // AsynchronousWait, WriteBurst, ContinuousClock, WriteFifo, PageSize
     let btcr = flash_access
+        | hal_sys::FMC_BCRx_EXTMOD
         | hal_sys::FMC_DATA_ADDRESS_MUX_DISABLE
         | hal_sys::FMC_MEMORY_TYPE_SRAM
         | hal_sys::FMC_NORSRAM_MEM_BUS_WIDTH_16
@@ -69,20 +75,22 @@ unsafe fn fmc_norsram_init(fmc: &FMC) {
         .modify(|old, w| w.bits(((old.bits()) & (!(mask))) | (btcr)));
 }
 
-unsafe fn fmc_norsram_timing_init(fmc: &FMC) {
-    let timings = (2 << hal_sys::FMC_BTRx_ADDSET_Pos)
+unsafe fn fmc_norsram_timing_init(fmc: &FMC, read: &FmcCycles) {
+    // With extended mode, these only apply to reads. The LCD controller needs a much longer
+    // read cycle than write cycle (450ns for GRAM reads), so the read strobe is stretched.
+    let timings = ((read.address_setup as u32) << hal_sys::FMC_BTRx_ADDSET_Pos)
         | (0 << hal_sys::FMC_BTRx_ADDHLD_Pos)
-        | (2 << hal_sys::FMC_BTRx_DATAST_Pos)
-        | (0 << hal_sys::FMC_BTRx_BUSTURN_Pos)
+        | ((read.data_setup as u32) << hal_sys::FMC_BTRx_DATAST_Pos)
+        | ((read.bus_turnaround as u32) << hal_sys::FMC_BTRx_BUSTURN_Pos)
         | (1u32.wrapping_sub(1) << hal_sys::FMC_BTRx_CLKDIV_Pos)
         | (2u32.wrapping_sub(2) << hal_sys::FMC_BTRx_DATLAT_Pos)
         | hal_sys::FMC_ACCESS_MODE_A;
     fmc.btr1.write(|w| w.bits(timings));
 }
 
-unsafe fn fmc_norsram_extended_timing_init(fmc: &FMC) {
-    // TODO: ??
-    let extended_mode = false;
+unsafe fn fmc_norsram_extended_timing_init(fmc: &FMC, write: &FmcCycles) {
+    // Write timings, see fmc_norsram_timing_init
+    let extended_mode = true;
     let mut mask = 0;
 
     let timings = if extended_mode {
@@ -92,10 +100,10 @@ unsafe fn fmc_norsram_extended_timing_init(fmc: &FMC) {
             | hal_sys::FMC_BWTRx_BUSTURN
             | hal_sys::FMC_BWTRx_ACCMOD;
         // ---------
-        (2 << hal_sys::FMC_BWTRx_ADDSET_Pos)
+        ((write.address_setup as u32) << hal_sys::FMC_BWTRx_ADDSET_Pos)
             | (0 << hal_sys::FMC_BWTRx_ADDHLD_Pos)
-            | (2 << hal_sys::FMC_BWTRx_DATAST_Pos)
-            | (0 << hal_sys::FMC_BWTRx_BUSTURN_Pos)
+            | ((write.data_setup as u32) << hal_sys::FMC_BWTRx_DATAST_Pos)
+            | ((write.bus_turnaround as u32) << hal_sys::FMC_BWTRx_BUSTURN_Pos)
             | hal_sys::FMC_ACCESS_MODE_A
     } else {
         0x0FFFFFFF
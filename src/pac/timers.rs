@@ -1,10 +1,10 @@
 use stm32h7xx_hal::{
     gpio::Pin,
-    pac::{TIM1, TIM3},
+    pac::{TIM1, TIM2, TIM3},
     pwm::{ComplementaryDisabled, ComplementaryImpossible, Pwm, PwmAdvExt},
     rcc::{
-        rec::{Tim1, Tim3},
-        CoreClocks,
+        rec::{Tim1, Tim2, Tim3},
+        CoreClocks, ResetEnable,
     },
 };
 
@@ -75,3 +75,22 @@ pub fn display_timer(
         .finalize();
     (t3c4, t3c3)
 }
+
+/// Configures TIM2 (32-bit) as a free-running counter, incrementing at `tick_hz`.
+pub fn uptime_timer(core_clocks: &CoreClocks, tim2: TIM2, ccdr_tim2: Tim2, tick_hz: u32) -> TIM2 {
+    ccdr_tim2.enable().reset();
+
+    let prescaler = core_clocks.timx_ker_ck().raw() / tick_hz;
+    assert!(
+        prescaler > 0 && prescaler <= u16::MAX as u32 + 1,
+        "tick frequency out of range"
+    );
+    unsafe {
+        tim2.psc.write(|w| w.bits(prescaler - 1));
+        tim2.arr.write(|w| w.bits(u32::MAX));
+        // Load the prescaler
+        tim2.egr.write(|w| w.ug().set_bit());
+        tim2.cr1.modify(|_, w| w.cen().set_bit());
+    }
+    tim2
+}
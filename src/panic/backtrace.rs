@@ -0,0 +1,78 @@
+//! Best-effort backtrace by scanning the stack for return addresses.
+//!
+//! Without unwind tables, the stack cannot be walked frame by frame. Instead, every word
+//! between the stack pointer and the top of the stack is checked: words that point into
+//! `.text` with the Thumb bit set, right after a `BL` or `BLX` instruction, are most likely
+//! return addresses. Stale values left over from earlier calls can show up too, so the result
+//! should be read top-down as a hint, not as an exact call chain.
+
+/// Maximum number of return addresses kept
+pub(crate) const MAX_FRAMES: usize = 16;
+
+extern "C" {
+    static __stext: u8;
+    static __etext: u8;
+    static _stack_start: u8;
+}
+
+pub(crate) struct Backtrace {
+    frames: [u32; MAX_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// Scans the stack from `sp` up to its top.
+    ///
+    /// ## Safety
+    /// `sp` must be a valid stack pointer for the main stack.
+    pub(crate) unsafe fn capture(sp: u32) -> Self {
+        let text = (&__stext as *const u8 as u32)..(&__etext as *const u8 as u32);
+        let stack_top = &_stack_start as *const u8 as u32;
+
+        let mut backtrace = Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        };
+        let mut addr = sp & !3;
+        while addr < stack_top && backtrace.len < MAX_FRAMES {
+            let word = (addr as *const u32).read_volatile();
+            if word & 1 == 1 && text.contains(&(word & !1)) && follows_call(word & !1, &text) {
+                backtrace.frames[backtrace.len] = word & !1;
+                backtrace.len += 1;
+            }
+            addr += 4;
+        }
+        backtrace
+    }
+
+    /// Returns the return addresses found, innermost first.
+    pub(crate) fn frames(&self) -> &[u32] {
+        &self.frames[..self.len]
+    }
+}
+
+/// Returns a checksum of `.text`, which identifies the firmware build, so crash reports can be
+/// matched with the right ELF file.
+pub(crate) fn build_id() -> u32 {
+    // Safety: `.text` is mapped and never written
+    unsafe {
+        let start = &__stext as *const u8;
+        let len = &__etext as *const u8 as usize - start as usize;
+        crate::crc::crc32(core::slice::from_raw_parts(start, len))
+    }
+}
+
+/// Checks whether the instruction before `ret` is a call.
+unsafe fn follows_call(ret: u32, text: &core::ops::Range<u32>) -> bool {
+    if ret < text.start + 4 {
+        return false;
+    }
+    // BLX <Rm>: 0100 0111 1xxx x000
+    let prev = ((ret - 2) as *const u16).read_volatile();
+    if prev & 0xff87 == 0x4780 {
+        return true;
+    }
+    // BL/BLX <label>: 1111 0xxx xxxx xxxx, 11x1 xxxx xxxx xxxx
+    let first = ((ret - 4) as *const u16).read_volatile();
+    first & 0xf800 == 0xf000 && prev & 0xd000 == 0xd000
+}
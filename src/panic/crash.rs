@@ -0,0 +1,380 @@
+//! Crash records that survive a warm reset.
+//!
+//! The panic handler (and the fault handlers, with the `fault-handlers` feature) saves a
+//! [`CrashRecord`] in the `.persist` section, which `link.ld` places above the RAM zeroed on
+//! startup, so it keeps its contents across a reset. After rebooting from the panic screen,
+//! [`last_crash`] returns it, so it can be displayed, logged or uploaded. The record is
+//! checksummed, so garbage left in RAM after a power cycle is not mistaken for a crash.
+
+use super::PanicData;
+use crate::clock::Instant;
+use crate::crc::crc32;
+use core::fmt;
+use core::mem::{size_of, MaybeUninit};
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Marks a crash record in `.persist` memory, also changed whenever the layout changes
+const MAGIC: u32 = 0xc4a5_0001;
+
+/// `flags` bit: the registers were stacked by an exception
+const HAS_FRAME: u32 = 1 << 0;
+/// `flags` bit: the crash is a fault, not a panic
+const IS_FAULT: u32 = 1 << 1;
+/// `flags` bit: the crash has a location
+const HAS_LOCATION: u32 = 1 << 2;
+
+#[link_section = ".persist.alarmo.crash"]
+static mut RECORD: MaybeUninit<CrashRecord> = MaybeUninit::uninit();
+
+/// Set by [`init`] if the record was left by the previous boot
+static VALID: AtomicBool = AtomicBool::new(false);
+
+/// Information about a panic or fault, see the [module documentation](self).
+///
+/// All fields are plain integers, so any bit pattern found in memory is a valid value. The
+/// layout has no padding, so the checksum covers every byte after it.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct CrashRecord {
+    magic: u32,
+    checksum: u32,
+    flags: u32,
+    uptime_ticks: u32,
+    line: u32,
+    message_len: u32,
+    file_len: u32,
+    /// R0-R3, R12, LR, PC, xPSR and SP
+    registers: [u32; 9],
+    /// Kind, CFSR, HFSR, MMFAR and BFAR
+    fault: [u32; 5],
+    message: [u8; 256],
+    file: [u8; 64],
+}
+
+const _: () = assert!(size_of::<CrashRecord>() == 7 * 4 + 9 * 4 + 5 * 4 + 256 + 64);
+
+/// Core registers at the time of a crash.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Registers {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+impl CrashRecord {
+    /// Returns the panic message, or the fault description. It may be truncated.
+    pub fn message(&self) -> &str {
+        text(&self.message, self.message_len)
+    }
+
+    /// Returns the source file and line of the panic.
+    pub fn location(&self) -> Option<(&str, u32)> {
+        if self.flags & HAS_LOCATION == 0 {
+            return None;
+        }
+        Some((text(&self.file, self.file_len), self.line))
+    }
+
+    /// Returns the uptime at the time of the crash.
+    pub fn uptime(&self) -> Instant {
+        Instant::from_ticks(self.uptime_ticks)
+    }
+
+    /// Returns the registers stacked by the exception that led to the crash, if any.
+    pub fn registers(&self) -> Option<Registers> {
+        if self.flags & HAS_FRAME == 0 {
+            return None;
+        }
+        let [r0, r1, r2, r3, r12, lr, pc, xpsr, _] = self.registers;
+        Some(Registers {
+            r0,
+            r1,
+            r2,
+            r3,
+            r12,
+            lr,
+            pc,
+            xpsr,
+        })
+    }
+
+    /// Returns the stack pointer at the time of the crash.
+    pub fn sp(&self) -> u32 {
+        self.registers[8]
+    }
+
+    /// Returns the decoded fault, if the crash was caused by one.
+    #[cfg(feature = "fault-handlers")]
+    pub fn fault(&self) -> Option<super::fault::FaultInfo> {
+        if self.flags & IS_FAULT == 0 {
+            return None;
+        }
+        let [kind, cfsr, hfsr, mmfar, bfar] = self.fault;
+        Some(super::fault::FaultInfo {
+            kind: super::fault::FaultKind::from_u32(kind)?,
+            cfsr,
+            hfsr,
+            mmfar,
+            bfar,
+            pc: self.registers[6],
+            lr: self.registers[5],
+        })
+    }
+
+    /// Whether the crash was caused by a fault rather than a panic.
+    pub fn is_fault(&self) -> bool {
+        self.flags & IS_FAULT != 0
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        // Safety: the record has no padding
+        let bytes = unsafe {
+            core::slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>())
+        };
+        crc32(&bytes[8..])
+    }
+}
+
+impl fmt::Display for CrashRecord {
+    /// Formats the record as a multi-line report.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.is_fault() { "fault" } else { "panic" };
+        writeln!(
+            f,
+            "{kind} after {} ms: {}",
+            self.uptime().as_millis(),
+            self.message()
+        )?;
+        if let Some((file, line)) = self.location() {
+            writeln!(f, "at {file}:{line}")?;
+        }
+        if let Some(r) = self.registers() {
+            writeln!(
+                f,
+                "r0={:08x} r1={:08x} r2={:08x} r3={:08x} r12={:08x}",
+                r.r0, r.r1, r.r2, r.r3, r.r12
+            )?;
+            writeln!(f, "lr={:08x} pc={:08x} xpsr={:08x}", r.lr, r.pc, r.xpsr)?;
+        }
+        writeln!(f, "sp={:08x}", self.sp())
+    }
+}
+
+/// Returns the crash that caused the last reset, if the device was reset after a panic or a
+/// fault without losing power.
+///
+/// Only available after [`Alarmo::init`](crate::Alarmo::init).
+pub fn last_crash() -> Option<CrashRecord> {
+    if !VALID.load(Ordering::Relaxed) {
+        return None;
+    }
+    // Safety: the record is valid, and it's only written again by the panic handler
+    Some(unsafe { addr_of!(RECORD).cast::<CrashRecord>().read_volatile() })
+}
+
+/// Checks for a record from the previous boot, and invalidates it in memory, so it's not
+/// reported again after the next reset.
+pub(crate) fn init() {
+    // Safety: called once, before the panic handler can write the record. All fields are
+    // plain integers.
+    unsafe {
+        let record = core::ptr::addr_of_mut!(RECORD).cast::<CrashRecord>();
+        let stored = record.read_volatile();
+        let valid = stored.magic == MAGIC && stored.checksum == stored.compute_checksum();
+        VALID.store(valid, Ordering::Relaxed);
+        (*record).magic = 0;
+    }
+    // Otherwise the old record may be found again after the next reset
+    clean_record();
+}
+
+/// Saves the record for a panic or fault, and returns it.
+pub(super) fn save(data: &PanicData) -> CrashRecord {
+    let mut flags = 0;
+    let mut registers = [0u32; 9];
+    if let Some(frame) = data.frame {
+        flags |= HAS_FRAME;
+        registers[..8].copy_from_slice(&[
+            frame.r0(),
+            frame.r1(),
+            frame.r2(),
+            frame.r3(),
+            frame.r12(),
+            frame.lr(),
+            frame.pc(),
+            frame.xpsr(),
+        ]);
+    }
+    registers[8] = data.sp;
+    if data.line != u32::MAX {
+        flags |= HAS_LOCATION;
+    }
+
+    #[cfg(feature = "fault-handlers")]
+    let fault = data.fault.map_or([0; 5], |info| {
+        flags |= IS_FAULT;
+        [
+            info.kind as u32,
+            info.cfsr,
+            info.hfsr,
+            info.mmfar,
+            info.bfar,
+        ]
+    });
+    #[cfg(not(feature = "fault-handlers"))]
+    let fault = [0; 5];
+
+    let message = data.message.as_str().as_bytes();
+    let file = data.file.as_str().as_bytes();
+    let mut message_buf = [0u8; 256];
+    message_buf[..message.len()].copy_from_slice(message);
+    let mut file_buf = [0u8; 64];
+    file_buf[..file.len()].copy_from_slice(file);
+
+    let mut record = CrashRecord {
+        magic: MAGIC,
+        checksum: 0,
+        flags,
+        uptime_ticks: data.uptime.ticks(),
+        line: data.line,
+        message_len: message.len() as u32,
+        file_len: file.len() as u32,
+        registers,
+        fault,
+        message: message_buf,
+        file: file_buf,
+    };
+    record.checksum = record.compute_checksum();
+    // Safety: the panic handler doesn't return, so nothing reads the record concurrently
+    unsafe {
+        core::ptr::addr_of_mut!(RECORD)
+            .cast::<CrashRecord>()
+            .write_volatile(record);
+    }
+    clean_record();
+    record
+}
+
+/// Writes the record from the D-cache to RAM, since a reset discards the cache.
+fn clean_record() {
+    // Host unit tests have no cache to clean
+    #[cfg(not(test))]
+    // Safety: cleaning only writes cached data back, and doesn't use the SCB otherwise
+    unsafe {
+        cortex_m::asm::dsb();
+        cortex_m::Peripherals::steal()
+            .SCB
+            .clean_dcache_by_address(addr_of!(RECORD) as usize, size_of::<CrashRecord>());
+        cortex_m::asm::dsb();
+    }
+}
+
+/// Reads text that may have been truncated in the middle of a character.
+fn text(buf: &[u8], len: u32) -> &str {
+    let buf = &buf[..(len as usize).min(buf.len())];
+    match core::str::from_utf8(buf) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&buf[..e.valid_up_to()]).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str) -> CrashRecord {
+        let mut record = CrashRecord {
+            magic: MAGIC,
+            checksum: 0,
+            flags: HAS_LOCATION,
+            uptime_ticks: 1234,
+            line: 42,
+            message_len: message.len() as u32,
+            file_len: 6,
+            registers: [0; 9],
+            fault: [0; 5],
+            message: [0; 256],
+            file: [0; 64],
+        };
+        record.message[..message.len()].copy_from_slice(message.as_bytes());
+        record.file[..6].copy_from_slice(b"lib.rs");
+        record.checksum = record.compute_checksum();
+        record
+    }
+
+    /// Stores `record` as if it had been left by the previous boot, then calls [`init`].
+    fn boot_with(record: CrashRecord) -> Option<CrashRecord> {
+        unsafe {
+            core::ptr::addr_of_mut!(RECORD)
+                .cast::<CrashRecord>()
+                .write(record)
+        };
+        init();
+        last_crash()
+    }
+
+    #[test]
+    fn checksum() {
+        let good = record("oops");
+        assert_eq!(good.checksum, good.compute_checksum());
+
+        let mut bad = good;
+        bad.message[1] ^= 0x20;
+        assert_ne!(bad.checksum, bad.compute_checksum());
+        let mut bad = good;
+        bad.line += 1;
+        assert_ne!(bad.checksum, bad.compute_checksum());
+        // The magic is not covered
+        let mut other = good;
+        other.magic = 0;
+        assert_eq!(other.checksum, other.compute_checksum());
+    }
+
+    #[test]
+    fn previous_boot() {
+        // A single test, since the record is global
+        let crash = boot_with(record("oops")).unwrap();
+        assert_eq!(crash.message(), "oops");
+        assert_eq!(crash.location(), Some(("lib.rs", 42)));
+        assert_eq!(crash.uptime(), Instant::from_ticks(1234));
+        assert!(!crash.is_fault());
+        assert_eq!(crash.registers(), None);
+
+        // Reported once only
+        init();
+        assert!(last_crash().is_none());
+
+        let mut bad_magic = record("oops");
+        bad_magic.magic ^= 1;
+        assert!(boot_with(bad_magic).is_none());
+
+        let mut bad_checksum = record("oops");
+        bad_checksum.checksum ^= 1;
+        assert!(boot_with(bad_checksum).is_none());
+
+        let mut corrupted = record("oops");
+        corrupted.message[0] = b'O';
+        assert!(boot_with(corrupted).is_none());
+
+        assert!(boot_with(record("again")).is_some());
+    }
+
+    #[test]
+    fn text_boundary() {
+        let bytes = "añb".as_bytes();
+        assert_eq!(text(bytes, 4), "añb");
+        // Cut in the middle of "ñ"
+        assert_eq!(text(bytes, 2), "a");
+        assert_eq!(text(bytes, 3), "añ");
+        // Lengths past the buffer are clamped
+        assert_eq!(text(bytes, 100), "añb");
+        assert_eq!(text(bytes, 0), "");
+        assert_eq!(text(&[0xff, b'a'], 2), "");
+    }
+}
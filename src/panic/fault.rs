@@ -0,0 +1,225 @@
+//! Fault handlers that show the fault on the panic screen (feature `fault-handlers`).
+//!
+//! The handlers replace the default ones, which just hang. They decode the fault status
+//! registers, show the reason and the stacked registers on the LCD like a panic, and save a
+//! crash record for the next boot, see [`CrashRecord::fault`](super::crash::CrashRecord::fault).
+//!
+//! Since this feature defines the `HardFault`, `MemoryManagement`, `BusFault` and `UsageFault`
+//! handlers, applications can't define their own.
+//!
+//! The configurable faults are enabled by [`Alarmo::init`](crate::Alarmo::init), until then
+//! they escalate to `HardFault`.
+
+use super::{show_panic, PanicData};
+use core::fmt::{self, Write};
+use cortex_m::peripheral::scb::Exception;
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::{exception, ExceptionFrame};
+
+// Configurable Fault Status Register bits
+const IACCVIOL: u32 = 1 << 0;
+const DACCVIOL: u32 = 1 << 1;
+const MUNSTKERR: u32 = 1 << 3;
+const MSTKERR: u32 = 1 << 4;
+const MLSPERR: u32 = 1 << 5;
+const MMARVALID: u32 = 1 << 7;
+const IBUSERR: u32 = 1 << 8;
+const PRECISERR: u32 = 1 << 9;
+const IMPRECISERR: u32 = 1 << 10;
+const UNSTKERR: u32 = 1 << 11;
+const STKERR: u32 = 1 << 12;
+const LSPERR: u32 = 1 << 13;
+const BFARVALID: u32 = 1 << 15;
+const UNDEFINSTR: u32 = 1 << 16;
+const INVSTATE: u32 = 1 << 17;
+const INVPC: u32 = 1 << 18;
+const NOCP: u32 = 1 << 19;
+const UNALIGNED: u32 = 1 << 24;
+const DIVBYZERO: u32 = 1 << 25;
+
+// HardFault Status Register bits
+const VECTTBL: u32 = 1 << 1;
+const FORCED: u32 = 1 << 30;
+const DEBUGEVT: u32 = 1 << 31;
+
+/// Descriptions of the CFSR bits, most specific first
+const CFSR_REASONS: [(u32, &str); 17] = [
+    (UNDEFINSTR, "undefined instruction"),
+    (INVSTATE, "invalid state (non-Thumb branch target)"),
+    (INVPC, "invalid exception return"),
+    (NOCP, "coprocessor disabled"),
+    (UNALIGNED, "unaligned access"),
+    (DIVBYZERO, "division by zero"),
+    (IBUSERR, "instruction bus error"),
+    (PRECISERR, "precise data bus error"),
+    (IMPRECISERR, "imprecise data bus error"),
+    (UNSTKERR, "bus error on exception return"),
+    (STKERR, "bus error on exception entry"),
+    (LSPERR, "bus error on FPU state preservation"),
+    (IACCVIOL, "instruction access violation"),
+    (DACCVIOL, "data access violation"),
+    (MUNSTKERR, "MPU violation on exception return"),
+    (MSTKERR, "MPU violation on exception entry"),
+    (MLSPERR, "MPU violation on FPU state preservation"),
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FaultKind {
+    HardFault,
+    MemoryManagement,
+    BusFault,
+    UsageFault,
+}
+
+/// A decoded fault.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FaultInfo {
+    pub kind: FaultKind,
+    /// Configurable Fault Status Register
+    pub cfsr: u32,
+    /// HardFault Status Register
+    pub hfsr: u32,
+    /// MemManage Fault Address Register, only valid if flagged in `cfsr`
+    pub mmfar: u32,
+    /// BusFault Address Register, only valid if flagged in `cfsr`
+    pub bfar: u32,
+    /// Address of the faulting instruction (for precise faults)
+    pub pc: u32,
+    /// Link register of the faulting code
+    pub lr: u32,
+}
+
+impl FaultKind {
+    pub(super) fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(FaultKind::HardFault),
+            1 => Some(FaultKind::MemoryManagement),
+            2 => Some(FaultKind::BusFault),
+            3 => Some(FaultKind::UsageFault),
+            _ => None,
+        }
+    }
+}
+
+impl FaultInfo {
+    /// Reads the fault status registers.
+    fn read(kind: FaultKind, frame: &ExceptionFrame) -> Self {
+        // Safety: reads only
+        let scb = unsafe { &*SCB::PTR };
+        Self {
+            kind,
+            cfsr: scb.cfsr.read(),
+            hfsr: scb.hfsr.read(),
+            mmfar: scb.mmfar.read(),
+            bfar: scb.bfar.read(),
+            pc: frame.pc(),
+            lr: frame.lr(),
+        }
+    }
+
+    /// Returns a short description of the cause of the fault.
+    pub fn reason(&self) -> &'static str {
+        if let Some((_, reason)) = CFSR_REASONS.iter().find(|(bit, _)| self.cfsr & bit != 0) {
+            return reason;
+        }
+        if self.hfsr & VECTTBL != 0 {
+            "vector table read error"
+        } else if self.hfsr & DEBUGEVT != 0 {
+            "debug event"
+        } else {
+            "unknown"
+        }
+    }
+
+    /// Returns the data address that caused the fault, if known.
+    pub fn fault_address(&self) -> Option<u32> {
+        if self.cfsr & BFARVALID != 0 {
+            Some(self.bfar)
+        } else if self.cfsr & MMARVALID != 0 {
+            Some(self.mmfar)
+        } else {
+            None
+        }
+    }
+
+    /// Whether a configurable fault escalated to a HardFault, because it was disabled or
+    /// happened in a fault handler.
+    pub fn is_escalated(&self) -> bool {
+        self.hfsr & FORCED != 0
+    }
+}
+
+impl fmt::Display for FaultInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.reason())?;
+        if let Some(address) = self.fault_address() {
+            write!(f, " at {address:#010x}")?;
+        }
+        if self.is_escalated() {
+            f.write_str(" (escalated)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Enables the MemManage, BusFault and UsageFault exceptions, so they don't escalate to
+/// HardFault.
+pub(crate) fn enable(scb: &mut SCB) {
+    scb.enable(Exception::MemoryManagement);
+    scb.enable(Exception::BusFault);
+    scb.enable(Exception::UsageFault);
+}
+
+unsafe fn handle_fault(kind: FaultKind, frame: &ExceptionFrame) -> ! {
+    let fault = FaultInfo::read(kind, frame);
+
+    // The stack of the faulting code starts at the exception frame
+    let mut data = PanicData::capture(frame as *const ExceptionFrame as u32, Some(*frame));
+    data.title = "!! FAULT !!";
+    data.fault = Some(fault);
+    write!(data.message, "{fault}").ok();
+    show_panic(data)
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    handle_fault(FaultKind::HardFault, frame)
+}
+
+extern "C" fn memory_management(frame: &ExceptionFrame) -> ! {
+    unsafe { handle_fault(FaultKind::MemoryManagement, frame) }
+}
+
+extern "C" fn bus_fault(frame: &ExceptionFrame) -> ! {
+    unsafe { handle_fault(FaultKind::BusFault, frame) }
+}
+
+extern "C" fn usage_fault(frame: &ExceptionFrame) -> ! {
+    unsafe { handle_fault(FaultKind::UsageFault, frame) }
+}
+
+// Only HardFault gets the exception frame from cortex-m-rt, so these trampolines pass the
+// frame from the active stack to the handlers, like cortex-m-rt's `HardFaultTrampoline`
+macro_rules! fault_trampoline {
+    ($name:literal, $handler:ident) => {
+        core::arch::global_asm!(
+            concat!(".section .text.", $name, ",\"ax\",%progbits"),
+            concat!(".global ", $name),
+            concat!(".type ", $name, ",%function"),
+            ".thumb_func",
+            concat!($name, ":"),
+            "tst lr, #4",
+            "ite eq",
+            "mrseq r0, msp",
+            "mrsne r0, psp",
+            "ldr r1, ={handler}",
+            "bx r1",
+            ".ltorg",
+            handler = sym $handler,
+        );
+    };
+}
+
+fault_trampoline!("MemoryManagement", memory_management);
+fault_trampoline!("BusFault", bus_fault);
+fault_trampoline!("UsageFault", usage_fault);
@@ -0,0 +1,533 @@
+//! Provides a panic handler that displays the panic message on the Alarmo's display
+//!
+//! Besides the message and its location, the panic screen shows:
+//! - the registers stacked by the exception that led to the panic, if it was recorded with
+//!   [`set_exception_frame`], or the stack pointer otherwise,
+//! - a best-effort backtrace of return addresses (see the `backtrace` module source for
+//!   its limitations), which can be resolved with `addr2line -e <elf>`,
+//! - heap usage, when the `alloc` feature is enabled.
+//!
+//! The Mail button switches to a QR code of the crash report, for bug reports from devices that
+//! aren't connected to anything. It holds the build id (a checksum of the code, to pick the
+//! matching ELF), the location, PC, LR, SP, the backtrace and the message, one per line.
+//!
+//! The device reboots when the Back button is pressed. The crash is saved in memory that
+//! survives the reset, see [`crash::last_crash`].
+//!
+//! With the `fault-handlers` feature, faults are shown on the same screen, see [`fault`]. With
+//! the `panic-usb` feature, the crash report is also sent over USB CDC serial, whenever the host
+//! opens the port.
+
+mod backtrace;
+pub mod crash;
+#[cfg(feature = "fault-handlers")]
+pub mod fault;
+mod qr;
+#[cfg(feature = "panic-usb")]
+mod usb;
+
+use crate::clock::{self, Instant};
+use crate::delay::HalDelay;
+use crate::{display, fmc, pac};
+use backtrace::Backtrace;
+use core::cell::{Cell, RefCell};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::ExceptionFrame;
+use embedded_graphics::geometry::Point;
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::{Dimensions, DrawTarget, RgbColor, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Alignment, Text};
+use embedded_graphics::Drawable;
+use embedded_hal::delay::DelayNs;
+use stm32h7xx_hal::delay::Delay;
+use stm32h7xx_hal::gpio::GpioExt;
+use stm32h7xx_hal::prelude::{_stm32h7xx_hal_pwr_PwrExt, _stm32h7xx_hal_rcc_RccExt};
+use stm32h7xx_hal::rcc::ResetEnable;
+use stm32h7xx_hal::stm32::Peripherals;
+
+/// Height of a line of text on the panic screen
+const LINE_HEIGHT: i32 = 12;
+const MARGIN: i32 = 15;
+/// Maximum length of the QR code contents, which fits a version 15 code with low redundancy
+const QR_PAYLOAD_LEN: usize = 512;
+
+static EXCEPTION_FRAME: Mutex<Cell<Option<ExceptionFrame>>> = Mutex::new(Cell::new(None));
+
+/// Records the registers stacked by an exception, to be shown on the panic screen.
+///
+/// Call this from exception handlers before panicking:
+/// ```no_run
+/// # use cortex_m_rt::exception;
+/// #[exception]
+/// unsafe fn HardFault(ef: &cortex_m_rt::ExceptionFrame) -> ! {
+///     alarmo::panic::set_exception_frame(ef);
+///     panic!("HardFault");
+/// }
+/// ```
+pub fn set_exception_frame(frame: &ExceptionFrame) {
+    cortex_m::interrupt::free(|cs| EXCEPTION_FRAME.borrow(cs).set(Some(*frame)));
+}
+
+// No inline to allow for debugging. Host unit tests use the standard library's handler.
+#[inline(never)]
+#[cfg_attr(not(test), panic_handler)]
+#[cfg_attr(test, allow(dead_code))]
+unsafe fn panic(info: &PanicInfo) -> ! {
+    handle_panic(info)
+}
+
+struct PanicData {
+    title: &'static str,
+    message: TextBuf<256>,
+    file: TextBuf<64>,
+    line: u32,
+    /// Registers stacked by the exception that caused the panic, if any
+    frame: Option<ExceptionFrame>,
+    /// Stack pointer when the panic handler was entered
+    sp: u32,
+    backtrace: Backtrace,
+    uptime: Instant,
+    #[cfg(feature = "fault-handlers")]
+    fault: Option<fault::FaultInfo>,
+    /// Used and free heap bytes
+    #[cfg(feature = "alloc")]
+    heap: (usize, usize),
+}
+
+impl PanicData {
+    /// Captures the state of the system, with an empty message and no location.
+    unsafe fn capture(sp: u32, frame: Option<ExceptionFrame>) -> Self {
+        Self {
+            title: "!! PANIC !!",
+            message: TextBuf::new(),
+            file: TextBuf::new(),
+            line: u32::MAX,
+            frame,
+            sp,
+            backtrace: Backtrace::capture(sp),
+            uptime: clock::now(),
+            #[cfg(feature = "fault-handlers")]
+            fault: None,
+            #[cfg(feature = "alloc")]
+            heap: crate::e_alloc::stats(),
+        }
+    }
+}
+
+unsafe fn handle_panic(info: &PanicInfo) -> ! {
+    // Capture the state first, before it's overwritten by the panic screen
+    let frame = cortex_m::interrupt::free(|cs| EXCEPTION_FRAME.borrow(cs).get());
+    let mut panic_data = PanicData::capture(cortex_m::register::msp::read(), frame);
+
+    write!(panic_data.message, "{}", info.message()).ok();
+    if let Some(location) = info.location() {
+        panic_data.file.write_str(location.file()).ok();
+        panic_data.line = location.line();
+    }
+
+    show_panic(panic_data)
+}
+
+unsafe fn show_panic(data: PanicData) -> ! {
+    // Take full control and prepare a barebones view of the panic message
+    cortex_m::interrupt::disable();
+
+    // Save the crash first, in case showing it fails
+    #[cfg_attr(not(feature = "panic-usb"), allow(unused_variables))]
+    let record = crash::save(&data);
+
+    let cortex = cortex_m::Peripherals::steal();
+    let peripherals = Peripherals::steal();
+
+    let pwr = peripherals.PWR.constrain();
+    let pwr_cfg = pwr.freeze();
+    let rcc = peripherals.RCC.constrain();
+    #[allow(unused_mut)]
+    let mut ccdr = rcc.freeze(pwr_cfg, &peripherals.SYSCFG);
+
+    // 48MHz clock for USB1, like in `Alarmo::init`
+    #[cfg(feature = "panic-usb")]
+    ccdr.peripheral
+        .kernel_usb_clk_mux(stm32h7xx_hal::rcc::rec::UsbClkSel::Hsi48);
+
+    // Split GPIO - only the ones needed for display, the buttons and USB
+    #[cfg(feature = "panic-usb")]
+    let gpioa = peripherals.GPIOA.split(ccdr.peripheral.GPIOA);
+    let gpiob = peripherals.GPIOB.split(ccdr.peripheral.GPIOB);
+    let gpioc = peripherals.GPIOC.split(ccdr.peripheral.GPIOC);
+    let gpiog = peripherals.GPIOG.split_without_reset(ccdr.peripheral.GPIOG);
+
+    // Split timers
+    let (disp_timer, _) = pac::timers::display_timer(
+        &ccdr.clocks,
+        peripherals.TIM3,
+        gpiob.pb1,
+        gpioc.pc8,
+        ccdr.peripheral.TIM3,
+    );
+
+    // Init FMC clocks and SRAM
+    let disp_pin =
+        unsafe { pac::sram::init(peripherals.FMC, gpioc.pc7, &fmc::FmcTimings::DEFAULT_PER_CK) };
+    ccdr.peripheral
+        .FMC
+        .kernel_clk_mux(stm32h7xx_hal::pac::rcc::d1ccipr::FMCSEL_A::Per)
+        .enable();
+
+    // Access is safe because we are the only accessor at this point
+    crate::DELAY = Some(RefCell::new(Delay::new(cortex.SYST, ccdr.clocks)));
+
+    let disp = display::AlarmoDisplay::new(
+        disp_timer,
+        disp_pin,
+        gpiog.pg4,
+        crate::DELAY.as_ref().unwrap(),
+    );
+    let mut disp = display::AlarmoScreen::new(disp);
+    disp.display_mut().set_backlight(1.0);
+
+    // Encode the report for the QR code view, with more redundancy if it fits
+    let mut payload = TextBuf::<QR_PAYLOAD_LEN>::new();
+    write_qr_payload(&mut payload, &data).ok();
+    let payload = payload.as_str().as_bytes();
+    let qr = qr::QrCode::encode(payload, qr::Ecc::Medium)
+        .or_else(|| qr::QrCode::encode(payload, qr::Ecc::Low));
+
+    draw_report(&mut disp, &data, qr.is_some());
+
+    #[cfg(feature = "panic-usb")]
+    let usb_bus = usb::bus(pac::usb::split_usb(
+        gpioa.pa11,
+        gpioa.pa12,
+        peripherals.OTG1_HS_GLOBAL,
+        peripherals.OTG1_HS_DEVICE,
+        peripherals.OTG1_HS_PWRCLK,
+        ccdr.peripheral.USB1OTG,
+        &ccdr.clocks,
+    ));
+    #[cfg(feature = "panic-usb")]
+    let mut serial = usb::PanicSerial::new(&usb_bus);
+
+    // Keep USB going while waiting for the buttons
+    #[cfg_attr(not(feature = "panic-usb"), allow(unused_mut))]
+    let mut idle = || {
+        for _ in 0..10 {
+            #[cfg(feature = "panic-usb")]
+            serial.poll(&record, &data.backtrace);
+            HalDelay.delay_ms(1);
+        }
+    };
+
+    // Reboot on Back and switch between the report and the QR code on Mail. Buttons held when
+    // the panic happened only count once they were released.
+    let back = gpiog.pg6.into_pull_up_input();
+    let mail = gpiog.pg5.into_pull_up_input();
+    let mut back_released = false;
+    let mut mail_was_pressed = mail.is_low();
+    let mut showing_qr = false;
+    loop {
+        idle();
+
+        let back_pressed = back.is_low();
+        if back_pressed && back_released {
+            SCB::sys_reset();
+        }
+        back_released |= !back_pressed;
+
+        let mail_pressed = mail.is_low();
+        if mail_pressed && !mail_was_pressed {
+            if let Some(qr) = &qr {
+                showing_qr = !showing_qr;
+                if showing_qr {
+                    draw_qr(&mut disp, qr);
+                } else {
+                    draw_report(&mut disp, &data, true);
+                }
+            }
+        }
+        mail_was_pressed = mail_pressed;
+    }
+}
+
+/// Draws the crash report, see the [module documentation](self).
+fn draw_report<D: DrawTarget<Color = Rgb565>>(disp: &mut D, data: &PanicData, has_qr: bool) {
+    let message = data.message.as_str();
+    let file = data.file.as_str();
+
+    disp.clear(Rgb565::BLUE).ok();
+    let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+
+    // Draw centered title
+    Text::with_alignment(data.title, Point::new(160, 20), style, Alignment::Center)
+        .draw(disp)
+        .ok();
+
+    // Draw panic message, this possibly spans multiple lines, so get the lowest point to properly
+    // position the next items
+    let text_msg = Text::new(message, Point::new(MARGIN, 40), style);
+    let max_y = text_msg
+        .bounding_box()
+        .bottom_right()
+        .map(|p| p.y)
+        .unwrap_or(40);
+    text_msg.draw(disp).ok();
+    let mut y = max_y + LINE_HEIGHT + 4;
+
+    // Draw file name and line, faults have none
+    if data.line != u32::MAX {
+        let mut text = LineBuf::new();
+        write!(text, "at {file}:{}", data.line).ok();
+        draw_line(disp, &mut y, text.as_str());
+        y += 4;
+    }
+
+    // Draw registers
+    match data.frame {
+        Some(frame) => {
+            let registers = [
+                ("R0", frame.r0()),
+                ("R1", frame.r1()),
+                ("R2", frame.r2()),
+                ("R3", frame.r3()),
+                ("R12", frame.r12()),
+                ("LR", frame.lr()),
+                ("PC", frame.pc()),
+                ("xPSR", frame.xpsr()),
+                ("SP", data.sp),
+            ];
+            for chunk in registers.chunks(3) {
+                let mut text = LineBuf::new();
+                for (name, value) in chunk {
+                    write!(text, "{name:>4} {value:08x}  ").ok();
+                }
+                draw_line(disp, &mut y, text.as_str());
+            }
+        }
+        None => {
+            let mut text = LineBuf::new();
+            write!(text, "  SP {:08x}  (no exception frame)", data.sp).ok();
+            draw_line(disp, &mut y, text.as_str());
+        }
+    }
+    y += 4;
+
+    // Draw backtrace
+    draw_line(disp, &mut y, "Backtrace:");
+    for chunk in data.backtrace.frames().chunks(4) {
+        let mut text = LineBuf::new();
+        for address in chunk {
+            write!(text, "  {address:08x}").ok();
+        }
+        draw_line(disp, &mut y, text.as_str());
+    }
+
+    #[cfg(feature = "alloc")]
+    {
+        y += 4;
+        let (used, free) = data.heap;
+        let mut text = LineBuf::new();
+        write!(text, "Heap: {used} bytes used, {free} free").ok();
+        draw_line(disp, &mut y, text.as_str());
+    }
+
+    let prompt = if has_qr {
+        "Back: reboot    Mail: QR code"
+    } else {
+        "Press Back to reboot"
+    };
+    Text::with_alignment(prompt, Point::new(160, 230), style, Alignment::Center)
+        .draw(disp)
+        .ok();
+}
+
+/// Draws the QR code as large as it fits, black on white so phones can scan it.
+fn draw_qr<D: DrawTarget<Color = Rgb565>>(disp: &mut D, qr: &qr::QrCode) {
+    disp.clear(Rgb565::WHITE).ok();
+
+    // Leave room for the prompt, and for the 4 module wide quiet zone around the code
+    let size = qr.size() as i32;
+    let scale = ((240 - 2 * LINE_HEIGHT) / (size + 8)).max(1);
+    let left = (320 - size * scale) / 2;
+    let top = (240 - LINE_HEIGHT - size * scale) / 2;
+    for y in 0..size {
+        for x in 0..size {
+            if qr.get(x, y) {
+                let module = Rectangle::new(
+                    Point::new(left + x * scale, top + y * scale),
+                    Size::new(scale as u32, scale as u32),
+                );
+                disp.fill_solid(&module, Rgb565::BLACK).ok();
+            }
+        }
+    }
+
+    let style = MonoTextStyle::new(&FONT_6X10, Rgb565::BLACK);
+    Text::with_alignment(
+        "Back: reboot    Mail: report",
+        Point::new(160, 230),
+        style,
+        Alignment::Center,
+    )
+    .draw(disp)
+    .ok();
+}
+
+/// Writes the crash report for the QR code, one field per line. The message comes last, so it's
+/// what gets truncated if the report is too long.
+fn write_qr_payload(out: &mut impl Write, data: &PanicData) -> fmt::Result {
+    writeln!(out, "alarmo crash 1")?;
+    writeln!(out, "build {:08x}", backtrace::build_id())?;
+    if data.line != u32::MAX {
+        writeln!(out, "at {}:{}", data.file.as_str(), data.line)?;
+    }
+    if let Some(frame) = data.frame {
+        writeln!(out, "pc {:08x} lr {:08x}", frame.pc(), frame.lr())?;
+    }
+    writeln!(out, "sp {:08x}", data.sp)?;
+    write!(out, "bt")?;
+    for address in data.backtrace.frames() {
+        write!(out, " {address:08x}")?;
+    }
+    writeln!(out)?;
+    write!(out, "msg {}", data.message.as_str())
+}
+
+fn draw_line<D: DrawTarget<Color = Rgb565>>(disp: &mut D, y: &mut i32, text: &str) {
+    let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+    Text::new(text, Point::new(MARGIN, *y), style)
+        .draw(disp)
+        .ok();
+    *y += LINE_HEIGHT;
+}
+
+/// Appended to text that didn't fit in a [`TextBuf`]
+const TRUNCATION_MARKER: &str = "...";
+
+/// Fixed-size text buffer, for formatting without a heap.
+///
+/// Output that doesn't fit is cut at a character boundary and replaced by
+/// [`TRUNCATION_MARKER`], and further writes fail.
+struct TextBuf<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    truncated: bool,
+}
+
+/// Buffer for a line of text on the panic screen
+type LineBuf = TextBuf<64>;
+
+impl<const N: usize> TextBuf<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+}
+
+impl<const N: usize> Write for TextBuf<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Err(fmt::Error);
+        }
+        if s.len() <= N - self.len {
+            self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            return Ok(());
+        }
+
+        // Fill the buffer, then make room for the marker without splitting a character
+        let take = N - self.len;
+        self.buf[self.len..].copy_from_slice(&s.as_bytes()[..take]);
+        let mut end = N - TRUNCATION_MARKER.len();
+        while end > 0 && self.buf[end] & 0xc0 == 0x80 {
+            end -= 1;
+        }
+        self.buf[end..end + TRUNCATION_MARKER.len()].copy_from_slice(TRUNCATION_MARKER.as_bytes());
+        self.len = end + TRUNCATION_MARKER.len();
+        self.truncated = true;
+        Err(fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_fit() {
+        let mut buf = TextBuf::<8>::new();
+        assert!(buf.write_str("abcd").is_ok());
+        assert!(buf.write_str("efgh").is_ok());
+        assert_eq!(buf.as_str(), "abcdefgh");
+        assert!(!buf.truncated);
+        // Empty writes still fit
+        assert!(buf.write_str("").is_ok());
+    }
+
+    #[test]
+    fn one_byte_over() {
+        let mut buf = TextBuf::<8>::new();
+        assert!(buf.write_str("abcdefghi").is_err());
+        assert_eq!(buf.as_str(), "abcde...");
+        assert_eq!(buf.len, 8);
+
+        let mut buf = TextBuf::<8>::new();
+        buf.write_str("abcdefgh").unwrap();
+        assert!(buf.write_str("i").is_err());
+        assert_eq!(buf.as_str(), "abcde...");
+    }
+
+    #[test]
+    fn multi_byte_cut() {
+        // "é" is 2 bytes, and would be split at byte 5
+        let mut buf = TextBuf::<8>::new();
+        assert!(buf.write_str("abcdé fgh").is_err());
+        assert_eq!(buf.as_str(), "abcd...");
+
+        // A 4-byte character over the end
+        let mut buf = TextBuf::<8>::new();
+        assert!(buf.write_str("ab🦀🦀").is_err());
+        assert!(core::str::from_utf8(&buf.buf[..buf.len]).is_ok());
+        assert_eq!(buf.as_str(), "ab...");
+    }
+
+    #[test]
+    fn refuses_after_truncation() {
+        let mut buf = TextBuf::<8>::new();
+        assert!(buf.write_str("0123456789").is_err());
+        assert_eq!(buf.write_str("x"), Err(fmt::Error));
+        assert_eq!(buf.write_str(""), Err(fmt::Error));
+        assert_eq!(buf.as_str(), "01234...");
+    }
+
+    #[test]
+    fn format_arguments() {
+        let mut buf = LineBuf::new();
+        write!(
+            buf,
+            "index {} out of range for {:?} at {:#x}",
+            7,
+            [1, 2],
+            255
+        )
+        .unwrap();
+        assert_eq!(buf.as_str(), "index 7 out of range for [1, 2] at 0xff");
+
+        let mut buf = TextBuf::<16>::new();
+        assert!(write!(buf, "value {} is too long", 123456).is_err());
+        assert_eq!(buf.as_str(), "value 123456 ...");
+        assert_eq!(buf.len, 16);
+    }
+}
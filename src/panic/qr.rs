@@ -0,0 +1,614 @@
+//! Minimal QR code encoder, for the crash report on the panic screen.
+//!
+//! Encodes bytes (byte mode only) into the smallest version up to [`MAX_VERSION`], without a
+//! heap. The mask is chosen with the penalty rules of the QR code specification (ISO/IEC 18004),
+//! so the output matches other conforming encoders.
+
+/// Largest supported version, which determines the size of [`QrCode`]
+pub(crate) const MAX_VERSION: u8 = 15;
+
+const MAX_SIZE: usize = MAX_VERSION as usize * 4 + 17;
+const MAX_MODULES: usize = MAX_SIZE * MAX_SIZE;
+/// Data and error correction codewords of the largest version
+const MAX_CODEWORDS: usize = raw_data_modules(MAX_VERSION) / 8;
+/// Largest number of error correction codewords per block, in any version
+const MAX_BLOCK_ECC: usize = 30;
+
+/// Error correction codewords per block, by level and version
+const ECC_CODEWORDS_PER_BLOCK: [[u8; MAX_VERSION as usize + 1]; 4] = [
+    [0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22],
+    [
+        0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24,
+    ],
+    [
+        0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30,
+    ],
+    [
+        0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24,
+    ],
+];
+
+/// Error correction blocks, by level and version
+const ERROR_CORRECTION_BLOCKS: [[u8; MAX_VERSION as usize + 1]; 4] = [
+    [0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6],
+    [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10],
+    [0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12],
+    [0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18],
+];
+
+// Mask penalty weights
+const PENALTY_N1: i32 = 3;
+const PENALTY_N2: i32 = 3;
+const PENALTY_N3: i32 = 40;
+const PENALTY_N4: i32 = 10;
+
+/// Error correction level, from the lowest redundancy (about 7% of the code can be damaged) to
+/// the highest (about 30%).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[allow(dead_code)] // The panic screen only uses the two lowest levels
+pub(crate) enum Ecc {
+    Low = 0,
+    Medium = 1,
+    Quartile = 2,
+    High = 3,
+}
+
+impl Ecc {
+    fn format_bits(self) -> u32 {
+        match self {
+            Ecc::Low => 1,
+            Ecc::Medium => 0,
+            Ecc::Quartile => 3,
+            Ecc::High => 2,
+        }
+    }
+}
+
+/// A QR code, as a square grid of dark and light modules.
+pub(crate) struct QrCode {
+    version: u8,
+    size: usize,
+    modules: Bits,
+    /// Modules of function patterns, which are not masked
+    function: Bits,
+}
+
+/// Bitmap of the largest version's modules, indexed by `y * size + x`
+struct Bits([u8; MAX_MODULES.div_ceil(8)]);
+
+impl Bits {
+    fn get(&self, index: usize) -> bool {
+        self.0[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn set(&mut self, index: usize, value: bool) {
+        if value {
+            self.0[index / 8] |= 1 << (index % 8);
+        } else {
+            self.0[index / 8] &= !(1 << (index % 8));
+        }
+    }
+}
+
+impl QrCode {
+    /// Encodes `data` in the smallest version that fits, or returns `None` if it needs more
+    /// than [`MAX_VERSION`].
+    pub(crate) fn encode(data: &[u8], ecc: Ecc) -> Option<Self> {
+        let version = (1..=MAX_VERSION).find(|&version| {
+            let count_bits = if version <= 9 { 8 } else { 16 };
+            4 + count_bits + data.len() * 8 <= data_codewords(version, ecc) * 8
+        })?;
+
+        let mut qr = Self {
+            version,
+            size: version as usize * 4 + 17,
+            modules: Bits([0; MAX_MODULES.div_ceil(8)]),
+            function: Bits([0; MAX_MODULES.div_ceil(8)]),
+        };
+        let mut codewords = [0u8; MAX_CODEWORDS];
+        let len = qr.codewords(data, ecc, &mut codewords);
+        qr.draw_function_patterns();
+        qr.draw_codewords(&codewords[..len]);
+
+        // Use the mask with the lowest penalty. Masks are undone by applying them again.
+        let mut best = (0, i32::MAX);
+        for mask in 0..8 {
+            qr.apply_mask(mask);
+            qr.draw_format_bits(ecc, mask);
+            let penalty = qr.penalty();
+            if penalty < best.1 {
+                best = (mask, penalty);
+            }
+            qr.apply_mask(mask);
+        }
+        qr.apply_mask(best.0);
+        qr.draw_format_bits(ecc, best.0);
+        Some(qr)
+    }
+
+    /// Returns the width and height, in modules.
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns whether the module at (`x`, `y`) is dark. Out of range modules are light, like the
+    /// quiet zone around the code.
+    pub(crate) fn get(&self, x: i32, y: i32) -> bool {
+        let size = self.size as i32;
+        (0..size).contains(&x) && (0..size).contains(&y) && self.module(x as usize, y as usize)
+    }
+
+    fn module(&self, x: usize, y: usize) -> bool {
+        self.modules.get(y * self.size + x)
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules.set(y * self.size + x, dark);
+        self.function.set(y * self.size + x, true);
+    }
+
+    /// Builds the data codewords and interleaves them with the error correction codewords.
+    /// Returns the number of codewords.
+    fn codewords(&self, data: &[u8], ecc: Ecc, out: &mut [u8; MAX_CODEWORDS]) -> usize {
+        let capacity = data_codewords(self.version, ecc);
+
+        // Byte mode segment, terminator and padding
+        let mut bits = BitWriter {
+            buf: [0; MAX_CODEWORDS],
+            len: 0,
+        };
+        bits.push(0b0100, 4);
+        bits.push(data.len() as u32, if self.version <= 9 { 8 } else { 16 });
+        for &byte in data {
+            bits.push(byte as u32, 8);
+        }
+        bits.push(0, (capacity * 8 - bits.len).min(4));
+        bits.push(0, (8 - bits.len % 8) % 8);
+        for pad in [0xec, 0x11].into_iter().cycle() {
+            if bits.len >= capacity * 8 {
+                break;
+            }
+            bits.push(pad, 8);
+        }
+        let data = &bits.buf[..capacity];
+
+        let num_blocks = ERROR_CORRECTION_BLOCKS[ecc as usize][self.version as usize] as usize;
+        let block_ecc = ECC_CODEWORDS_PER_BLOCK[ecc as usize][self.version as usize] as usize;
+        let raw_codewords = raw_data_modules(self.version) / 8;
+        let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+        let short_block_data = raw_codewords / num_blocks - block_ecc;
+        let divisor = reed_solomon_divisor(block_ecc);
+
+        let mut start = 0;
+        for block in 0..num_blocks {
+            let len = short_block_data + usize::from(block >= num_short_blocks);
+            let block_data = &data[start..start + len];
+            start += len;
+
+            // Data codewords are interleaved first, the extra codeword of long blocks last
+            for (i, &byte) in block_data.iter().enumerate() {
+                let index = if i < short_block_data {
+                    i * num_blocks + block
+                } else {
+                    short_block_data * num_blocks + block - num_short_blocks
+                };
+                out[index] = byte;
+            }
+            let remainder = reed_solomon_remainder(block_data, &divisor[..block_ecc]);
+            for (i, &byte) in remainder[..block_ecc].iter().enumerate() {
+                out[capacity + i * num_blocks + block] = byte;
+            }
+        }
+        raw_codewords
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let size = self.size;
+
+        // Timing patterns
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        // Finder patterns, with their separators
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                    if (0..size as i32).contains(&x) && (0..size as i32).contains(&y) {
+                        let distance = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+
+        // Alignment patterns, except where they would overlap the finder patterns
+        let (positions, count) = alignment_positions(self.version);
+        for i in 0..count {
+            for j in 0..count {
+                // Skip the corners with finder patterns
+                if (i == 0 && (j == 0 || j == count - 1)) || (i == count - 1 && j == 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let x = (positions[i] as i32 + dx) as usize;
+                        let y = (positions[j] as i32 + dy) as usize;
+                        self.set_function(x, y, dx.abs().max(dy.abs()) != 1);
+                    }
+                }
+            }
+        }
+
+        // Reserve the format bits, they are drawn for each mask
+        self.draw_format_bits(Ecc::Low, 0);
+
+        // Version information
+        if self.version >= 7 {
+            let version = self.version as u32;
+            let mut remainder = version;
+            for _ in 0..12 {
+                remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+            }
+            let bits = version << 12 | remainder;
+            for i in 0..18 {
+                let dark = bits >> i & 1 != 0;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, ecc: Ecc, mask: u8) {
+        let data = ecc.format_bits() << 3 | mask as u32;
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| bits >> i & 1 != 0;
+        let size = self.size;
+
+        // First copy, around the top left finder pattern
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        // Second copy, split between the other finder patterns
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        // Always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Draws the codewords in the zigzag pattern, skipping function modules.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut bit = 0;
+        let mut right = size - 1;
+        while right >= 1 {
+            // Skip the vertical timing pattern
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward {
+                    size - 1 - vertical
+                } else {
+                    vertical
+                };
+                for x in [right, right - 1] {
+                    if !self.function.get(y * size + x) && bit < codewords.len() * 8 {
+                        let dark = codewords[bit / 8] >> (7 - bit % 8) & 1 != 0;
+                        self.modules.set(y * size + x, dark);
+                        bit += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// Inverts the data modules selected by `mask`.
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                if invert && !self.function.get(index) {
+                    self.modules.set(index, !self.modules.get(index));
+                }
+            }
+        }
+    }
+
+    /// Computes the penalty score of the current mask.
+    fn penalty(&self) -> i32 {
+        let size = self.size;
+        let mut result = 0;
+
+        // Runs of the same color and finder-like patterns, in rows then columns
+        for transpose in [false, true] {
+            for a in 0..size {
+                let mut run_color = false;
+                let mut run = 0;
+                let mut history = RunHistory::new(size as i32);
+                for b in 0..size {
+                    let color = if transpose {
+                        self.module(a, b)
+                    } else {
+                        self.module(b, a)
+                    };
+                    if color == run_color {
+                        run += 1;
+                        if run == 5 {
+                            result += PENALTY_N1;
+                        } else if run > 5 {
+                            result += 1;
+                        }
+                    } else {
+                        history.push(run);
+                        if !run_color {
+                            result += history.count_patterns() * PENALTY_N3;
+                        }
+                        run_color = color;
+                        run = 1;
+                    }
+                }
+                result += history.terminate_and_count(run_color, run) * PENALTY_N3;
+            }
+        }
+
+        // 2x2 blocks of the same color
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.module(x, y);
+                if color == self.module(x + 1, y)
+                    && color == self.module(x, y + 1)
+                    && color == self.module(x + 1, y + 1)
+                {
+                    result += PENALTY_N2;
+                }
+            }
+        }
+
+        // Balance of dark and light modules: smallest k such that the dark ratio is within
+        // (45 - 5k)% and (55 + 5k)%
+        let total = (size * size) as i32;
+        let dark = (0..size * size).filter(|&i| self.modules.get(i)).count() as i32;
+        let k = ((dark * 20 - total * 10).abs() + total - 1) / total - 1;
+        result + k * PENALTY_N4
+    }
+}
+
+/// Lengths of the last runs of modules in a row or column, most recent first
+struct RunHistory {
+    size: i32,
+    runs: [i32; 7],
+}
+
+impl RunHistory {
+    fn new(size: i32) -> Self {
+        Self { size, runs: [0; 7] }
+    }
+
+    fn push(&mut self, mut run: i32) {
+        // The first run includes the light border
+        if self.runs[0] == 0 {
+            run += self.size;
+        }
+        self.runs.copy_within(0..6, 1);
+        self.runs[0] = run;
+    }
+
+    /// Counts 1:1:3:1:1 patterns with 4 light modules on either side. Must be called right after
+    /// a light run is pushed.
+    fn count_patterns(&self) -> i32 {
+        let runs = &self.runs;
+        let n = runs[1];
+        let core = n > 0 && runs[2] == n && runs[3] == n * 3 && runs[4] == n && runs[5] == n;
+        i32::from(core && runs[0] >= n * 4 && runs[6] >= n)
+            + i32::from(core && runs[6] >= n * 4 && runs[0] >= n)
+    }
+
+    fn terminate_and_count(mut self, color: bool, mut run: i32) -> i32 {
+        if color {
+            self.push(run);
+            run = 0;
+        }
+        // The last run includes the light border
+        self.push(run + self.size);
+        self.count_patterns()
+    }
+}
+
+/// Appends bits to a buffer, most significant first.
+struct BitWriter {
+    buf: [u8; MAX_CODEWORDS],
+    len: usize,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if value >> i & 1 != 0 {
+                self.buf[self.len / 8] |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+/// Number of modules available for codewords, including remainder bits.
+const fn raw_data_modules(version: u8) -> usize {
+    let version = version as usize;
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignment = version / 7 + 2;
+        result -= (25 * alignment - 10) * alignment - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+/// Number of data codewords for a version and error correction level.
+fn data_codewords(version: u8, ecc: Ecc) -> usize {
+    raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[ecc as usize][version as usize] as usize
+            * ERROR_CORRECTION_BLOCKS[ecc as usize][version as usize] as usize
+}
+
+/// Returns the centers of the alignment patterns on each axis, and their count.
+fn alignment_positions(version: u8) -> ([u8; 7], usize) {
+    let mut positions = [0u8; 7];
+    if version == 1 {
+        return (positions, 0);
+    }
+    let version = version as usize;
+    let count = version / 7 + 2;
+    let step = (version * 4 + count * 2 + 1) / (count * 2 - 2) * 2;
+    positions[0] = 6;
+    for i in 1..count {
+        positions[count - i] = (version * 4 + 17 - 7 - (i - 1) * step) as u8;
+    }
+    (positions, count)
+}
+
+/// Multiplies in GF(2^8) with the QR code polynomial.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u8 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x1d);
+        z ^= ((y >> i) & 1) * x;
+    }
+    z
+}
+
+/// Computes the Reed-Solomon generator polynomial of the given degree, highest coefficient
+/// first, without the leading 1.
+fn reed_solomon_divisor(degree: usize) -> [u8; MAX_BLOCK_ECC] {
+    let mut result = [0u8; MAX_BLOCK_ECC];
+    result[degree - 1] = 1;
+    let mut root: u8 = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> [u8; MAX_BLOCK_ECC] {
+    let mut result = [0u8; MAX_BLOCK_ECC];
+    let degree = divisor.len();
+    for &byte in data {
+        let factor = byte ^ result[0];
+        result.copy_within(1..degree, 0);
+        result[degree - 1] = 0;
+        for (x, &y) in result[..degree].iter_mut().zip(divisor) {
+            *x ^= gf_multiply(y, factor);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Payload of the fixtures, see `testdata/gen.rs`
+    fn payload(len: usize) -> Vec<u8> {
+        b"alarmo crash report "
+            .iter()
+            .copied()
+            .cycle()
+            .take(len)
+            .collect()
+    }
+
+    fn modules(qr: &QrCode) -> String {
+        let size = qr.size() as i32;
+        let mut text = String::new();
+        for y in 0..size {
+            for x in 0..size {
+                text.push(if qr.get(x, y) { '#' } else { '.' });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Compares against the output of the reference encoder, which also checks that the same
+    /// mask was chosen, since the format bits encode it.
+    macro_rules! known_answer {
+        ($name:ident, $len:literal, $ecc:expr, $file:literal) => {
+            #[test]
+            fn $name() {
+                let qr = QrCode::encode(&payload($len), $ecc).unwrap();
+                assert_eq!(modules(&qr), include_str!(concat!("testdata/", $file)));
+            }
+        };
+    }
+
+    known_answer!(v1_low_mask7, 5, Ecc::Low, "qr_v1_l_mask7.txt");
+    known_answer!(v1_quartile_mask4, 5, Ecc::Quartile, "qr_v1_q_mask4.txt");
+    known_answer!(v1_high_mask5, 5, Ecc::High, "qr_v1_h_mask5.txt");
+    known_answer!(v2_medium_mask2, 20, Ecc::Medium, "qr_v2_m_mask2.txt");
+    known_answer!(v3_quartile_mask6, 25, Ecc::Quartile, "qr_v3_q_mask6.txt");
+    known_answer!(v5_quartile_mask0, 60, Ecc::Quartile, "qr_v5_q_mask0.txt");
+    // Version information
+    known_answer!(v7_quartile_mask4, 80, Ecc::Quartile, "qr_v7_q_mask4.txt");
+    // 16-bit length field, blocks of two sizes
+    known_answer!(v10_low_mask2, 250, Ecc::Low, "qr_v10_l_mask2.txt");
+
+    #[test]
+    fn capacity() {
+        // 523 data codewords in version 15-L, minus the mode and length fields
+        let qr = QrCode::encode(&payload(520), Ecc::Low).unwrap();
+        assert_eq!(qr.size(), MAX_SIZE);
+        assert!(QrCode::encode(&payload(521), Ecc::Low).is_none());
+    }
+
+    #[test]
+    fn quiet_zone() {
+        let qr = QrCode::encode(b"alarmo", Ecc::Medium).unwrap();
+        assert_eq!(qr.size(), 21);
+        // Corner of the top left finder pattern
+        assert!(qr.get(0, 0));
+        for (x, y) in [(-1, 0), (0, -1), (21, 0), (0, 21), (-100, 500)] {
+            assert!(!qr.get(x, y));
+        }
+    }
+}
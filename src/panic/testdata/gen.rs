@@ -0,0 +1,64 @@
+//! Generates the QR code test fixtures in this directory with `qrcodegen`, the reference
+//! encoder by Project Nayuki, which is independent of the encoder in `qr.rs`.
+//!
+//! Each `qr_v<version>_<ecc>_mask<mask>.txt` holds the modules of one code, a row per line with
+//! `#` for dark modules. The payload is `alarmo crash report ` repeated to the given length,
+//! encoded in byte mode without boosting the error correction level.
+//!
+//! Run from this directory, with this file as the `src/main.rs` of a scratch project depending
+//! on `qrcodegen = "=1.8.0"`. The host target is needed, since `.cargo/config.toml` selects the
+//! Alarmo's: `cargo run --manifest-path <project>/Cargo.toml --target <host triple>`
+
+use qrcodegen::{QrCode, QrCodeEcc, QrSegment, Version};
+use std::fmt::Write;
+
+/// Payload length and error correction level of each fixture
+const CASES: [(usize, QrCodeEcc, &str); 8] = [
+    (5, QrCodeEcc::Low, "l"),
+    (5, QrCodeEcc::Quartile, "q"),
+    (5, QrCodeEcc::High, "h"),
+    (20, QrCodeEcc::Medium, "m"),
+    (25, QrCodeEcc::Quartile, "q"),
+    (60, QrCodeEcc::Quartile, "q"),
+    (80, QrCodeEcc::Quartile, "q"),
+    (250, QrCodeEcc::Low, "l"),
+];
+
+fn main() {
+    for (len, ecc, ecc_name) in CASES {
+        let data: Vec<u8> = b"alarmo crash report "
+            .iter()
+            .copied()
+            .cycle()
+            .take(len)
+            .collect();
+        let segments = [QrSegment::make_bytes(&data)];
+        let qr = QrCode::encode_segments_advanced(
+            &segments,
+            ecc,
+            Version::new(1),
+            Version::new(15),
+            None,
+            false,
+        )
+        .unwrap();
+
+        let mut text = String::new();
+        for y in 0..qr.size() {
+            for x in 0..qr.size() {
+                text.push(if qr.get_module(x, y) { '#' } else { '.' });
+            }
+            text.push('\n');
+        }
+        let mut name = String::new();
+        write!(
+            name,
+            "qr_v{}_{ecc_name}_mask{}.txt",
+            qr.version().value(),
+            qr.mask().value()
+        )
+        .unwrap();
+        std::fs::write(&name, text).unwrap();
+        println!("{name}: {len} bytes");
+    }
+}
@@ -0,0 +1,111 @@
+//! Panic report over USB CDC serial (feature `panic-usb`).
+//!
+//! The panic handler brings up USB1 again, as a bare CDC ACM device, and sends the crash report
+//! every time the host opens the port (asserts DTR). Test rigs can capture crashes by opening
+//! the port and reading until the `=== end ===` line.
+
+use super::backtrace::Backtrace;
+use super::crash::CrashRecord;
+use crate::delay::HalDelay;
+use core::fmt::{self, Write};
+use embedded_hal::delay::DelayNs;
+use stm32h7xx_hal::usb_hs::{UsbBus, USB1};
+use usb_device::bus::UsbBusAllocator;
+use usb_device::device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::UsbError;
+use usbd_serial::SerialPort;
+
+/// Time to wait for the host to read the report before giving up, in milliseconds
+const WRITE_TIMEOUT_MS: u32 = 500;
+
+static mut EP_MEMORY: [u32; 1024] = [0; 1024];
+
+pub(super) struct PanicSerial<'a> {
+    device: UsbDevice<'a, UsbBus<USB1>>,
+    serial: SerialPort<'a, UsbBus<USB1>>,
+    /// Whether the report was sent since the port was opened
+    sent: bool,
+}
+
+/// Creates the USB bus.
+///
+/// ## Safety
+/// Must only be called once, from the panic handler.
+pub(super) unsafe fn bus(usb1: USB1) -> UsbBusAllocator<UsbBus<USB1>> {
+    UsbBus::new(usb1, &mut *core::ptr::addr_of_mut!(EP_MEMORY))
+}
+
+impl<'a> PanicSerial<'a> {
+    pub(super) fn new(bus: &'a UsbBusAllocator<UsbBus<USB1>>) -> Self {
+        let serial = SerialPort::new(bus);
+        let device = UsbDeviceBuilder::new(bus, UsbVidPid(0x2111, 0x2024))
+            .strings(&[StringDescriptors::default()
+                .manufacturer("alarmo-rs")
+                .product("Alarmo panic report")])
+            .unwrap()
+            .device_class(usbd_serial::USB_CLASS_CDC)
+            .build();
+        Self {
+            device,
+            serial,
+            sent: false,
+        }
+    }
+
+    /// Handles USB events, and sends the report if the port was just opened.
+    pub(super) fn poll(&mut self, record: &CrashRecord, backtrace: &Backtrace) {
+        self.device.poll(&mut [&mut self.serial]);
+        if !self.serial.dtr() {
+            self.sent = false;
+            return;
+        }
+        if !self.sent {
+            // Don't retry on timeout, the host is likely not reading
+            self.sent = true;
+            self.write_report(record, backtrace).ok();
+        }
+    }
+
+    fn write_report(&mut self, record: &CrashRecord, backtrace: &Backtrace) -> fmt::Result {
+        writeln!(self, "=== alarmo crash report ===")?;
+        write!(self, "{record}")?;
+        write!(self, "backtrace:")?;
+        for address in backtrace.frames() {
+            write!(self, " {address:08x}")?;
+        }
+        writeln!(self)?;
+        writeln!(self, "=== end ===")?;
+        self.flush().map_err(|_| fmt::Error)
+    }
+
+    fn flush(&mut self) -> Result<(), UsbError> {
+        for _ in 0..WRITE_TIMEOUT_MS {
+            self.device.poll(&mut [&mut self.serial]);
+            match self.serial.flush() {
+                Ok(()) => return Ok(()),
+                Err(UsbError::WouldBlock) => HalDelay.delay_ms(1),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(UsbError::WouldBlock)
+    }
+}
+
+impl Write for PanicSerial<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut data = s.as_bytes();
+        let mut waited = 0;
+        while !data.is_empty() {
+            self.device.poll(&mut [&mut self.serial]);
+            match self.serial.write(data) {
+                Ok(count) => data = &data[count..],
+                Err(UsbError::WouldBlock) if waited < WRITE_TIMEOUT_MS => {
+                    HalDelay.delay_ms(1);
+                    waited += 1;
+                }
+                Err(_) => return Err(fmt::Error),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,619 @@
+//! Recording and deterministic replay of button and dial input.
+//!
+//! Input is captured as a compact binary log, which can be stored (e.g. in RAM or on the eMMC)
+//! and replayed later through the same [`InputSource`] interface used for live input. Application
+//! code written against [`InputSource`] cannot tell a [`Replay`] apart from the real hardware,
+//! which makes it possible to reproduce UI bugs exactly, or to drive the same code from a host
+//! unit test.
+//!
+//! ## Log format
+//! A log starts with the 4-byte magic `AIRL` and a version byte (currently `1`), followed by
+//! a sequence of events. Each event is:
+//!
+//! * the time elapsed since the previous event (or since the start of the recording), in
+//!   milliseconds, as an unsigned LEB128 varint
+//! * a flags byte: bits 0-2 hold the levels of the mail, back and dial click buttons; bit 7 is
+//!   set if the dial angle follows
+//! * if bit 7 is set, the dial angle as a little-endian `u16`, in units of `360 / 65536` degrees
+//!
+//! Events are only written when the input changes, so an idle device produces no data.
+
+use crate::dial::Dial;
+use crate::input::{Button, Buttons};
+
+const MAGIC: &[u8; 4] = b"AIRL";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1;
+const FLAG_DIAL: u8 = 1 << 7;
+const BUTTONS_MASK: u8 = 0b111;
+
+/// Common interface for live and replayed input.
+pub trait InputSource {
+    /// Returns whether the given button is currently pressed.
+    fn button(&self, button: Button) -> bool;
+
+    /// Returns the current rotation of the dial, in normalized degrees (`[0, 360)`)
+    fn rotation_deg(&self) -> f32;
+}
+
+/// Live input, read directly from the Alarmo's peripherals.
+pub struct LiveInput<'a> {
+    pub buttons: &'a Buttons,
+    pub dial: &'a Dial,
+}
+
+impl InputSource for LiveInput<'_> {
+    fn button(&self, button: Button) -> bool {
+        self.buttons.button(button)
+    }
+
+    fn rotation_deg(&self) -> f32 {
+        self.dial.rotation_deg()
+    }
+}
+
+/// Snapshot of all inputs at a point in time.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct InputFrame {
+    buttons: u8,
+    dial: u16,
+}
+
+/// A single event decoded from an input log.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct InputEvent {
+    /// Time since the start of the recording, in milliseconds
+    pub timestamp_ms: u32,
+    pub frame: InputFrame,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LogError {
+    /// The recording buffer has no space left for the event
+    Full,
+    /// The log does not start with a valid header
+    BadHeader,
+    /// The log ends in the middle of an event
+    Truncated,
+}
+
+/// Writes input events into a caller-provided buffer.
+///
+/// See the [module docs](self) for the log format.
+pub struct Recorder<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    start_ms: Option<u32>,
+    last_ms: u32,
+    last: Option<InputFrame>,
+    dial_deadband: u16,
+}
+
+/// Reads events from an input log.
+pub struct LogReader<'a> {
+    log: &'a [u8],
+    pos: usize,
+    timestamp_ms: u32,
+    frame: InputFrame,
+}
+
+/// Replays an input log as an [`InputSource`].
+///
+/// The replay starts at time 0 with all buttons released. Call [`advance_to`] with the
+/// current time (relative to the start of the replay) before reading inputs.
+///
+/// [`advance_to`]: Replay::advance_to
+pub struct Replay<'a> {
+    reader: LogReader<'a>,
+    next: Option<InputEvent>,
+    current: InputFrame,
+    error: Option<LogError>,
+}
+
+impl InputFrame {
+    /// Captures the current state of an input source.
+    pub fn capture(source: &impl InputSource) -> Self {
+        let mut frame = Self {
+            buttons: 0,
+            dial: angle_to_raw(source.rotation_deg()),
+        };
+        for button in [Button::Mail, Button::Back, Button::DialClick] {
+            if source.button(button) {
+                frame.buttons |= button_mask(button);
+            }
+        }
+        frame
+    }
+
+    pub fn button(&self, button: Button) -> bool {
+        self.buttons & button_mask(button) != 0
+    }
+
+    pub fn rotation_deg(&self) -> f32 {
+        self.dial as f32 * 360f32 / 65536f32
+    }
+}
+
+impl<'a> Recorder<'a> {
+    /// Starts a new recording in the given buffer.
+    ///
+    /// ## Panics
+    /// Panics if the buffer is too small to hold the log header.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        assert!(buf.len() >= HEADER_LEN, "buffer too small for log header");
+        buf[..MAGIC.len()].copy_from_slice(MAGIC);
+        buf[MAGIC.len()] = VERSION;
+        Self {
+            buf,
+            len: HEADER_LEN,
+            start_ms: None,
+            last_ms: 0,
+            last: None,
+            dial_deadband: 0,
+        }
+    }
+
+    /// Ignores dial movements smaller than `degrees`, to avoid recording ADC noise.
+    pub fn with_dial_deadband(mut self, degrees: f32) -> Self {
+        self.dial_deadband = angle_to_raw(degrees);
+        self
+    }
+
+    /// Samples the input source and appends an event if anything changed since the last sample.
+    ///
+    /// `timestamp_ms` is the current time in milliseconds, using any monotonic clock. The first
+    /// recorded sample defines the start of the recording.
+    ///
+    /// Returns whether an event was written.
+    pub fn record(
+        &mut self,
+        timestamp_ms: u32,
+        source: &impl InputSource,
+    ) -> Result<bool, LogError> {
+        self.record_frame(timestamp_ms, InputFrame::capture(source))
+    }
+
+    /// Like [`record`](Recorder::record), but takes an already captured frame.
+    pub fn record_frame(&mut self, timestamp_ms: u32, frame: InputFrame) -> Result<bool, LogError> {
+        let start = *self.start_ms.get_or_insert(timestamp_ms);
+        let elapsed = timestamp_ms.wrapping_sub(start);
+
+        let mut flags = frame.buttons & BUTTONS_MASK;
+        let mut frame = frame;
+        match self.last {
+            Some(last) => {
+                if angle_distance(last.dial, frame.dial) > self.dial_deadband {
+                    flags |= FLAG_DIAL;
+                } else {
+                    frame.dial = last.dial;
+                }
+                if flags & FLAG_DIAL == 0 && last.buttons == frame.buttons {
+                    return Ok(false);
+                }
+            }
+            None => flags |= FLAG_DIAL,
+        }
+
+        let mut event = [0u8; 8];
+        let mut event_len = write_varint(elapsed.wrapping_sub(self.last_ms), &mut event);
+        event[event_len] = flags;
+        event_len += 1;
+        if flags & FLAG_DIAL != 0 {
+            event[event_len..event_len + 2].copy_from_slice(&frame.dial.to_le_bytes());
+            event_len += 2;
+        }
+
+        let dest = self
+            .buf
+            .get_mut(self.len..self.len + event_len)
+            .ok_or(LogError::Full)?;
+        dest.copy_from_slice(&event[..event_len]);
+        self.len += event_len;
+        self.last_ms = elapsed;
+        self.last = Some(frame);
+        Ok(true)
+    }
+
+    /// Returns the log recorded so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Ends the recording, returning the log.
+    pub fn finish(self) -> &'a [u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<'a> LogReader<'a> {
+    /// Validates the log header and prepares to read events.
+    pub fn new(log: &'a [u8]) -> Result<Self, LogError> {
+        if log.len() < HEADER_LEN || &log[..MAGIC.len()] != MAGIC || log[MAGIC.len()] != VERSION {
+            return Err(LogError::BadHeader);
+        }
+        Ok(Self {
+            log,
+            pos: HEADER_LEN,
+            timestamp_ms: 0,
+            frame: InputFrame::default(),
+        })
+    }
+
+    fn read_event(&mut self) -> Result<InputEvent, LogError> {
+        let (delta, varint_len) = read_varint(&self.log[self.pos..]).ok_or(LogError::Truncated)?;
+        let mut pos = self.pos + varint_len;
+        let flags = *self.log.get(pos).ok_or(LogError::Truncated)?;
+        pos += 1;
+
+        self.frame.buttons = flags & BUTTONS_MASK;
+        if flags & FLAG_DIAL != 0 {
+            let raw = self.log.get(pos..pos + 2).ok_or(LogError::Truncated)?;
+            self.frame.dial = u16::from_le_bytes([raw[0], raw[1]]);
+            pos += 2;
+        }
+
+        self.pos = pos;
+        self.timestamp_ms = self.timestamp_ms.wrapping_add(delta);
+        Ok(InputEvent {
+            timestamp_ms: self.timestamp_ms,
+            frame: self.frame,
+        })
+    }
+}
+
+impl Iterator for LogReader<'_> {
+    type Item = Result<InputEvent, LogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.log.len() {
+            return None;
+        }
+        let event = self.read_event();
+        if event.is_err() {
+            // Don't keep returning the same error
+            self.pos = self.log.len();
+        }
+        Some(event)
+    }
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(log: &'a [u8]) -> Result<Self, LogError> {
+        let mut reader = LogReader::new(log)?;
+        let next = reader.next().transpose()?;
+        Ok(Self {
+            reader,
+            next,
+            current: InputFrame::default(),
+            error: None,
+        })
+    }
+
+    /// Applies all events up to (and including) `timestamp_ms`, in milliseconds since the start
+    /// of the replay.
+    ///
+    /// Returns whether the inputs changed.
+    pub fn advance_to(&mut self, timestamp_ms: u32) -> bool {
+        let before = self.current;
+        while let Some(event) = self.next.filter(|e| e.timestamp_ms <= timestamp_ms) {
+            self.current = event.frame;
+            self.next = match self.reader.next().transpose() {
+                Ok(next) => next,
+                Err(e) => {
+                    self.error = Some(e);
+                    None
+                }
+            };
+        }
+        before != self.current
+    }
+
+    /// Returns the timestamp of the next pending event, or `None` if the replay is over.
+    pub fn next_event_ms(&self) -> Option<u32> {
+        self.next.map(|e| e.timestamp_ms)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next.is_none()
+    }
+
+    /// Returns the error that stopped the replay early, if the log was malformed.
+    pub fn error(&self) -> Option<LogError> {
+        self.error
+    }
+
+    pub fn frame(&self) -> InputFrame {
+        self.current
+    }
+}
+
+impl InputSource for Replay<'_> {
+    fn button(&self, button: Button) -> bool {
+        self.current.button(button)
+    }
+
+    fn rotation_deg(&self) -> f32 {
+        self.current.rotation_deg()
+    }
+}
+
+fn button_mask(button: Button) -> u8 {
+    match button {
+        Button::Mail => 1 << 0,
+        Button::Back => 1 << 1,
+        Button::DialClick => 1 << 2,
+    }
+}
+
+fn angle_to_raw(degrees: f32) -> u16 {
+    let normalized = (degrees % 360f32 + 360f32) % 360f32;
+    ((normalized / 360f32 * 65536f32) as u32 & 0xffff) as u16
+}
+
+fn angle_distance(a: u16, b: u16) -> u16 {
+    let diff = a.wrapping_sub(b);
+    diff.min(diff.wrapping_neg())
+}
+
+fn write_varint(mut value: u32, buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            return len + 1;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+}
+
+fn read_varint(buf: &[u8]) -> Option<(u32, usize)> {
+    let mut value = 0u32;
+    for (i, &byte) in buf.iter().enumerate().take(5) {
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Input source with fixed values
+    struct Fixed {
+        buttons: [bool; 3],
+        degrees: f32,
+    }
+
+    impl InputSource for Fixed {
+        fn button(&self, button: Button) -> bool {
+            self.buttons[button as usize]
+        }
+
+        fn rotation_deg(&self) -> f32 {
+            self.degrees
+        }
+    }
+
+    fn frame(mail: bool, back: bool, click: bool, degrees: f32) -> InputFrame {
+        InputFrame::capture(&Fixed {
+            buttons: [mail, back, click],
+            degrees,
+        })
+    }
+
+    fn events(log: &[u8]) -> Vec<Result<InputEvent, LogError>> {
+        LogReader::new(log).unwrap().collect()
+    }
+
+    #[test]
+    fn varint_edges() {
+        for (value, len) in [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (16383, 2),
+            (16384, 3),
+            (u32::MAX, 5),
+        ] {
+            let mut buf = [0u8; 5];
+            assert_eq!(write_varint(value, &mut buf), len, "{value}");
+            assert_eq!(read_varint(&buf[..len]), Some((value, len)), "{value}");
+            // Missing continuation bytes
+            assert_eq!(read_varint(&buf[..len - 1]), None, "{value}");
+        }
+        assert_eq!(read_varint(&[0x80; 6]), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0u8; 64];
+        let mut recorder = Recorder::new(&mut buf);
+        // The first sample is the start of the recording
+        assert_eq!(
+            recorder.record_frame(1000, frame(false, false, false, 90.0)),
+            Ok(true)
+        );
+        // Nothing changed
+        assert_eq!(
+            recorder.record_frame(1010, frame(false, false, false, 90.0)),
+            Ok(false)
+        );
+        assert_eq!(
+            recorder.record_frame(1100, frame(true, false, false, 90.0)),
+            Ok(true)
+        );
+        // Delta needs a 2-byte varint
+        assert_eq!(
+            recorder.record_frame(1300, frame(true, false, true, 180.0)),
+            Ok(true)
+        );
+        // Delta needs a 5-byte varint
+        let late = 1000u32.wrapping_add(u32::MAX);
+        assert_eq!(
+            recorder.record_frame(late, frame(false, true, false, 180.0)),
+            Ok(true)
+        );
+        let log = recorder.finish();
+        assert_eq!(&log[..HEADER_LEN], b"AIRL\x01");
+        // Header, then 4 + 2 + 5 + 6 bytes of events
+        assert_eq!(log.len(), HEADER_LEN + 17);
+
+        assert_eq!(
+            events(log),
+            [
+                Ok(InputEvent {
+                    timestamp_ms: 0,
+                    frame: frame(false, false, false, 90.0)
+                }),
+                Ok(InputEvent {
+                    timestamp_ms: 100,
+                    frame: frame(true, false, false, 90.0)
+                }),
+                Ok(InputEvent {
+                    timestamp_ms: 300,
+                    frame: frame(true, false, true, 180.0)
+                }),
+                Ok(InputEvent {
+                    timestamp_ms: u32::MAX,
+                    frame: frame(false, true, false, 180.0)
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn dial_deadband() {
+        let mut buf = [0u8; 32];
+        let mut recorder = Recorder::new(&mut buf).with_dial_deadband(2.0);
+        assert_eq!(
+            recorder.record_frame(0, frame(false, false, false, 359.5)),
+            Ok(true)
+        );
+        // Within the deadband, across the wrap-around
+        assert_eq!(
+            recorder.record_frame(10, frame(false, false, false, 1.0)),
+            Ok(false)
+        );
+        assert_eq!(
+            recorder.record_frame(20, frame(false, false, false, 3.0)),
+            Ok(true)
+        );
+        // A button change doesn't record the dial noise
+        assert_eq!(
+            recorder.record_frame(30, frame(true, false, false, 3.5)),
+            Ok(true)
+        );
+        let log = recorder.finish();
+
+        let events = events(log);
+        assert_eq!(events.len(), 3);
+        let last = events[2].unwrap().frame;
+        assert_eq!(last.rotation_deg(), events[1].unwrap().frame.rotation_deg());
+        assert!((last.rotation_deg() - 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn full_buffer() {
+        let mut buf = [0u8; HEADER_LEN + 4];
+        let mut recorder = Recorder::new(&mut buf);
+        assert_eq!(
+            recorder.record_frame(0, frame(false, false, false, 0.0)),
+            Ok(true)
+        );
+        // Needs 2 bytes, 0 left
+        assert_eq!(
+            recorder.record_frame(1, frame(true, false, false, 0.0)),
+            Err(LogError::Full)
+        );
+        // The failed event doesn't corrupt the log
+        assert_eq!(recorder.as_bytes().len(), HEADER_LEN + 4);
+        assert_eq!(events(recorder.as_bytes()).len(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn buffer_too_small_for_header() {
+        Recorder::new(&mut [0u8; HEADER_LEN - 1]);
+    }
+
+    #[test]
+    fn bad_header() {
+        assert_eq!(LogReader::new(b"AIRL").err(), Some(LogError::BadHeader));
+        assert_eq!(LogReader::new(b"AIRX\x01").err(), Some(LogError::BadHeader));
+        assert_eq!(LogReader::new(b"AIRL\x02").err(), Some(LogError::BadHeader));
+        assert_eq!(Replay::new(b"").err(), Some(LogError::BadHeader));
+        assert!(events(b"AIRL\x01").is_empty());
+    }
+
+    #[test]
+    fn truncated() {
+        // Unterminated varint
+        assert_eq!(events(b"AIRL\x01\x80"), [Err(LogError::Truncated)]);
+        // Missing flags
+        assert_eq!(events(b"AIRL\x01\x05"), [Err(LogError::Truncated)]);
+        // Half of the dial angle, then nothing more after the error
+        let mut reader = LogReader::new(b"AIRL\x01\x00\x01\x05\x81\x00").unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(reader.next(), Some(Err(LogError::Truncated)));
+        assert_eq!(reader.next(), None);
+    }
+
+    #[test]
+    fn replay() {
+        let mut buf = [0u8; 64];
+        let mut recorder = Recorder::new(&mut buf);
+        recorder
+            .record_frame(500, frame(false, false, false, 45.0))
+            .unwrap();
+        recorder
+            .record_frame(600, frame(true, false, false, 45.0))
+            .unwrap();
+        recorder
+            .record_frame(800, frame(false, false, true, 270.0))
+            .unwrap();
+        let log = recorder.finish();
+
+        let mut replay = Replay::new(log).unwrap();
+        // Starts released, before the first event is applied
+        assert!(!replay.button(Button::Mail));
+        assert_eq!(replay.next_event_ms(), Some(0));
+
+        assert!(replay.advance_to(0));
+        assert!((replay.rotation_deg() - 45.0).abs() < 0.01);
+        assert!(!replay.advance_to(99));
+        assert!(!replay.button(Button::Mail));
+
+        assert!(replay.advance_to(100));
+        assert!(replay.button(Button::Mail));
+        assert_eq!(replay.next_event_ms(), Some(300));
+
+        // Skipping over events only keeps the last one
+        assert!(replay.advance_to(10_000));
+        assert!(!replay.button(Button::Mail));
+        assert!(replay.button(Button::DialClick));
+        assert!((replay.rotation_deg() - 270.0).abs() < 0.01);
+        assert!(replay.is_finished());
+        assert_eq!(replay.error(), None);
+
+        // Replays can be recorded again like live input
+        let mut copy_buf = [0u8; 64];
+        let mut copy = Recorder::new(&mut copy_buf);
+        assert_eq!(copy.record(0, &replay), Ok(true));
+        assert_eq!(events(copy.as_bytes())[0].unwrap().frame, replay.frame());
+    }
+
+    #[test]
+    fn replay_stops_at_corrupt_event() {
+        let mut replay = Replay::new(b"AIRL\x01\x00\x81\x00\x00\x0a").unwrap();
+        assert!(replay.advance_to(0));
+        assert!(replay.button(Button::Mail));
+        assert!(replay.is_finished());
+        assert_eq!(replay.error(), Some(LogError::Truncated));
+    }
+}
@@ -0,0 +1,685 @@
+//! Screenshot capture, for documentation and bug reports from real units.
+//!
+//! A screenshot is taken from a [`FrameSource`], either a frame buffer or the LCD's own memory
+//! (see [`GramSource`]), encoded as a 16-bit BMP image and streamed row by row into a
+//! [`ScreenshotSink`]. Nothing is buffered beyond a single row, so captures work without a heap.
+//!
+//! Available sinks:
+//! * [`UsbSerialSink`] (feature `usb`): streams the image over a USB CDC serial port, e.g. to
+//!   `cat /dev/ttyACM0 > screenshot.bmp` on the host
+//! * [`FatSink`] (feature `embedded-sdmmc`): saves each image to a new `SCRNnnnn.BMP` file on a
+//!   FAT volume, e.g. a partition of the eMMC
+//! * [`BlockSink`]: writes the image to consecutive blocks of a
+//!   [`BlockDevice`](crate::storage::BlockDevice), without a file system
+//!
+//! Captures are usually bound to a button combination with [`ChordTrigger`]:
+//!
+//! ```no_run
+//! # use alarmo::input::Button;
+//! # use alarmo::recording::LiveInput;
+//! # use alarmo::screenshot::{self, ChordTrigger, GramSource, ScreenshotSink};
+//! # fn run(alarmo: &mut alarmo::Alarmo, sink: &mut impl ScreenshotSink) {
+//! let mut trigger = ChordTrigger::new(&[Button::Mail, Button::Back]);
+//! loop {
+//!     let input = LiveInput { buttons: &alarmo.buttons, dial: &alarmo.dial };
+//!     if trigger.update(&input) {
+//!         let mut gram = GramSource::new(&mut alarmo.display, 320, 240);
+//!         screenshot::capture(&mut gram, sink).ok();
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::display::bus::{DisplayBus, FmcBus};
+use crate::display::AlarmoDisplay;
+use crate::input::Button;
+use crate::recording::InputSource;
+
+/// Maximum width of a captured frame, in pixels
+pub const MAX_WIDTH: usize = 320;
+
+const BMP_FILE_HEADER_LEN: u32 = 14;
+const BMP_INFO_HEADER_LEN: u32 = 40;
+/// Color masks following the info header, for `BI_BITFIELDS`
+const BMP_MASKS: [u32; 3] = [0xf800, 0x07e0, 0x001f];
+const BMP_HEADERS_LEN: u32 = BMP_FILE_HEADER_LEN + BMP_INFO_HEADER_LEN + 4 * BMP_MASKS.len() as u32;
+const BI_BITFIELDS: u32 = 3;
+
+/// A frame that can be read row by row, as RGB565 pixels.
+pub trait FrameSource {
+    /// Returns the dimensions of the frame, as `(width, height)`.
+    fn size(&self) -> (u16, u16);
+
+    /// Reads row `y` into `row`, which holds exactly `width` pixels.
+    fn read_row(&mut self, y: u16, row: &mut [u16]);
+}
+
+/// Destination for encoded screenshots.
+pub trait ScreenshotSink {
+    type Error;
+
+    /// Writes the next chunk of the image.
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Called once the whole image has been written.
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Reads the frame currently shown on the LCD, straight from GRAM.
+///
+/// `width` and `height` are the dimensions of the screen in the current memory access mode,
+/// e.g. 320x240 for the default [`AlarmoScreen`](crate::display::AlarmoScreen) orientation.
+/// See [`AlarmoDisplay::read_pixels`] for the color precision of GRAM reads.
+pub struct GramSource<'a, B: DisplayBus = FmcBus> {
+    display: &'a mut AlarmoDisplay<B>,
+    width: u16,
+    height: u16,
+}
+
+/// Edge-triggered detection of a button combination.
+pub struct ChordTrigger<'a> {
+    buttons: &'a [Button],
+    held: bool,
+}
+
+impl<'a, B: DisplayBus> GramSource<'a, B> {
+    pub fn new(display: &'a mut AlarmoDisplay<B>, width: u16, height: u16) -> Self {
+        Self {
+            display,
+            width,
+            height,
+        }
+    }
+}
+
+impl<B: DisplayBus> FrameSource for GramSource<'_, B> {
+    fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn read_row(&mut self, y: u16, row: &mut [u16]) {
+        self.display.read_pixels(0, y, self.width - 1, y, row);
+    }
+}
+
+#[cfg(all(feature = "graphics", feature = "alloc"))]
+impl FrameSource for crate::display::framebuffer::FrameBuffer {
+    fn size(&self) -> (u16, u16) {
+        let size = embedded_graphics::geometry::OriginDimensions::size(self);
+        (size.width as u16, size.height as u16)
+    }
+
+    fn read_row(&mut self, y: u16, row: &mut [u16]) {
+        let start = y as usize * row.len();
+        row.copy_from_slice(&self.pixels()[start..start + row.len()]);
+    }
+}
+
+impl<'a> ChordTrigger<'a> {
+    /// Creates a trigger that fires when all of `buttons` are held at the same time.
+    pub fn new(buttons: &'a [Button]) -> Self {
+        Self {
+            buttons,
+            held: false,
+        }
+    }
+
+    /// Samples the input. Returns `true` once each time the chord is pressed; the buttons must
+    /// be released before it can fire again.
+    pub fn update(&mut self, input: &impl InputSource) -> bool {
+        let held = !self.buttons.is_empty() && self.buttons.iter().all(|b| input.button(*b));
+        let fired = held && !self.held;
+        self.held = held;
+        fired
+    }
+}
+
+/// Returns the size of the BMP file produced by [`capture`] for a frame of the given size.
+pub fn bmp_size(width: u16, height: u16) -> u32 {
+    BMP_HEADERS_LEN + row_stride(width) * height as u32
+}
+
+/// Encodes the frame from `source` as a BMP image and writes it to `sink`.
+///
+/// The image uses 16-bit RGB565 pixels (`BI_BITFIELDS`), stored bottom-up like most BMP files,
+/// so the rows are read from the source starting with the last one.
+///
+/// ## Panics
+/// Panics if the frame is wider than [`MAX_WIDTH`].
+pub fn capture<S: ScreenshotSink>(
+    source: &mut impl FrameSource,
+    sink: &mut S,
+) -> Result<(), S::Error> {
+    let (width, height) = source.size();
+    assert!(width as usize <= MAX_WIDTH, "frame too wide");
+
+    sink.write(&bmp_headers(width, height))?;
+
+    let mut row_buf = [0u16; MAX_WIDTH];
+    let mut bytes = [0u8; MAX_WIDTH * 2];
+    let row_bytes = row_stride(width) as usize;
+    for y in (0..height).rev() {
+        let row = &mut row_buf[..width as usize];
+        source.read_row(y, row);
+        for (chunk, pixel) in bytes.chunks_exact_mut(2).zip(row.iter()) {
+            chunk.copy_from_slice(&pixel.to_le_bytes());
+        }
+        // Padding bytes are never written to, so they stay zero
+        sink.write(&bytes[..row_bytes])?;
+    }
+    sink.finish()
+}
+
+/// Bytes per BMP row, padded to a multiple of 4
+fn row_stride(width: u16) -> u32 {
+    (width as u32 * 2 + 3) & !3
+}
+
+fn bmp_headers(width: u16, height: u16) -> [u8; BMP_HEADERS_LEN as usize] {
+    let mut header = [0u8; BMP_HEADERS_LEN as usize];
+    let image_len = row_stride(width) * height as u32;
+
+    // BITMAPFILEHEADER
+    header[0..2].copy_from_slice(b"BM");
+    header[2..6].copy_from_slice(&(BMP_HEADERS_LEN + image_len).to_le_bytes());
+    header[10..14].copy_from_slice(&BMP_HEADERS_LEN.to_le_bytes());
+
+    // BITMAPINFOHEADER, a positive height means bottom-up rows
+    let info = &mut header[BMP_FILE_HEADER_LEN as usize..];
+    info[0..4].copy_from_slice(&BMP_INFO_HEADER_LEN.to_le_bytes());
+    info[4..8].copy_from_slice(&(width as i32).to_le_bytes());
+    info[8..12].copy_from_slice(&(height as i32).to_le_bytes());
+    info[12..14].copy_from_slice(&1u16.to_le_bytes()); // Planes
+    info[14..16].copy_from_slice(&16u16.to_le_bytes()); // Bits per pixel
+    info[16..20].copy_from_slice(&BI_BITFIELDS.to_le_bytes());
+    info[20..24].copy_from_slice(&image_len.to_le_bytes());
+
+    let masks = &mut info[BMP_INFO_HEADER_LEN as usize..];
+    for (chunk, mask) in masks.chunks_exact_mut(4).zip(BMP_MASKS) {
+        chunk.copy_from_slice(&mask.to_le_bytes());
+    }
+    header
+}
+
+#[cfg(feature = "usb")]
+pub use usb_sink::UsbSerialSink;
+
+#[cfg(feature = "usb")]
+mod usb_sink {
+    use super::ScreenshotSink;
+    use usb_device::bus::UsbBus;
+    use usb_device::device::UsbDevice;
+    use usb_device::UsbError;
+    use usbd_serial::SerialPort;
+
+    /// Streams screenshots over a USB CDC serial port.
+    ///
+    /// Writes block (while polling the device) until the host has read the data, so a program
+    /// must be reading from the port on the host side.
+    pub struct UsbSerialSink<'a, 'b, B: UsbBus> {
+        device: &'a mut UsbDevice<'b, B>,
+        serial: &'a mut SerialPort<'b, B>,
+    }
+
+    impl<'a, 'b, B: UsbBus> UsbSerialSink<'a, 'b, B> {
+        pub fn new(device: &'a mut UsbDevice<'b, B>, serial: &'a mut SerialPort<'b, B>) -> Self {
+            Self { device, serial }
+        }
+    }
+
+    impl<B: UsbBus> ScreenshotSink for UsbSerialSink<'_, '_, B> {
+        type Error = UsbError;
+
+        fn write(&mut self, mut data: &[u8]) -> Result<(), UsbError> {
+            while !data.is_empty() {
+                self.device.poll(&mut [&mut *self.serial]);
+                match self.serial.write(data) {
+                    Ok(count) => data = &data[count..],
+                    Err(UsbError::WouldBlock) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), UsbError> {
+            loop {
+                self.device.poll(&mut [&mut *self.serial]);
+                match self.serial.flush() {
+                    Ok(()) => return Ok(()),
+                    Err(UsbError::WouldBlock) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+}
+
+pub use block_sink::BlockSink;
+
+mod block_sink {
+    use super::ScreenshotSink;
+    use crate::storage::{BlockDevice, Error, BLOCK_SIZE};
+
+    /// Writes screenshots to consecutive blocks of a [`BlockDevice`], starting at a given block.
+    ///
+    /// The image is written raw, without a file system: reserve an area that is not used by
+    /// any partition, and make sure it can hold [`bmp_size`](super::bmp_size) bytes. The last
+    /// block is padded with zeros. On the eMMC, the blocks must be allowed by the write gate of
+    /// [`EmmcStorage`](crate::storage::EmmcStorage).
+    ///
+    /// After a capture, [`next_block`](BlockSink::next_block) is the first block after the
+    /// image, so consecutive captures can be stored back to back.
+    pub struct BlockSink<D> {
+        device: D,
+        next_block: u32,
+        buf: [u8; BLOCK_SIZE],
+        filled: usize,
+    }
+
+    impl<D: BlockDevice> BlockSink<D> {
+        pub fn new(device: D, start_block: u32) -> Self {
+            Self {
+                device,
+                next_block: start_block,
+                buf: [0; BLOCK_SIZE],
+                filled: 0,
+            }
+        }
+
+        pub fn next_block(&self) -> u32 {
+            self.next_block
+        }
+
+        pub fn into_inner(self) -> D {
+            self.device
+        }
+
+        fn flush_block(&mut self) -> Result<(), Error<D::Error>> {
+            self.buf[self.filled..].fill(0);
+            self.device.write(self.next_block, &self.buf)?;
+            self.next_block += 1;
+            self.filled = 0;
+            Ok(())
+        }
+    }
+
+    impl<D: BlockDevice> ScreenshotSink for BlockSink<D> {
+        type Error = Error<D::Error>;
+
+        fn write(&mut self, mut data: &[u8]) -> Result<(), Self::Error> {
+            while !data.is_empty() {
+                let count = data.len().min(BLOCK_SIZE - self.filled);
+                self.buf[self.filled..self.filled + count].copy_from_slice(&data[..count]);
+                self.filled += count;
+                data = &data[count..];
+                if self.filled == BLOCK_SIZE {
+                    self.flush_block()?;
+                }
+            }
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), Self::Error> {
+            if self.filled > 0 {
+                self.flush_block()?;
+            }
+            self.device.flush()
+        }
+    }
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+pub use fat_sink::FatSink;
+
+#[cfg(feature = "embedded-sdmmc")]
+mod fat_sink {
+    use super::ScreenshotSink;
+    use crate::storage::fat::{FatError, FatFs};
+    use crate::storage::{BlockDevice, BLOCK_SIZE};
+    use embedded_sdmmc::filesystem::FilenameError;
+    use embedded_sdmmc::TimeSource;
+
+    /// Longest directory accepted by [`FatSink::create`], in bytes
+    const MAX_DIR_LEN: usize = 64;
+    const PREFIX: &[u8] = b"SCRN";
+    const EXTENSION: &[u8] = b"BMP";
+    /// `SCRNnnnn.BMP`
+    const NAME_LEN: usize = 12;
+
+    /// Saves each screenshot to a new file named `SCRNnnnn.BMP` on a FAT volume.
+    ///
+    /// [`create`](FatSink::create) numbers the file after the highest one already in the
+    /// directory, and creates it empty. The image is then appended to it one block at a time.
+    pub struct FatSink<'a, D: BlockDevice, T: TimeSource> {
+        fs: &'a FatFs<D, T>,
+        path: [u8; MAX_DIR_LEN + 1 + NAME_LEN],
+        path_len: usize,
+        buf: [u8; BLOCK_SIZE],
+        filled: usize,
+    }
+
+    impl<'a, D: BlockDevice, T: TimeSource> FatSink<'a, D, T> {
+        /// Creates the next screenshot file in the directory at `dir`, which must exist. The root
+        /// directory is `""`.
+        ///
+        /// Fails with [`FilenameError::NameTooLong`] if `dir` is longer than 64 bytes, and with
+        /// [`FileAlreadyExists`](embedded_sdmmc::Error::FileAlreadyExists) once `SCRN9999.BMP`
+        /// exists.
+        pub fn create(fs: &'a FatFs<D, T>, dir: &str) -> Result<Self, FatError<D::Error>> {
+            let dir = dir.trim_matches('/');
+            if dir.len() > MAX_DIR_LEN {
+                return Err(FatError::FilenameError(FilenameError::NameTooLong));
+            }
+            let mut last = None;
+            fs.list_dir(dir, |entry, _| {
+                let number = screenshot_number(entry.name.base_name(), entry.name.extension());
+                last = last.max(number);
+            })?;
+            let number = match last {
+                None => 0,
+                Some(9999) => return Err(FatError::FileAlreadyExists),
+                Some(last) => last + 1,
+            };
+
+            let mut sink = Self {
+                fs,
+                path: [0; MAX_DIR_LEN + 1 + NAME_LEN],
+                path_len: 0,
+                buf: [0; BLOCK_SIZE],
+                filled: 0,
+            };
+            let mut name = *b"SCRN0000.BMP";
+            for (digit, place) in name[4..8].iter_mut().zip([1000, 100, 10, 1]) {
+                *digit = b'0' + (number / place % 10) as u8;
+            }
+            let separator: &[u8] = if dir.is_empty() { b"" } else { b"/" };
+            for part in [dir.as_bytes(), separator, &name] {
+                sink.path[sink.path_len..sink.path_len + part.len()].copy_from_slice(part);
+                sink.path_len += part.len();
+            }
+            fs.write_file(sink.path(), &[])?;
+            Ok(sink)
+        }
+
+        /// Returns the path of the file, e.g. `SHOTS/SCRN0003.BMP`.
+        pub fn path(&self) -> &str {
+            core::str::from_utf8(&self.path[..self.path_len]).unwrap()
+        }
+
+        fn flush_buf(&mut self) -> Result<(), FatError<D::Error>> {
+            self.fs.append_file(self.path(), &self.buf[..self.filled])?;
+            self.filled = 0;
+            Ok(())
+        }
+    }
+
+    impl<D: BlockDevice, T: TimeSource> ScreenshotSink for FatSink<'_, D, T> {
+        type Error = FatError<D::Error>;
+
+        fn write(&mut self, mut data: &[u8]) -> Result<(), Self::Error> {
+            while !data.is_empty() {
+                let count = data.len().min(BLOCK_SIZE - self.filled);
+                self.buf[self.filled..self.filled + count].copy_from_slice(&data[..count]);
+                self.filled += count;
+                data = &data[count..];
+                if self.filled == BLOCK_SIZE {
+                    self.flush_buf()?;
+                }
+            }
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), Self::Error> {
+            if self.filled > 0 {
+                self.flush_buf()?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Returns `nnnn` if the short name is `SCRNnnnn.BMP`.
+    fn screenshot_number(base_name: &[u8], extension: &[u8]) -> Option<u16> {
+        let digits = base_name.strip_prefix(PREFIX)?;
+        if extension != EXTENSION || digits.len() != 4 {
+            return None;
+        }
+        digits.iter().try_fold(0, |number, &digit| {
+            digit
+                .is_ascii_digit()
+                .then(|| number * 10 + (digit - b'0') as u16)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{RamDisk, BLOCK_SIZE};
+
+    /// A frame where each pixel encodes its position
+    struct Pattern {
+        width: u16,
+        height: u16,
+        rows_read: Vec<u16>,
+    }
+
+    impl Pattern {
+        fn new(width: u16, height: u16) -> Self {
+            Self {
+                width,
+                height,
+                rows_read: Vec::new(),
+            }
+        }
+    }
+
+    fn pixel(x: u16, y: u16) -> u16 {
+        y << 8 | x
+    }
+
+    impl FrameSource for Pattern {
+        fn size(&self) -> (u16, u16) {
+            (self.width, self.height)
+        }
+
+        fn read_row(&mut self, y: u16, row: &mut [u16]) {
+            assert_eq!(row.len(), self.width as usize);
+            self.rows_read.push(y);
+            for (x, pixel_out) in row.iter_mut().enumerate() {
+                *pixel_out = pixel(x as u16, y);
+            }
+        }
+    }
+
+    /// Collects the image in memory
+    #[derive(Default)]
+    struct VecSink {
+        data: Vec<u8>,
+        writes: usize,
+        finished: bool,
+    }
+
+    impl ScreenshotSink for VecSink {
+        type Error = ();
+
+        fn write(&mut self, data: &[u8]) -> Result<(), ()> {
+            assert!(!self.finished);
+            self.data.extend_from_slice(data);
+            self.writes += 1;
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), ()> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    struct Held(&'static [Button]);
+
+    impl InputSource for Held {
+        fn button(&self, button: Button) -> bool {
+            self.0.contains(&button)
+        }
+
+        fn rotation_deg(&self) -> f32 {
+            0.0
+        }
+    }
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn headers() {
+        // 3 pixels are 6 bytes, padded to 8
+        assert_eq!(row_stride(3), 8);
+        assert_eq!(row_stride(4), 8);
+        assert_eq!(row_stride(320), 640);
+
+        let header = bmp_headers(3, 2);
+        assert_eq!(&header[0..2], b"BM");
+        assert_eq!(u32_at(&header, 2), 66 + 16);
+        assert_eq!(u32_at(&header, 2), bmp_size(3, 2));
+        assert_eq!(u32_at(&header, 6), 0);
+        assert_eq!(u32_at(&header, 10), 66);
+        assert_eq!(u32_at(&header, 14), 40);
+        assert_eq!(u32_at(&header, 18), 3);
+        // Positive height: bottom-up
+        assert_eq!(u32_at(&header, 22) as i32, 2);
+        assert_eq!(u16_at(&header, 26), 1);
+        assert_eq!(u16_at(&header, 28), 16);
+        assert_eq!(u32_at(&header, 30), BI_BITFIELDS);
+        assert_eq!(u32_at(&header, 34), 16);
+        assert_eq!(u32_at(&header, 54), 0xf800);
+        assert_eq!(u32_at(&header, 58), 0x07e0);
+        assert_eq!(u32_at(&header, 62), 0x001f);
+    }
+
+    #[test]
+    fn capture_rows() {
+        let mut source = Pattern::new(3, 4);
+        let mut sink = VecSink::default();
+        capture(&mut source, &mut sink).unwrap();
+        assert!(sink.finished);
+        assert_eq!(sink.data.len(), bmp_size(3, 4) as usize);
+        assert_eq!(source.rows_read, [3, 2, 1, 0]);
+
+        // The first row of the file is the bottom row of the frame
+        let rows: Vec<&[u8]> = sink.data[66..].chunks(8).collect();
+        for (i, row) in rows.iter().enumerate() {
+            let y = 3 - i as u16;
+            let pixels: Vec<u16> = (0..3).map(|x| u16_at(row, x * 2)).collect();
+            assert_eq!(pixels, [pixel(0, y), pixel(1, y), pixel(2, y)]);
+            assert_eq!(row[6..], [0, 0], "padding");
+        }
+
+        // An empty frame is only headers
+        let mut sink = VecSink::default();
+        capture(&mut Pattern::new(0, 0), &mut sink).unwrap();
+        assert_eq!(sink.data.len(), 66);
+        assert!(sink.finished);
+    }
+
+    #[test]
+    #[should_panic(expected = "frame too wide")]
+    fn capture_too_wide() {
+        capture(&mut Pattern::new(321, 1), &mut VecSink::default()).ok();
+    }
+
+    #[test]
+    fn chord() {
+        let mut trigger = ChordTrigger::new(&[Button::Mail, Button::Back]);
+        assert!(!trigger.update(&Held(&[])));
+        assert!(!trigger.update(&Held(&[Button::Mail])));
+        // Fires once per press
+        assert!(trigger.update(&Held(&[Button::Mail, Button::Back])));
+        assert!(!trigger.update(&Held(&[Button::Mail, Button::Back])));
+        assert!(!trigger.update(&Held(&[Button::Mail, Button::Back, Button::DialClick])));
+        // Releasing one of the buttons rearms it
+        assert!(!trigger.update(&Held(&[Button::Back])));
+        assert!(trigger.update(&Held(&[Button::Back, Button::Mail])));
+
+        let mut empty = ChordTrigger::new(&[]);
+        assert!(!empty.update(&Held(&[Button::Mail])));
+    }
+
+    #[test]
+    fn block_sink() {
+        let mut data = vec![0xffu8; 8 * BLOCK_SIZE];
+        let mut sink = BlockSink::new(RamDisk::new(&mut data), 2);
+        let image: Vec<u8> = (0..BLOCK_SIZE + 100).map(|i| i as u8).collect();
+        sink.write(&image[..10]).unwrap();
+        sink.write(&image[10..]).unwrap();
+        assert_eq!(sink.next_block(), 3);
+        sink.finish().unwrap();
+        assert_eq!(sink.next_block(), 4);
+
+        assert!(data[..2 * BLOCK_SIZE].iter().all(|&b| b == 0xff));
+        assert_eq!(data[2 * BLOCK_SIZE..][..image.len()], image);
+        // The last block is padded with zeros
+        assert!(data[2 * BLOCK_SIZE + image.len()..4 * BLOCK_SIZE]
+            .iter()
+            .all(|&b| b == 0));
+        assert!(data[4 * BLOCK_SIZE..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn block_sink_bounds() {
+        let mut data = vec![0u8; 2 * BLOCK_SIZE];
+        let mut sink = BlockSink::new(RamDisk::new(&mut data), 1);
+        sink.write(&[1; BLOCK_SIZE]).unwrap();
+        assert_eq!(
+            sink.write(&[1; BLOCK_SIZE]),
+            Err(crate::storage::Error::OutOfBounds)
+        );
+    }
+
+    #[cfg(feature = "embedded-sdmmc")]
+    #[test]
+    fn fat_sink() {
+        use crate::storage::fat::tests::{image, FatKind};
+        use crate::storage::fat::{FatFs, NoClock};
+
+        let mut volume = image(FatKind::Fat16);
+        let fs = FatFs::mount(RamDisk::new(&mut volume), NoClock).unwrap();
+        let mut sink = FatSink::create(&fs, "").unwrap();
+        assert_eq!(sink.path(), "SCRN0000.BMP");
+        capture(&mut Pattern::new(20, 30), &mut sink).unwrap();
+        let mut sink = FatSink::create(&fs, "/LOGS/").unwrap();
+        assert_eq!(sink.path(), "LOGS/SCRN0000.BMP");
+        capture(&mut Pattern::new(3, 4), &mut sink).unwrap();
+
+        // Numbered after the highest one, even with gaps
+        fs.write_file("SCRN0041.BMP", b"").unwrap();
+        fs.write_file("SCRN99.BMP", b"").unwrap();
+        fs.write_file("SCRN0050.TXT", b"").unwrap();
+        assert_eq!(FatSink::create(&fs, "").unwrap().path(), "SCRN0042.BMP");
+        fs.write_file("SCRN9999.BMP", b"").unwrap();
+        assert!(matches!(
+            FatSink::create(&fs, ""),
+            Err(embedded_sdmmc::Error::FileAlreadyExists)
+        ));
+        assert!(FatSink::create(&fs, "MISSING").is_err());
+
+        let mut expected = VecSink::default();
+        capture(&mut Pattern::new(20, 30), &mut expected).unwrap();
+        let mut file = vec![0; 4096];
+        let len = fs.read_file("SCRN0000.BMP", 0, &mut file).unwrap();
+        assert_eq!(file[..len], expected.data);
+        assert_eq!(len, bmp_size(20, 30) as usize);
+        assert_eq!(
+            fs.metadata("LOGS/SCRN0000.BMP").unwrap().size,
+            bmp_size(3, 4)
+        );
+        fs.unmount().unwrap();
+    }
+}
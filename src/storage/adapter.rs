@@ -0,0 +1,157 @@
+//! Adapters between [`BlockDevice`] and the `embedded-sdmmc` crate (feature `embedded-sdmmc`).
+//!
+//! ```no_run
+//! # use alarmo::storage::{adapter::AsEmbeddedSdmmc, EmmcStorage};
+//! # struct Clock;
+//! # impl embedded_sdmmc::TimeSource for Clock {
+//! #     fn get_timestamp(&self) -> embedded_sdmmc::Timestamp { unimplemented!() }
+//! # }
+//! # fn run(emmc: EmmcStorage) {
+//! let volumes = embedded_sdmmc::VolumeManager::new(AsEmbeddedSdmmc::new(emmc), Clock);
+//! # }
+//! ```
+
+use super::{BlockDevice, Error, BLOCK_SIZE};
+use core::cell::RefCell;
+use embedded_sdmmc::{Block, BlockCount, BlockIdx};
+
+/// Exposes a [`BlockDevice`] as an [`embedded_sdmmc::BlockDevice`].
+pub struct AsEmbeddedSdmmc<D> {
+    // embedded-sdmmc accesses the device through shared references
+    device: RefCell<D>,
+}
+
+impl<D: BlockDevice> AsEmbeddedSdmmc<D> {
+    pub fn new(device: D) -> Self {
+        Self {
+            device: RefCell::new(device),
+        }
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device.into_inner()
+    }
+}
+
+impl<D: BlockDevice> embedded_sdmmc::BlockDevice for AsEmbeddedSdmmc<D> {
+    type Error = Error<D::Error>;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        let mut device = self.device.borrow_mut();
+        for (address, block) in (start_block_idx.0..).zip(blocks) {
+            device.read(address, &mut block.contents)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        let mut device = self.device.borrow_mut();
+        for (address, block) in (start_block_idx.0..).zip(blocks) {
+            device.write(address, &block.contents)?;
+        }
+        device.flush()
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        Ok(BlockCount(self.device.borrow().block_count()))
+    }
+}
+
+/// Exposes an [`embedded_sdmmc::BlockDevice`], e.g. its SD card driver, as a [`BlockDevice`].
+pub struct FromEmbeddedSdmmc<D> {
+    device: D,
+    block_count: u32,
+}
+
+impl<D: embedded_sdmmc::BlockDevice> FromEmbeddedSdmmc<D> {
+    pub fn new(device: D) -> Result<Self, D::Error> {
+        let block_count = device.num_blocks()?.0;
+        Ok(Self {
+            device,
+            block_count,
+        })
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: embedded_sdmmc::BlockDevice> BlockDevice for FromEmbeddedSdmmc<D> {
+    type Error = D::Error;
+
+    fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    fn read(&mut self, start: u32, buf: &mut [u8]) -> Result<(), Error<D::Error>> {
+        super::check_access(self.block_count, start, buf.len())?;
+        let mut block = [Block::new()];
+        for (address, chunk) in (start..).zip(buf.chunks_exact_mut(BLOCK_SIZE)) {
+            self.device
+                .read(&mut block, BlockIdx(address))
+                .map_err(Error::Device)?;
+            chunk.copy_from_slice(&block[0].contents);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, start: u32, buf: &[u8]) -> Result<(), Error<D::Error>> {
+        super::check_access(self.block_count, start, buf.len())?;
+        let mut block = [Block::new()];
+        for (address, chunk) in (start..).zip(buf.chunks_exact(BLOCK_SIZE)) {
+            block[0].contents.copy_from_slice(chunk);
+            self.device
+                .write(&block, BlockIdx(address))
+                .map_err(Error::Device)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error<D::Error>> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::RamDisk;
+    use embedded_sdmmc::BlockDevice as _;
+
+    #[test]
+    fn round_trip() {
+        let mut data = vec![0u8; 4 * BLOCK_SIZE];
+        {
+            // Both adapters stacked on a RAM disk
+            let sdmmc = AsEmbeddedSdmmc::new(RamDisk::new(&mut data));
+            assert_eq!(sdmmc.num_blocks().unwrap(), BlockCount(4));
+            let mut disk = FromEmbeddedSdmmc::new(sdmmc).unwrap();
+            assert_eq!(disk.block_count(), 4);
+
+            let blocks: Vec<u8> = (0..2 * BLOCK_SIZE).map(|i| (i / 3) as u8).collect();
+            disk.write(2, &blocks).unwrap();
+            let mut buf = vec![0u8; 2 * BLOCK_SIZE];
+            disk.read(2, &mut buf).unwrap();
+            assert_eq!(buf, blocks);
+        }
+        assert_eq!(data[2 * BLOCK_SIZE + 3], 1);
+    }
+
+    #[test]
+    fn bounds() {
+        let mut data = vec![0u8; 4 * BLOCK_SIZE];
+        let sdmmc = AsEmbeddedSdmmc::new(RamDisk::new(&mut data));
+        // Errors from the wrapped device are passed through
+        let mut blocks = [Block::new(), Block::new()];
+        assert_eq!(
+            sdmmc.read(&mut blocks, BlockIdx(3)),
+            Err(Error::OutOfBounds)
+        );
+
+        let mut disk = FromEmbeddedSdmmc::new(sdmmc).unwrap();
+        let mut buf = [0u8; BLOCK_SIZE];
+        assert_eq!(disk.read(4, &mut buf), Err(Error::OutOfBounds));
+        assert_eq!(disk.write(0, &buf[..10]), Err(Error::BadLength));
+    }
+}
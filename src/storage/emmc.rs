@@ -0,0 +1,290 @@
+use super::guard::{WriteGate, WritePolicy};
+use super::{check_access, BlockDevice, Error, BLOCK_SIZE};
+use crate::pac::sdmmc::{calibrate_sampling_delay, set_sampling_phase};
+use stm32h7xx_hal::pac::SDMMC2;
+use stm32h7xx_hal::sdmmc::{self, Buswidth, Emmc, EmmcSignaling, Sdmmc};
+use stm32h7xx_hal::time::Hertz;
+
+/// Highest bus frequency in the default speed mode, faster buses use the high speed mode
+const MAX_DEFAULT_SPEED: u32 = 26_000_000;
+/// Number of blocks read at once by the bus tests
+const TEST_BLOCKS: usize = 4;
+
+/// Width of the eMMC data bus, selected by
+/// [`AlarmoOptions::emmc_bus_width`](crate::AlarmoOptions::emmc_bus_width).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum EmmcBusWidth {
+    /// D0 to D3
+    #[default]
+    Four,
+    /// D0 to D7, twice the throughput at the same clock
+    Eight,
+}
+
+impl From<EmmcBusWidth> for Buswidth {
+    fn from(width: EmmcBusWidth) -> Self {
+        match width {
+            EmmcBusWidth::Four => Buswidth::Four,
+            EmmcBusWidth::Eight => Buswidth::Eight,
+        }
+    }
+}
+
+/// Result of [`EmmcStorage::verify_bus_width`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct BusTest {
+    /// Number of blocks compared
+    pub blocks: u32,
+    /// Blocks that were read differently on the 8-bit bus
+    pub mismatches: u32,
+    /// Blocks that could not be read on the 8-bit bus, e.g. because of CRC errors
+    pub read_errors: u32,
+    /// The first block that was mismatched or couldn't be read
+    pub first_failure: Option<u32>,
+}
+
+impl BusTest {
+    pub fn passed(&self) -> bool {
+        self.mismatches == 0 && self.read_errors == 0
+    }
+
+    fn record(&mut self, block: u32, read: bool, matched: bool) {
+        self.blocks += 1;
+        match (read, matched) {
+            (false, _) => self.read_errors += 1,
+            (true, false) => self.mismatches += 1,
+            (true, true) => return,
+        }
+        self.first_failure.get_or_insert(block);
+    }
+}
+
+/// The eMMC as a [`BlockDevice`].
+///
+/// Every write goes through a [`WriteGate`], which starts out locked and protecting both copies
+/// of the GPT: see [`write_gate_mut`](Self::write_gate_mut) and the [`guard`](super::guard)
+/// module. Allowed writes go straight to the eMMC, so [`flush`](BlockDevice::flush) has nothing
+/// to do.
+pub struct EmmcStorage {
+    emmc: Sdmmc<SDMMC2, Emmc>,
+    block_count: u32,
+    gate: WriteGate<'static>,
+}
+
+impl EmmcStorage {
+    /// Wraps the eMMC, which must already be initialized with
+    /// [`Sdmmc::init`](stm32h7xx_hal::sdmmc::Sdmmc::init).
+    pub fn new(emmc: Sdmmc<SDMMC2, Emmc>) -> Result<Self, sdmmc::Error> {
+        // The HAL reads EXT_CSD with the wrong byte order
+        let block_count = emmc.card()?.ext_csd.sector_count().swap_bytes();
+        Ok(Self {
+            emmc,
+            block_count,
+            gate: WriteGate::new(WritePolicy::new(&[]).with_gpt(block_count)),
+        })
+    }
+
+    pub fn write_gate(&self) -> &WriteGate<'static> {
+        &self.gate
+    }
+
+    /// Gives access to the write gate, e.g. to unlock it or to protect more blocks. Its policy
+    /// uses block addresses on the whole eMMC.
+    pub fn write_gate_mut(&mut self) -> &mut WriteGate<'static> {
+        &mut self.gate
+    }
+
+    /// Returns the eMMC. This is the only way to access it directly, since writes made through
+    /// it bypass the write gate.
+    pub fn into_inner(self) -> Sdmmc<SDMMC2, Emmc> {
+        self.emmc
+    }
+
+    /// The width the data bus currently runs at.
+    pub fn bus_width(&self) -> EmmcBusWidth {
+        match self.emmc.inner().clkcr.read().widbus().bits() {
+            2 => EmmcBusWidth::Eight,
+            _ => EmmcBusWidth::Four,
+        }
+    }
+
+    /// Switches the eMMC and the host to another bus width. `freq` is the bus frequency, as
+    /// passed to [`Sdmmc::init`](stm32h7xx_hal::sdmmc::Sdmmc::init).
+    ///
+    /// The 8-bit bus only works if it was selected in [`AlarmoOptions`](crate::AlarmoOptions),
+    /// which sets up the pins of D4 to D7.
+    pub fn set_bus_width(
+        &mut self,
+        width: EmmcBusWidth,
+        freq: impl Into<Hertz>,
+    ) -> Result<(), sdmmc::Error> {
+        let freq = freq.into();
+        let signaling = match freq.raw() {
+            0..=MAX_DEFAULT_SPEED => EmmcSignaling::DefaultSpeed,
+            _ => EmmcSignaling::HighSpeed,
+        };
+        self.emmc.set_bus(width.into(), freq, signaling)
+    }
+
+    /// Changes the bus frequency, keeping the current bus width. See
+    /// [`set_bus_width`](Self::set_bus_width).
+    pub fn set_bus_frequency(&mut self, freq: impl Into<Hertz>) -> Result<(), sdmmc::Error> {
+        self.set_bus_width(self.bus_width(), freq)
+    }
+
+    /// Reads `block_count` blocks starting at `start` on the 4-bit bus, then on the 8-bit bus,
+    /// and compares them, to check the wiring and timing of D4 to D7. `freq` is the bus
+    /// frequency, as for [`set_bus_width`](Self::set_bus_width).
+    ///
+    /// Errors on the 8-bit bus are counted in the result, other errors are returned. The bus
+    /// width is restored afterwards, and nothing is written.
+    pub fn verify_bus_width(
+        &mut self,
+        start: u32,
+        block_count: u32,
+        freq: impl Into<Hertz>,
+    ) -> Result<BusTest, Error<sdmmc::Error>> {
+        let freq = freq.into();
+        if !start
+            .checked_add(block_count)
+            .is_some_and(|end| end <= self.block_count)
+        {
+            return Err(Error::OutOfBounds);
+        }
+
+        let width = self.bus_width();
+        let result = self.compare_bus_widths(start, block_count, freq);
+        self.set_bus_width(width, freq).map_err(Error::Device)?;
+        result
+    }
+
+    /// Tunes the phase at which the host samples the data it receives, so the bus works at high
+    /// frequencies. The blocks at `start` are read with every phase, and compared to a reference
+    /// read on the 4-bit bus without tuning. `freq` is the bus frequency, as for
+    /// [`set_bus_width`](Self::set_bus_width).
+    ///
+    /// Returns the selected phase, in the middle of the longest range of phases that read
+    /// correctly, or `None` if no phase did, in which case tuning is disabled again.
+    pub fn tune_sampling(
+        &mut self,
+        start: u32,
+        freq: impl Into<Hertz>,
+    ) -> Result<Option<u8>, Error<sdmmc::Error>> {
+        let freq = freq.into();
+        check_access::<sdmmc::Error>(self.block_count, start, TEST_BLOCKS * BLOCK_SIZE)?;
+        set_sampling_phase(self.emmc.inner_mut(), None);
+
+        let width = self.bus_width();
+        let mut reference = [0u8; TEST_BLOCKS * BLOCK_SIZE];
+        let read = self.read_with_width(EmmcBusWidth::Four, freq, start, &mut reference);
+        self.set_bus_width(width, freq).map_err(Error::Device)?;
+        read?;
+
+        let Some(phases) = calibrate_sampling_delay(self.emmc.inner_mut()) else {
+            set_sampling_phase(self.emmc.inner_mut(), None);
+            return Ok(None);
+        };
+        // (first phase, length) of the longest range of good phases, and of the current one
+        let mut best = (0, 0);
+        let mut current = (0, 0);
+        let mut data = [0u8; TEST_BLOCKS * BLOCK_SIZE];
+        for phase in 0..phases {
+            set_sampling_phase(self.emmc.inner_mut(), Some(phase));
+            let good = match self.read(start, &mut data) {
+                Ok(()) => data == reference,
+                Err(Error::Device(_)) => false,
+                Err(e) => return Err(e),
+            };
+            current = match good {
+                true if current.1 == 0 => (phase, 1),
+                true => (current.0, current.1 + 1),
+                false => (0, 0),
+            };
+            if current.1 > best.1 {
+                best = current;
+            }
+        }
+
+        let phase = match best {
+            (_, 0) => None,
+            (first, len) => Some(first + len / 2),
+        };
+        set_sampling_phase(self.emmc.inner_mut(), phase);
+        Ok(phase)
+    }
+
+    fn compare_bus_widths(
+        &mut self,
+        start: u32,
+        block_count: u32,
+        freq: Hertz,
+    ) -> Result<BusTest, Error<sdmmc::Error>> {
+        let mut test = BusTest::default();
+        let mut reference = [0u8; TEST_BLOCKS * BLOCK_SIZE];
+        let mut data = [0u8; TEST_BLOCKS * BLOCK_SIZE];
+        let end = start + block_count;
+        for chunk in (start..end).step_by(TEST_BLOCKS) {
+            let len = (end - chunk).min(TEST_BLOCKS as u32) as usize * BLOCK_SIZE;
+            let (reference, data) = (&mut reference[..len], &mut data[..len]);
+            self.read_with_width(EmmcBusWidth::Four, freq, chunk, reference)?;
+            let read = match self.read_with_width(EmmcBusWidth::Eight, freq, chunk, data) {
+                Ok(()) => true,
+                Err(Error::Device(_)) => false,
+                Err(e) => return Err(e),
+            };
+            let blocks = reference
+                .chunks_exact(BLOCK_SIZE)
+                .zip(data.chunks_exact(BLOCK_SIZE));
+            for (block, (expected, actual)) in (chunk..).zip(blocks) {
+                test.record(block, read, expected == actual);
+            }
+        }
+        Ok(test)
+    }
+
+    fn read_with_width(
+        &mut self,
+        width: EmmcBusWidth,
+        freq: Hertz,
+        start: u32,
+        buf: &mut [u8],
+    ) -> Result<(), Error<sdmmc::Error>> {
+        if self.bus_width() != width {
+            self.set_bus_width(width, freq).map_err(Error::Device)?;
+        }
+        self.read(start, buf)
+    }
+}
+
+impl BlockDevice for EmmcStorage {
+    type Error = sdmmc::Error;
+
+    fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    fn read(&mut self, start: u32, buf: &mut [u8]) -> Result<(), Error<sdmmc::Error>> {
+        match check_access(self.block_count, start, buf.len())? {
+            0 => Ok(()),
+            _ => self.emmc.read_blocks(start, buf).map_err(Error::Device),
+        }
+    }
+
+    fn write(&mut self, start: u32, buf: &[u8]) -> Result<(), Error<sdmmc::Error>> {
+        let block_count = check_access(self.block_count, start, buf.len())?;
+        if !self.gate.admit(start, block_count)? {
+            return Ok(());
+        }
+        for (address, block) in (start..).zip(buf.chunks_exact(BLOCK_SIZE)) {
+            let block = block.try_into().unwrap();
+            self.emmc
+                .write_block(address, block)
+                .map_err(Error::Device)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error<sdmmc::Error>> {
+        Ok(())
+    }
+}
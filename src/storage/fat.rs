@@ -0,0 +1,740 @@
+//! FAT16/FAT32 file systems (feature `embedded-sdmmc`).
+//!
+//! [`FatFs`] mounts a FAT volume from any [`BlockDevice`], usually a partition of the eMMC, and
+//! gives access to files by path:
+//!
+//! ```no_run
+//! # use alarmo::storage::fat::{FatFs, NoClock};
+//! # use alarmo::storage::partition::PartitionTable;
+//! # use alarmo::storage::EmmcStorage;
+//! # fn run(emmc: &mut EmmcStorage) {
+//! let table = PartitionTable::read(emmc).unwrap();
+//! let partition = table.find("data").unwrap().open(emmc).unwrap();
+//! let fs = FatFs::mount(partition, NoClock).unwrap();
+//! fs.append_file("LOGS/BOOT.TXT", b"booted\n").unwrap();
+//! let mut config = [0; 256];
+//! let len = fs.read_file("CONFIG.INI", 0, &mut config).unwrap();
+//! let partition = fs.unmount().unwrap();
+//! # }
+//! ```
+//!
+//! The file system is implemented by the `embedded-sdmmc` crate, so it has the same limits:
+//! files and directories are opened by their 8.3 short names (long names are only reported by
+//! [`FatFs::list_dir`]), and files are limited to 4 GiB.
+//!
+//! Every method closes the files and directories it opens, and writes go straight to the
+//! device, so the file system is consistent between calls. [`FatFs::unmount`] also updates
+//! the free cluster count of FAT32 volumes and flushes the device: call it before writing to
+//! the device directly or turning off the power.
+
+use super::adapter::AsEmbeddedSdmmc;
+use super::{BlockDevice, Error, BLOCK_SIZE};
+use embedded_sdmmc::{
+    DirEntry, Mode, RawDirectory, RawFile, RawVolume, TimeSource, Timestamp, VolumeIdx,
+    VolumeManager,
+};
+
+/// Maximum length of a long file name reported by [`FatFs::list_dir`], in bytes of UTF-8
+pub const MAX_LONG_NAME_LEN: usize = 255;
+
+/// MBR partition type for the volume, FAT32 with LBA. FAT16 volumes are detected from their
+/// boot sector regardless.
+const PARTITION_TYPE_FAT32_LBA: u8 = 0x0c;
+
+/// Volume manager for one volume, with one open file in a directory, or two nested directories
+type Volumes<D, T> = VolumeManager<AsEmbeddedSdmmc<SingleVolume<D>>, T, 2, 1, 1>;
+
+/// Errors from the file system, which wrap the errors of the device.
+pub type FatError<E> = embedded_sdmmc::Error<Error<E>>;
+
+/// Time source for devices without a real-time clock, which dates every change to the FAT epoch
+/// (1980-01-01 00:00:00).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoClock;
+
+impl TimeSource for NoClock {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp {
+            year_since_1970: 10,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+        }
+    }
+}
+
+/// A mounted FAT volume, see the [module documentation](self).
+///
+/// Paths are separated by `/`, and relative to the root directory.
+pub struct FatFs<D: BlockDevice, T: TimeSource> {
+    volumes: Volumes<D, T>,
+    volume: RawVolume,
+}
+
+impl<D: BlockDevice, T: TimeSource> FatFs<D, T> {
+    /// Mounts the FAT volume that covers the whole of `device`, e.g. a partition. Without a
+    /// real-time clock, use [`NoClock`] as `clock`.
+    pub fn mount(device: D, clock: T) -> Result<Self, FatError<D::Error>> {
+        let volumes =
+            VolumeManager::new_with_limits(AsEmbeddedSdmmc::new(SingleVolume { device }), clock, 0);
+        let volume = volumes.open_raw_volume(VolumeIdx(0))?;
+        Ok(Self { volumes, volume })
+    }
+
+    /// Calls `f` with each entry of the directory at `path`, and its long name if it has one.
+    /// The root directory is `""` or `"/"`.
+    ///
+    /// `f` can't access the file system.
+    pub fn list_dir(
+        &self,
+        path: &str,
+        mut f: impl FnMut(&DirEntry, Option<&str>),
+    ) -> Result<(), FatError<D::Error>> {
+        let dir = self.open_dir(path.split('/'))?;
+        let mut name = [0; MAX_LONG_NAME_LEN];
+        let mut lfn = embedded_sdmmc::LfnBuffer::new(&mut name);
+        let result = self
+            .volumes
+            .iterate_dir_lfn(dir, &mut lfn, |entry, long_name| {
+                if !entry.attributes.is_volume() {
+                    f(entry, long_name)
+                }
+            });
+        self.close_dir(dir, result)
+    }
+
+    /// Returns the directory entry of the file or directory at `path`.
+    pub fn metadata(&self, path: &str) -> Result<DirEntry, FatError<D::Error>> {
+        let (parent, name) = self.open_parent(path)?;
+        let result = self.volumes.find_directory_entry(parent, name);
+        self.close_dir(parent, result)
+    }
+
+    /// Reads the file at `path`, starting at byte `offset`, into `buf`. Returns the number of
+    /// bytes read, which is less than the length of `buf` at the end of the file.
+    pub fn read_file(
+        &self,
+        path: &str,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<usize, FatError<D::Error>> {
+        self.with_file(path, Mode::ReadOnly, |volumes, file| {
+            if offset >= volumes.file_length(file)? {
+                return Ok(0);
+            }
+            volumes.file_seek_from_start(file, offset)?;
+            volumes.read(file, buf)
+        })
+    }
+
+    /// Replaces the contents of the file at `path` with `data`, creating the file if needed.
+    pub fn write_file(&self, path: &str, data: &[u8]) -> Result<(), FatError<D::Error>> {
+        self.with_file(path, Mode::ReadWriteCreateOrTruncate, |volumes, file| {
+            volumes.write(file, data)
+        })
+    }
+
+    /// Appends `data` to the file at `path`, creating the file if needed.
+    pub fn append_file(&self, path: &str, data: &[u8]) -> Result<(), FatError<D::Error>> {
+        self.with_file(path, Mode::ReadWriteCreateOrAppend, |volumes, file| {
+            volumes.write(file, data)
+        })
+    }
+
+    /// Deletes the file at `path`. Directories can't be deleted.
+    pub fn remove_file(&self, path: &str) -> Result<(), FatError<D::Error>> {
+        let (parent, name) = self.open_parent(path)?;
+        let result = self.volumes.delete_file_in_dir(parent, name);
+        self.close_dir(parent, result)
+    }
+
+    /// Creates a directory at `path`. Its parent directory must exist.
+    pub fn create_dir(&self, path: &str) -> Result<(), FatError<D::Error>> {
+        let (parent, name) = self.open_parent(path)?;
+        let result = self.volumes.make_dir_in_dir(parent, name);
+        self.close_dir(parent, result)
+    }
+
+    /// Unmounts the file system, and returns the device once all changes reached it.
+    pub fn unmount(self) -> Result<D, FatError<D::Error>> {
+        self.volumes.close_volume(self.volume)?;
+        let (device, _) = self.volumes.free();
+        let mut device = device.into_inner().device;
+        device.flush().map_err(embedded_sdmmc::Error::DeviceError)?;
+        Ok(device)
+    }
+
+    /// Opens the directory at the end of `components`, skipping empty ones.
+    fn open_dir<'a>(
+        &self,
+        components: impl Iterator<Item = &'a str>,
+    ) -> Result<RawDirectory, FatError<D::Error>> {
+        let mut dir = self.volumes.open_root_dir(self.volume)?;
+        for component in components.filter(|c| !c.is_empty()) {
+            let child = self.volumes.open_dir(dir, component);
+            dir = self.close_dir(dir, child)?;
+        }
+        Ok(dir)
+    }
+
+    /// Opens the parent directory of `path`, and returns it with the last component of `path`.
+    fn open_parent<'a>(
+        &self,
+        path: &'a str,
+    ) -> Result<(RawDirectory, &'a str), FatError<D::Error>> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        Ok((self.open_dir(parent.split('/'))?, name))
+    }
+
+    /// Closes `dir`, and returns `result`, unless closing fails.
+    fn close_dir<R>(
+        &self,
+        dir: RawDirectory,
+        result: Result<R, FatError<D::Error>>,
+    ) -> Result<R, FatError<D::Error>> {
+        let closed = self.volumes.close_dir(dir);
+        let result = result?;
+        closed.map(|()| result)
+    }
+
+    fn with_file<R>(
+        &self,
+        path: &str,
+        mode: Mode,
+        f: impl FnOnce(&Volumes<D, T>, RawFile) -> Result<R, FatError<D::Error>>,
+    ) -> Result<R, FatError<D::Error>> {
+        let (parent, name) = self.open_parent(path)?;
+        let file = self.volumes.open_file_in_dir(parent, name, mode);
+        let file = self.close_dir(parent, file)?;
+        let result = f(&self.volumes, file);
+        // Closing also writes the new size of the file
+        let closed = self.volumes.close_file(file);
+        let result = result?;
+        closed.map(|()| result)
+    }
+}
+
+/// Shows `device` behind an MBR with a single FAT partition, because `embedded-sdmmc` only opens
+/// volumes listed in an MBR, and partitions may come from a GPT.
+struct SingleVolume<D> {
+    device: D,
+}
+
+impl<D: BlockDevice> BlockDevice for SingleVolume<D> {
+    type Error = D::Error;
+
+    fn block_count(&self) -> u32 {
+        self.device.block_count().saturating_add(1)
+    }
+
+    fn read(&mut self, start: u32, buf: &mut [u8]) -> Result<(), Error<D::Error>> {
+        let buf = match start {
+            0 if buf.len() >= BLOCK_SIZE => {
+                let (mbr, rest) = buf.split_at_mut(BLOCK_SIZE);
+                self.write_mbr(mbr);
+                rest
+            }
+            _ => buf,
+        };
+        self.device.read(start.saturating_sub(1), buf)
+    }
+
+    fn write(&mut self, start: u32, buf: &[u8]) -> Result<(), Error<D::Error>> {
+        match start {
+            // The MBR only exists in memory
+            0 => Err(Error::OutOfBounds),
+            _ => self.device.write(start - 1, buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error<D::Error>> {
+        self.device.flush()
+    }
+}
+
+impl<D: BlockDevice> SingleVolume<D> {
+    fn write_mbr(&self, block: &mut [u8]) {
+        block.fill(0);
+        let entry = &mut block[446..462];
+        entry[4] = PARTITION_TYPE_FAT32_LBA;
+        entry[8..12].copy_from_slice(&1u32.to_le_bytes());
+        entry[12..16].copy_from_slice(&self.device.block_count().to_le_bytes());
+        block[510..512].copy_from_slice(&[0x55, 0xaa]);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::storage::partition::Partition;
+    use crate::storage::RamDisk;
+
+    /// Contents of `README.TXT`, longer than a cluster
+    const README: &[u8; 700] = &[b'r'; 700];
+    const LONG_NAME: &str = "Long file name.txt";
+    /// Contents of the file with a long name
+    const LONG: &[u8] = b"long name contents";
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub(crate) enum FatKind {
+        Fat16,
+        Fat32,
+    }
+
+    /// Location of a directory's entries: the fixed FAT16 root directory, or a cluster
+    #[derive(Copy, Clone, PartialEq)]
+    enum Dir {
+        Root,
+        Cluster(u32),
+    }
+
+    /// Formats a volume, with one sector per cluster, following the FAT specification. The
+    /// number of clusters decides the FAT type, so FAT32 volumes are at least 32 MiB.
+    struct Image {
+        kind: FatKind,
+        data: Vec<u8>,
+        fat_start: usize,
+        fat_len: usize,
+        root_start: usize,
+        data_start: usize,
+        clusters: u32,
+        next_cluster: u32,
+        /// Next free entry of each directory, by first cluster (0 for the FAT16 root)
+        dir_entries: Vec<(u32, usize)>,
+    }
+
+    impl Image {
+        fn new(kind: FatKind) -> Self {
+            let (reserved, fat_len, root_len, clusters) = match kind {
+                // 512 root entries
+                FatKind::Fat16 => (1, 17, 32, 4200),
+                FatKind::Fat32 => (32, 516, 0, 66000),
+            };
+            let total = reserved + 2 * fat_len + root_len + clusters;
+            let mut image = Self {
+                kind,
+                data: vec![0; total * BLOCK_SIZE],
+                fat_start: reserved,
+                fat_len,
+                root_start: reserved + 2 * fat_len,
+                data_start: reserved + 2 * fat_len + root_len,
+                clusters: clusters as u32,
+                next_cluster: 2,
+                dir_entries: Vec::new(),
+            };
+
+            let boot = &mut image.data[..BLOCK_SIZE];
+            boot[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+            boot[3..11].copy_from_slice(b"MSWIN4.1");
+            boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+            boot[13] = 1;
+            boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+            boot[16] = 2;
+            boot[21] = 0xf8;
+            boot[32..36].copy_from_slice(&(total as u32).to_le_bytes());
+            boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+            let ext = match kind {
+                FatKind::Fat16 => {
+                    boot[17..19].copy_from_slice(&512u16.to_le_bytes());
+                    boot[22..24].copy_from_slice(&(fat_len as u16).to_le_bytes());
+                    boot[54..62].copy_from_slice(b"FAT16   ");
+                    36
+                }
+                FatKind::Fat32 => {
+                    boot[36..40].copy_from_slice(&(fat_len as u32).to_le_bytes());
+                    // Root directory cluster, FSInfo and backup boot sectors
+                    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+                    boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+                    boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+                    boot[82..90].copy_from_slice(b"FAT32   ");
+                    64
+                }
+            };
+            boot[ext] = 0x80;
+            boot[ext + 2] = 0x29;
+            boot[ext + 3..ext + 7].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+            boot[ext + 7..ext + 18].copy_from_slice(b"ALARMO     ");
+
+            image.set_fat(0, 0x0fff_fff8);
+            image.set_fat(1, 0x0fff_ffff);
+            if kind == FatKind::Fat32 {
+                let root = image.allocate(1);
+                assert_eq!(root, 2);
+            }
+
+            let mut label = [0; 32];
+            label[..11].copy_from_slice(b"ALARMO     ");
+            label[11] = 0x08;
+            image.push_entry(image.root(), &label);
+            image
+        }
+
+        /// Writes the FSInfo sector of FAT32 volumes, and returns the volume.
+        fn finish(mut self) -> Vec<u8> {
+            if self.kind == FatKind::Fat32 {
+                let free = self.free_clusters();
+                let info = &mut self.data[BLOCK_SIZE..2 * BLOCK_SIZE];
+                info[..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+                info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+                info[488..492].copy_from_slice(&free.to_le_bytes());
+                info[492..496].copy_from_slice(&self.next_cluster.to_le_bytes());
+                info[508..512].copy_from_slice(&0xaa55_0000u32.to_le_bytes());
+            }
+            self.data
+        }
+
+        fn free_clusters(&self) -> u32 {
+            self.clusters - (self.next_cluster - 2)
+        }
+
+        fn root(&self) -> Dir {
+            match self.kind {
+                FatKind::Fat16 => Dir::Root,
+                FatKind::Fat32 => Dir::Cluster(2),
+            }
+        }
+
+        fn set_fat(&mut self, cluster: u32, value: u32) {
+            for copy in 0..2 {
+                let fat = (self.fat_start + copy * self.fat_len) * BLOCK_SIZE;
+                match self.kind {
+                    FatKind::Fat16 => {
+                        let i = fat + cluster as usize * 2;
+                        self.data[i..i + 2].copy_from_slice(&(value as u16).to_le_bytes());
+                    }
+                    FatKind::Fat32 => {
+                        let i = fat + cluster as usize * 4;
+                        self.data[i..i + 4].copy_from_slice(&value.to_le_bytes());
+                    }
+                }
+            }
+        }
+
+        /// Allocates a chain of `count` clusters, and returns the first one.
+        fn allocate(&mut self, count: u32) -> u32 {
+            let first = self.next_cluster;
+            for cluster in first..first + count {
+                let next = if cluster + 1 == first + count {
+                    0x0fff_ffff
+                } else {
+                    cluster + 1
+                };
+                self.set_fat(cluster, next);
+            }
+            self.next_cluster += count;
+            first
+        }
+
+        fn cluster_offset(&self, cluster: u32) -> usize {
+            (self.data_start + cluster as usize - 2) * BLOCK_SIZE
+        }
+
+        fn push_entry(&mut self, dir: Dir, entry: &[u8; 32]) {
+            let (key, base) = match dir {
+                Dir::Root => (0, self.root_start * BLOCK_SIZE),
+                Dir::Cluster(cluster) => (cluster, self.cluster_offset(cluster)),
+            };
+            let index = match self.dir_entries.iter_mut().find(|(c, _)| *c == key) {
+                Some((_, next)) => {
+                    *next += 1;
+                    *next - 1
+                }
+                None => {
+                    self.dir_entries.push((key, 1));
+                    0
+                }
+            };
+            // Directory clusters are a single sector
+            assert!(index < BLOCK_SIZE / 32 || matches!(dir, Dir::Root));
+            self.data[base + index * 32..][..32].copy_from_slice(entry);
+        }
+
+        fn short_entry(name: &[u8; 11], attributes: u8, cluster: u32, size: u32) -> [u8; 32] {
+            let mut entry = [0; 32];
+            entry[..11].copy_from_slice(name);
+            entry[11] = attributes;
+            // 1980-01-01
+            entry[16..18].copy_from_slice(&0x21u16.to_le_bytes());
+            entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+            entry[24..26].copy_from_slice(&0x21u16.to_le_bytes());
+            entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+            entry[28..32].copy_from_slice(&size.to_le_bytes());
+            entry
+        }
+
+        /// Adds a file with its short name, e.g. `b"README  TXT"`, and optionally a long name.
+        fn add_file(&mut self, dir: Dir, name: &[u8; 11], long_name: Option<&str>, data: &[u8]) {
+            let clusters = data.len().div_ceil(BLOCK_SIZE) as u32;
+            let cluster = if clusters == 0 {
+                0
+            } else {
+                self.allocate(clusters)
+            };
+            let offset = self.cluster_offset(cluster);
+            self.data[offset..offset + data.len()].copy_from_slice(data);
+
+            if let Some(long_name) = long_name {
+                let checksum = name
+                    .iter()
+                    .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c));
+                let mut units: Vec<u16> = long_name.encode_utf16().collect();
+                units.push(0);
+                units.resize(units.len().div_ceil(13) * 13, 0xffff);
+                let count = units.len() / 13;
+                for (i, part) in units.chunks(13).enumerate().rev() {
+                    let mut entry = [0; 32];
+                    entry[0] = (i as u8 + 1) | if i + 1 == count { 0x40 } else { 0 };
+                    entry[11] = 0x0f;
+                    entry[13] = checksum;
+                    let slots = (1..11).step_by(2).chain((14..26).step_by(2));
+                    for (slot, unit) in slots.chain((28..32).step_by(2)).zip(part) {
+                        entry[slot..slot + 2].copy_from_slice(&unit.to_le_bytes());
+                    }
+                    self.push_entry(dir, &entry);
+                }
+            }
+            let entry = Self::short_entry(name, 0x20, cluster, data.len() as u32);
+            self.push_entry(dir, &entry);
+        }
+
+        fn add_dir(&mut self, parent: Dir, name: &[u8; 11]) -> Dir {
+            let cluster = self.allocate(1);
+            let parent_cluster = match parent {
+                Dir::Root => 0,
+                Dir::Cluster(cluster) if self.root() == Dir::Cluster(cluster) => 0,
+                Dir::Cluster(cluster) => cluster,
+            };
+            self.push_entry(parent, &Self::short_entry(name, 0x10, cluster, 0));
+            let dir = Dir::Cluster(cluster);
+            self.push_entry(dir, &Self::short_entry(b".          ", 0x10, cluster, 0));
+            let dotdot = Self::short_entry(b"..         ", 0x10, parent_cluster, 0);
+            self.push_entry(dir, &dotdot);
+            dir
+        }
+    }
+
+    /// A volume with `README.TXT`, a file with a long name and `LOGS/OLD.LOG`.
+    pub(crate) fn image(kind: FatKind) -> Vec<u8> {
+        let mut image = Image::new(kind);
+        let root = image.root();
+        image.add_file(root, b"README  TXT", None, README);
+        image.add_file(root, b"LONGFI~1TXT", Some(LONG_NAME), LONG);
+        let logs = image.add_dir(root, b"LOGS       ");
+        image.add_file(logs, b"OLD     LOG", None, b"old\n");
+        image.finish()
+    }
+
+    /// Returns the free cluster count from the FSInfo sector of a FAT32 volume.
+    fn fsinfo_free(volume: &[u8]) -> u32 {
+        u32::from_le_bytes(volume[BLOCK_SIZE + 488..][..4].try_into().unwrap())
+    }
+
+    type Fs<'a> = FatFs<RamDisk<'a>, NoClock>;
+
+    fn entries(fs: &Fs, path: &str) -> Vec<(String, Option<String>, u32)> {
+        let mut entries = Vec::new();
+        fs.list_dir(path, |entry, long_name| {
+            entries.push((
+                entry.name.to_string(),
+                long_name.map(String::from),
+                entry.size,
+            ))
+        })
+        .unwrap();
+        entries
+    }
+
+    fn read_all<D: BlockDevice>(fs: &FatFs<D, NoClock>, path: &str) -> Vec<u8> {
+        let mut buf = vec![0; 4096];
+        let len = fs.read_file(path, 0, &mut buf).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    fn for_each_kind(test: impl Fn(FatKind, &mut [u8])) {
+        for kind in [FatKind::Fat16, FatKind::Fat32] {
+            test(kind, &mut image(kind));
+        }
+    }
+
+    #[test]
+    fn list() {
+        for_each_kind(|kind, data| {
+            let fs = FatFs::mount(RamDisk::new(data), NoClock).unwrap();
+            // The volume label is not listed
+            let root = vec![
+                ("README.TXT".into(), None, 700),
+                (
+                    "LONGFI~1.TXT".into(),
+                    Some(LONG_NAME.into()),
+                    LONG.len() as u32,
+                ),
+                ("LOGS".into(), None, 0),
+            ];
+            assert_eq!(entries(&fs, ""), root, "{kind:?}");
+            assert_eq!(entries(&fs, "/"), root, "{kind:?}");
+            let logs = entries(&fs, "LOGS/");
+            assert_eq!(logs[2], ("OLD.LOG".into(), None, 4), "{kind:?}");
+            assert!(fs.metadata("LOGS").unwrap().attributes.is_directory());
+            assert_eq!(fs.metadata("logs/old.log").unwrap().size, 4);
+            assert!(fs.list_dir("MISSING", |_, _| ()).is_err());
+            fs.unmount().unwrap();
+        });
+    }
+
+    #[test]
+    fn read() {
+        for_each_kind(|kind, data| {
+            let fs = FatFs::mount(RamDisk::new(data), NoClock).unwrap();
+            assert_eq!(read_all(&fs, "README.TXT"), README, "{kind:?}");
+            assert_eq!(read_all(&fs, "LONGFI~1.TXT"), LONG, "{kind:?}");
+            assert_eq!(read_all(&fs, "LOGS/OLD.LOG"), b"old\n", "{kind:?}");
+
+            // From the second cluster, and past the end
+            let mut buf = [0; 300];
+            assert_eq!(fs.read_file("README.TXT", 600, &mut buf).unwrap(), 100);
+            assert_eq!(fs.read_file("README.TXT", 700, &mut buf).unwrap(), 0);
+            assert_eq!(fs.read_file("README.TXT", 5000, &mut buf).unwrap(), 0);
+            assert!(fs.read_file("MISSING.TXT", 0, &mut buf).is_err());
+            fs.unmount().unwrap();
+        });
+    }
+
+    #[test]
+    fn write() {
+        for_each_kind(|kind, data| {
+            let fs = FatFs::mount(RamDisk::new(data), NoClock).unwrap();
+            let contents: Vec<u8> = (0..1500).map(|i| i as u8).collect();
+            fs.write_file("LOGS/NEW.BIN", &contents).unwrap();
+            assert_eq!(read_all(&fs, "LOGS/NEW.BIN"), contents, "{kind:?}");
+
+            // Overwriting truncates
+            fs.write_file("LOGS/NEW.BIN", b"short").unwrap();
+            assert_eq!(read_all(&fs, "LOGS/NEW.BIN"), b"short", "{kind:?}");
+            assert_eq!(fs.metadata("LOGS/NEW.BIN").unwrap().size, 5);
+
+            fs.create_dir("LOGS/SUB").unwrap();
+            fs.write_file("LOGS/SUB/A.TXT", b"a").unwrap();
+            assert_eq!(read_all(&fs, "LOGS/SUB/A.TXT"), b"a", "{kind:?}");
+
+            fs.remove_file("LOGS/OLD.LOG").unwrap();
+            assert!(fs.metadata("LOGS/OLD.LOG").is_err());
+            // The other files are untouched
+            assert_eq!(read_all(&fs, "README.TXT"), README, "{kind:?}");
+            fs.unmount().unwrap();
+        });
+    }
+
+    #[test]
+    fn append() {
+        for_each_kind(|kind, data| {
+            let fs = FatFs::mount(RamDisk::new(data), NoClock).unwrap();
+            fs.append_file("LOGS/OLD.LOG", b"new\n").unwrap();
+            assert_eq!(read_all(&fs, "LOGS/OLD.LOG"), b"old\nnew\n", "{kind:?}");
+
+            // Created on the first append, then grows past a cluster
+            let line = [b'x'; 100];
+            for _ in 0..7 {
+                fs.append_file("BOOT.TXT", &line).unwrap();
+            }
+            assert_eq!(read_all(&fs, "BOOT.TXT"), [b'x'; 700], "{kind:?}");
+            fs.unmount().unwrap();
+        });
+    }
+
+    #[test]
+    fn unmount() {
+        for_each_kind(|kind, data| {
+            let fs = FatFs::mount(RamDisk::new(data), NoClock).unwrap();
+            fs.write_file("SAVED.TXT", b"saved").unwrap();
+            let device = fs.unmount().unwrap();
+
+            // The changes are on the device, and it can be mounted again
+            let fs = FatFs::mount(device, NoClock).unwrap();
+            assert_eq!(read_all(&fs, "SAVED.TXT"), b"saved", "{kind:?}");
+            let device = fs.unmount().unwrap();
+
+            if kind == FatKind::Fat32 {
+                // 6 clusters were used by the image, and SAVED.TXT takes another one
+                assert_eq!(fsinfo_free(device.data()), 66000 - 7);
+            }
+        });
+    }
+
+    #[test]
+    fn not_fat() {
+        let mut data = vec![0; 64 * BLOCK_SIZE];
+        assert!(FatFs::mount(RamDisk::new(&mut data), NoClock).is_err());
+    }
+
+    #[test]
+    fn partition_offset() {
+        // The volume starts at block 8 of the disk, and its blocks must be found relative to
+        // the partition, behind the MBR of SingleVolume
+        let volume = image(FatKind::Fat16);
+        let mut disk = vec![0xa5; volume.len() + 16 * BLOCK_SIZE];
+        disk[8 * BLOCK_SIZE..][..volume.len()].copy_from_slice(&volume);
+        let blocks = (volume.len() / BLOCK_SIZE) as u32;
+        let partition = Partition::new(RamDisk::new(&mut disk), 8, blocks).unwrap();
+        let fs = FatFs::mount(partition, NoClock).unwrap();
+        assert_eq!(
+            read_all(&fs, "README.TXT"),
+            README,
+            "pre-existing data is read from the partition"
+        );
+        fs.write_file("NEW.TXT", &[b'n'; 600]).unwrap();
+        fs.unmount().unwrap();
+
+        // Blocks around the partition are untouched
+        assert!(disk[..8 * BLOCK_SIZE].iter().all(|&b| b == 0xa5));
+        assert!(disk[8 * BLOCK_SIZE + volume.len()..]
+            .iter()
+            .all(|&b| b == 0xa5));
+        assert_eq!(disk[8 * BLOCK_SIZE..][..BLOCK_SIZE], volume[..BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn single_volume() {
+        let mut data: Vec<u8> = (0..4 * BLOCK_SIZE)
+            .map(|i| (i / BLOCK_SIZE) as u8)
+            .collect();
+        let mut volume = SingleVolume {
+            device: RamDisk::new(&mut data),
+        };
+        assert_eq!(volume.block_count(), 5);
+
+        let mut block = [0xffu8; BLOCK_SIZE];
+        volume.read(0, &mut block).unwrap();
+        let table = crate::storage::partition::PartitionTable::read(&mut volume).unwrap();
+        let [fat] = table.partitions() else {
+            panic!("{:?}", table.partitions());
+        };
+        assert_eq!((fat.start, fat.block_count), (1, 4));
+        assert_eq!(block[..446], [0; 446]);
+
+        // Block 0 is the MBR, the following blocks are shifted by one
+        let mut blocks = [0u8; 3 * BLOCK_SIZE];
+        volume.read(0, &mut blocks).unwrap();
+        assert_eq!(blocks[..BLOCK_SIZE], block);
+        assert_eq!(blocks[BLOCK_SIZE], 0);
+        assert_eq!(blocks[2 * BLOCK_SIZE], 1);
+        volume.read(4, &mut blocks[..BLOCK_SIZE]).unwrap();
+        assert_eq!(blocks[0], 3);
+        assert_eq!(
+            volume.read(4, &mut blocks[..2 * BLOCK_SIZE]),
+            Err(Error::OutOfBounds)
+        );
+
+        assert_eq!(volume.write(0, &block), Err(Error::OutOfBounds));
+        volume.write(1, &[9; BLOCK_SIZE]).unwrap();
+        assert_eq!(
+            volume.write(4, &[9; 2 * BLOCK_SIZE]),
+            Err(Error::OutOfBounds)
+        );
+        assert_eq!(data[0], 9);
+        assert_eq!(data[BLOCK_SIZE], 1);
+    }
+}
@@ -0,0 +1,450 @@
+//! Guarded writes, to keep the stock firmware safe while writing to the eMMC.
+//!
+//! A [`WriteGate`] checks every write against a [`WritePolicy`], which lists the
+//! [`ProtectedRange`]s that may never be written. The gate starts in [`WriteMode::Locked`],
+//! where all writes are refused, and has to be unlocked explicitly. [`WriteMode::DryRun`]
+//! reports the writes that would happen, without writing anything.
+//!
+//! [`EmmcStorage`](super::EmmcStorage) has its own gate, so every write to the eMMC goes
+//! through one, and [`WriteGuard`] adds a gate to any other [`BlockDevice`]:
+//!
+//! ```no_run
+//! # use alarmo::storage::guard::{ProtectedRange, WriteAttempt, WriteMode, WritePolicy};
+//! # use alarmo::storage::{BlockDevice, EmmcStorage};
+//! # fn run(emmc: &mut EmmcStorage) {
+//! const PROTECTED: &[ProtectedRange] = &[ProtectedRange::new("firmware", 2048, 65536)];
+//! let block_count = emmc.block_count();
+//! let gate = emmc.write_gate_mut();
+//! gate.set_policy(WritePolicy::new(PROTECTED).with_gpt(block_count));
+//! gate.set_logger(|attempt: &WriteAttempt| { /* e.g. print it on the console */ });
+//! gate.set_mode(WriteMode::DryRun);
+//! # }
+//! ```
+//!
+//! Block addresses in a policy are those of the device the gate checks: for the eMMC's gate,
+//! addresses on the whole eMMC, even when writing through a
+//! [`Partition`](super::partition::Partition) on top of it. A [`WriteGuard`] around a partition
+//! checks addresses relative to the start of the partition instead, see
+//! [`ProtectedRange::partition`].
+//!
+//! The eMMC's boot partitions are always protected: [`EmmcStorage`](super::EmmcStorage) only
+//! accesses the user data area.
+
+use super::partition::PartitionInfo;
+use super::{check_access, BlockDevice, Error};
+
+/// A range of blocks that may never be written.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ProtectedRange {
+    /// Shown in the log, to tell which range refused a write
+    pub name: &'static str,
+    pub start: u32,
+    pub block_count: u32,
+}
+
+impl ProtectedRange {
+    /// The MBR, and the primary GPT with the usual 128 entries.
+    pub const PRIMARY_GPT: ProtectedRange = ProtectedRange::new("primary GPT", 0, 34);
+
+    pub const fn new(name: &'static str, start: u32, block_count: u32) -> Self {
+        Self {
+            name,
+            start,
+            block_count,
+        }
+    }
+
+    /// The backup GPT, with the usual 128 entries, at the end of a device of `block_count`
+    /// blocks.
+    pub const fn backup_gpt(block_count: u32) -> Self {
+        Self::new("backup GPT", block_count.saturating_sub(33), 33)
+    }
+
+    /// The blocks of a partition, e.g. one holding the stock firmware.
+    ///
+    /// The range uses the addresses of the partition on the device it was found on, so it only
+    /// protects the partition in a gate that checks that whole device, like the eMMC's. In a
+    /// [`WriteGuard`] around another partition, it would protect unrelated blocks.
+    pub const fn partition(name: &'static str, partition: &PartitionInfo) -> Self {
+        Self::new(name, partition.start, partition.block_count)
+    }
+
+    /// Whether any of the `block_count` blocks starting at `start` is in the range.
+    pub const fn overlaps(&self, start: u32, block_count: u32) -> bool {
+        let end = start as u64 + block_count as u64;
+        let protected_end = self.start as u64 + self.block_count as u64;
+        block_count > 0
+            && self.block_count > 0
+            && (start as u64) < protected_end
+            && (self.start as u64) < end
+    }
+}
+
+/// The ranges a [`WriteGate`] refuses to write to.
+#[derive(Copy, Clone, Debug)]
+pub struct WritePolicy<'a> {
+    protected: &'a [ProtectedRange],
+    /// The primary and backup GPT, see [`with_gpt`](Self::with_gpt)
+    gpt: Option<[ProtectedRange; 2]>,
+}
+
+impl<'a> WritePolicy<'a> {
+    pub const fn new(protected: &'a [ProtectedRange]) -> Self {
+        Self {
+            protected,
+            gpt: None,
+        }
+    }
+
+    /// Also protects both copies of the GPT of a device of `block_count` blocks. The address
+    /// of the backup GPT depends on the size of the device, so it can't be part of a constant
+    /// list of ranges.
+    pub const fn with_gpt(mut self, block_count: u32) -> Self {
+        self.gpt = Some([
+            ProtectedRange::PRIMARY_GPT,
+            ProtectedRange::backup_gpt(block_count),
+        ]);
+        self
+    }
+
+    /// Checks a write of `block_count` blocks starting at `start`, and returns the first
+    /// protected range it would overwrite, if any. The GPT is checked first.
+    pub fn check(&self, start: u32, block_count: u32) -> Result<(), ProtectedRange> {
+        match self
+            .gpt
+            .iter()
+            .flatten()
+            .chain(self.protected)
+            .find(|range| range.overlaps(start, block_count))
+        {
+            Some(range) => Err(*range),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum WriteMode {
+    /// All writes are refused
+    #[default]
+    Locked,
+    /// Writes are checked and logged, but not performed
+    DryRun,
+    /// Writes outside of the protected ranges are performed
+    Unlocked,
+}
+
+/// What happened to a write.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WriteOutcome {
+    Written,
+    /// The write was allowed, but not performed because of [`WriteMode::DryRun`]
+    DryRun,
+    /// Refused because of [`WriteMode::Locked`]
+    Locked,
+    /// Refused because it would overwrite this range
+    Protected(ProtectedRange),
+}
+
+/// A write, as reported to the logger of a [`WriteGate`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WriteAttempt {
+    pub start: u32,
+    pub block_count: u32,
+    pub outcome: WriteOutcome,
+}
+
+/// Decides which writes are performed, see the [module documentation](self).
+#[derive(Copy, Clone, Debug)]
+pub struct WriteGate<'a> {
+    policy: WritePolicy<'a>,
+    mode: WriteMode,
+    logger: Option<fn(&WriteAttempt)>,
+}
+
+impl<'a> WriteGate<'a> {
+    /// Creates a locked gate.
+    pub const fn new(policy: WritePolicy<'a>) -> Self {
+        Self {
+            policy,
+            mode: WriteMode::Locked,
+            logger: None,
+        }
+    }
+
+    pub fn mode(&self) -> WriteMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: WriteMode) {
+        self.mode = mode;
+    }
+
+    pub fn policy(&self) -> WritePolicy<'a> {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: WritePolicy<'a>) {
+        self.policy = policy;
+    }
+
+    /// Sets a function that is called for every write, whether it's performed or not.
+    pub fn set_logger(&mut self, logger: fn(&WriteAttempt)) {
+        self.logger = Some(logger);
+    }
+
+    /// Decides what happens to a write, without performing it.
+    pub fn evaluate(&self, start: u32, block_count: u32) -> WriteOutcome {
+        match (self.mode, self.policy.check(start, block_count)) {
+            (WriteMode::Locked, _) => WriteOutcome::Locked,
+            (_, Err(range)) => WriteOutcome::Protected(range),
+            (WriteMode::DryRun, Ok(())) => WriteOutcome::DryRun,
+            (WriteMode::Unlocked, Ok(())) => WriteOutcome::Written,
+        }
+    }
+
+    /// Decides what happens to a write and logs it. Returns whether the write must be
+    /// performed, or [`Error::WriteProtected`] if it's refused.
+    pub(crate) fn admit<E>(&self, start: u32, block_count: u32) -> Result<bool, Error<E>> {
+        let outcome = self.evaluate(start, block_count);
+        if let Some(logger) = self.logger {
+            logger(&WriteAttempt {
+                start,
+                block_count,
+                outcome,
+            });
+        }
+        match outcome {
+            WriteOutcome::Written => Ok(true),
+            WriteOutcome::DryRun => Ok(false),
+            WriteOutcome::Locked | WriteOutcome::Protected(_) => Err(Error::WriteProtected),
+        }
+    }
+}
+
+/// A block device that only writes what its [`WriteGate`] allows, see the
+/// [module documentation](self).
+///
+/// Refused writes fail with [`Error::WriteProtected`]. Reads are never restricted.
+pub struct WriteGuard<'a, D> {
+    device: D,
+    gate: WriteGate<'a>,
+}
+
+impl<'a, D: BlockDevice> WriteGuard<'a, D> {
+    /// Wraps `device`, locked. The policy uses the block addresses of `device`.
+    pub fn new(device: D, policy: WritePolicy<'a>) -> Self {
+        Self {
+            device,
+            gate: WriteGate::new(policy),
+        }
+    }
+
+    pub fn gate(&self) -> &WriteGate<'a> {
+        &self.gate
+    }
+
+    pub fn gate_mut(&mut self) -> &mut WriteGate<'a> {
+        &mut self.gate
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for WriteGuard<'_, D> {
+    type Error = D::Error;
+
+    fn block_count(&self) -> u32 {
+        self.device.block_count()
+    }
+
+    fn read(&mut self, start: u32, buf: &mut [u8]) -> Result<(), Error<D::Error>> {
+        self.device.read(start, buf)
+    }
+
+    fn write(&mut self, start: u32, buf: &[u8]) -> Result<(), Error<D::Error>> {
+        let block_count = check_access(self.device.block_count(), start, buf.len())?;
+        match self.gate.admit(start, block_count)? {
+            true => self.device.write(start, buf),
+            false => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error<D::Error>> {
+        self.device.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::partition::Partition;
+    use crate::storage::{RamDisk, BLOCK_SIZE};
+    use std::cell::RefCell;
+
+    const FIRMWARE: ProtectedRange = ProtectedRange::new("firmware", 100, 50);
+    const PROTECTED: &[ProtectedRange] = &[ProtectedRange::PRIMARY_GPT, FIRMWARE];
+
+    std::thread_local! {
+        static LOG: RefCell<Vec<WriteAttempt>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn log(attempt: &WriteAttempt) {
+        LOG.with(|log| log.borrow_mut().push(*attempt));
+    }
+
+    fn take_log() -> Vec<WriteAttempt> {
+        LOG.with(|log| log.take())
+    }
+
+    #[test]
+    fn overlaps() {
+        assert!(FIRMWARE.overlaps(100, 1));
+        assert!(FIRMWARE.overlaps(149, 1));
+        assert!(FIRMWARE.overlaps(90, 11));
+        assert!(FIRMWARE.overlaps(0, u32::MAX));
+        // Right before and right after
+        assert!(!FIRMWARE.overlaps(90, 10));
+        assert!(!FIRMWARE.overlaps(150, 10));
+        // Empty writes never overlap
+        assert!(!FIRMWARE.overlaps(120, 0));
+        // No overflow at the end of the address space
+        let last = ProtectedRange::new("last", u32::MAX, 1);
+        assert!(last.overlaps(u32::MAX, u32::MAX));
+        assert!(!last.overlaps(u32::MAX - 1, 1));
+        assert!(!ProtectedRange::new("empty", 10, 0).overlaps(0, 100));
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(ProtectedRange::backup_gpt(1000).start, 967);
+        assert_eq!(ProtectedRange::backup_gpt(1000).block_count, 33);
+        assert_eq!(ProtectedRange::backup_gpt(10).start, 0);
+        let partition = PartitionInfo {
+            start: 2048,
+            block_count: 4096,
+            ..PartitionInfo::EMPTY
+        };
+        let range = ProtectedRange::partition("data", &partition);
+        assert_eq!((range.start, range.block_count), (2048, 4096));
+    }
+
+    #[test]
+    fn check() {
+        let policy = WritePolicy::new(PROTECTED);
+        assert_eq!(policy.check(34, 66), Ok(()));
+        assert_eq!(policy.check(33, 1), Err(ProtectedRange::PRIMARY_GPT));
+        assert_eq!(policy.check(99, 2), Err(FIRMWARE));
+        // The first range that overlaps is reported
+        assert_eq!(policy.check(0, 200), Err(ProtectedRange::PRIMARY_GPT));
+        assert_eq!(policy.check(150, 10), Ok(()));
+        assert_eq!(policy.check(120, 0), Ok(()));
+        assert_eq!(WritePolicy::new(&[]).check(0, u32::MAX), Ok(()));
+    }
+
+    #[test]
+    fn check_gpt() {
+        let backup = ProtectedRange::backup_gpt(1000);
+        let policy = WritePolicy::new(&[FIRMWARE]).with_gpt(1000);
+        assert_eq!(policy.check(0, 1), Err(ProtectedRange::PRIMARY_GPT));
+        assert_eq!(policy.check(999, 1), Err(backup));
+        assert_eq!(policy.check(140, 900), Err(backup));
+        assert_eq!(policy.check(120, 1), Err(FIRMWARE));
+        assert_eq!(policy.check(34, 66), Ok(()));
+        assert_eq!(policy.check(150, 817), Ok(()));
+        // Without the GPT, only the listed ranges are protected
+        assert_eq!(WritePolicy::new(&[FIRMWARE]).check(999, 1), Ok(()));
+    }
+
+    #[test]
+    fn evaluate() {
+        let mut gate = WriteGate::new(WritePolicy::new(PROTECTED));
+        assert_eq!(gate.mode(), WriteMode::Locked);
+        assert_eq!(gate.evaluate(40, 1), WriteOutcome::Locked);
+        assert_eq!(gate.evaluate(0, 1), WriteOutcome::Locked);
+
+        gate.set_mode(WriteMode::DryRun);
+        assert_eq!(gate.evaluate(40, 1), WriteOutcome::DryRun);
+        assert_eq!(gate.evaluate(0, 1), WriteOutcome::Protected(PROTECTED[0]));
+
+        gate.set_mode(WriteMode::Unlocked);
+        assert_eq!(gate.evaluate(40, 1), WriteOutcome::Written);
+        assert_eq!(gate.evaluate(140, 20), WriteOutcome::Protected(FIRMWARE));
+
+        gate.set_policy(WritePolicy::new(&[]));
+        assert_eq!(gate.evaluate(140, 20), WriteOutcome::Written);
+    }
+
+    #[test]
+    fn guard() {
+        let mut data = vec![0u8; 200 * BLOCK_SIZE];
+        let mut guard = WriteGuard::new(RamDisk::new(&mut data), WritePolicy::new(PROTECTED));
+        guard.gate_mut().set_logger(log);
+        let block = [1u8; BLOCK_SIZE];
+        take_log();
+
+        // Locked by default
+        assert_eq!(guard.write(40, &block), Err(Error::WriteProtected));
+        guard.gate_mut().set_mode(WriteMode::DryRun);
+        assert_eq!(guard.write(41, &block), Ok(()));
+        assert_eq!(guard.write(0, &block), Err(Error::WriteProtected));
+        guard.gate_mut().set_mode(WriteMode::Unlocked);
+        assert_eq!(guard.write(42, &block), Ok(()));
+        assert_eq!(
+            guard.write(98, &[2; 3 * BLOCK_SIZE]),
+            Err(Error::WriteProtected)
+        );
+        // Invalid accesses fail before reaching the gate, and are not logged
+        assert_eq!(
+            guard.write(199, &[2; 2 * BLOCK_SIZE]),
+            Err(Error::OutOfBounds)
+        );
+
+        let attempt = |start, block_count, outcome| WriteAttempt {
+            start,
+            block_count,
+            outcome,
+        };
+        assert_eq!(
+            take_log(),
+            [
+                attempt(40, 1, WriteOutcome::Locked),
+                attempt(41, 1, WriteOutcome::DryRun),
+                attempt(0, 1, WriteOutcome::Protected(ProtectedRange::PRIMARY_GPT)),
+                attempt(42, 1, WriteOutcome::Written),
+                attempt(98, 3, WriteOutcome::Protected(FIRMWARE)),
+            ]
+        );
+
+        // Reads are never restricted
+        let mut buf = [0u8; BLOCK_SIZE];
+        guard.read(100, &mut buf).unwrap();
+        let ram = guard.into_inner();
+        let written: Vec<usize> = (0..200)
+            .filter(|b| ram.data()[b * BLOCK_SIZE] != 0)
+            .collect();
+        assert_eq!(written, [42]);
+    }
+
+    #[test]
+    fn partition_addresses() {
+        // A gate below a partition checks addresses on the whole device
+        let mut data = vec![0u8; 200 * BLOCK_SIZE];
+        let mut guard = WriteGuard::new(RamDisk::new(&mut data), WritePolicy::new(PROTECTED));
+        guard.gate_mut().set_mode(WriteMode::Unlocked);
+        let mut partition = Partition::new(&mut guard, 90, 100).unwrap();
+        let block = [1u8; BLOCK_SIZE];
+        assert_eq!(partition.write(9, &block), Ok(()));
+        assert_eq!(partition.write(10, &block), Err(Error::WriteProtected));
+
+        // A guard around the partition checks addresses relative to it
+        let partition = Partition::new(RamDisk::new(&mut data), 90, 100).unwrap();
+        let mut guard = WriteGuard::new(partition, WritePolicy::new(PROTECTED));
+        guard.gate_mut().set_mode(WriteMode::Unlocked);
+        assert_eq!(guard.write(10, &block), Err(Error::WriteProtected));
+        assert_eq!(guard.write(60, &block), Ok(()));
+        assert_eq!(data[150 * BLOCK_SIZE], 1);
+    }
+}
@@ -0,0 +1,146 @@
+//! Block storage, with the eMMC as the main backend.
+//!
+//! [`BlockDevice`] is the common interface for anything that stores fixed-size blocks:
+//! * [`EmmcStorage`] (feature `emmc`): the Alarmo's eMMC
+//! * [`RamDisk`]: a byte slice, e.g. a disk image loaded on the host for testing
+//!
+//! The [`partition`] module reads MBR and GPT partition tables, and gives access to each
+//! partition as a separate block device. The [`guard`] module refuses writes to protected
+//! ranges of blocks, unless it is explicitly unlocked.
+//!
+//! With the `embedded-sdmmc` feature, the [`fat`] module reads and writes files on FAT volumes,
+//! and the [`adapter`] module converts block devices to and from the `embedded-sdmmc` crate.
+
+#[cfg(feature = "embedded-sdmmc")]
+pub mod adapter;
+#[cfg(feature = "emmc")]
+mod emmc;
+#[cfg(feature = "embedded-sdmmc")]
+pub mod fat;
+pub mod guard;
+pub mod partition;
+mod ram;
+
+#[cfg(feature = "emmc")]
+pub use emmc::{BusTest, EmmcBusWidth, EmmcStorage};
+pub use ram::RamDisk;
+
+use core::fmt::Debug;
+
+/// Size of a block, in bytes. Devices with larger native blocks must emulate 512-byte blocks.
+pub const BLOCK_SIZE: usize = 512;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error<E> {
+    /// The access goes past the last block of the device
+    OutOfBounds,
+    /// The buffer length is not a multiple of [`BLOCK_SIZE`]
+    BadLength,
+    /// The write was refused by a [`WriteGate`](guard::WriteGate)
+    WriteProtected,
+    /// The device reported an error
+    Device(E),
+}
+
+/// A device that stores [`BLOCK_SIZE`] byte blocks, addressed by their index.
+pub trait BlockDevice {
+    type Error: Debug;
+
+    /// Returns the number of blocks of the device.
+    fn block_count(&self) -> u32;
+
+    /// Reads consecutive blocks, starting at block `start`, into `buf`. The length of `buf`
+    /// must be a multiple of [`BLOCK_SIZE`].
+    fn read(&mut self, start: u32, buf: &mut [u8]) -> Result<(), Error<Self::Error>>;
+
+    /// Writes `buf` to consecutive blocks, starting at block `start`. The length of `buf` must
+    /// be a multiple of [`BLOCK_SIZE`].
+    ///
+    /// Writes may be cached until [`flush`](BlockDevice::flush) is called.
+    fn write(&mut self, start: u32, buf: &[u8]) -> Result<(), Error<Self::Error>>;
+
+    /// Makes sure all writes reached the storage.
+    fn flush(&mut self) -> Result<(), Error<Self::Error>>;
+
+    /// Returns the size of the device, in bytes.
+    fn size(&self) -> u64 {
+        self.block_count() as u64 * BLOCK_SIZE as u64
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    type Error = D::Error;
+
+    fn block_count(&self) -> u32 {
+        (**self).block_count()
+    }
+
+    fn read(&mut self, start: u32, buf: &mut [u8]) -> Result<(), Error<D::Error>> {
+        (**self).read(start, buf)
+    }
+
+    fn write(&mut self, start: u32, buf: &[u8]) -> Result<(), Error<D::Error>> {
+        (**self).write(start, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Error<D::Error>> {
+        (**self).flush()
+    }
+}
+
+/// Checks that `len` bytes starting at block `start` are whole blocks within `block_count`,
+/// and returns the number of blocks.
+pub(crate) fn check_access<E>(block_count: u32, start: u32, len: usize) -> Result<u32, Error<E>> {
+    if len % BLOCK_SIZE != 0 {
+        return Err(Error::BadLength);
+    }
+    let blocks = u32::try_from(len / BLOCK_SIZE).map_err(|_| Error::OutOfBounds)?;
+    match start.checked_add(blocks) {
+        Some(end) if end <= block_count => Ok(blocks),
+        _ => Err(Error::OutOfBounds),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(block_count: u32, start: u32, len: usize) -> Result<u32, Error<()>> {
+        check_access(block_count, start, len)
+    }
+
+    #[test]
+    fn check_access_bounds() {
+        assert_eq!(check(8, 0, 0), Ok(0));
+        assert_eq!(check(8, 8, 0), Ok(0));
+        assert_eq!(check(8, 0, 8 * BLOCK_SIZE), Ok(8));
+        assert_eq!(check(8, 7, BLOCK_SIZE), Ok(1));
+        assert_eq!(check(8, 7, 2 * BLOCK_SIZE), Err(Error::OutOfBounds));
+        // Empty accesses may end at the last block, but not start past it
+        assert_eq!(check(8, 9, 0), Err(Error::OutOfBounds));
+        assert_eq!(check(8, 9, BLOCK_SIZE), Err(Error::OutOfBounds));
+        assert_eq!(check(0, 0, BLOCK_SIZE), Err(Error::OutOfBounds));
+    }
+
+    #[test]
+    fn check_access_length() {
+        assert_eq!(check(8, 0, 1), Err(Error::BadLength));
+        assert_eq!(check(8, 0, BLOCK_SIZE + 1), Err(Error::BadLength));
+        // The length is checked first, even if the access is also out of bounds
+        assert_eq!(check(8, 100, 3), Err(Error::BadLength));
+    }
+
+    #[test]
+    fn check_access_overflow() {
+        assert_eq!(
+            check(u32::MAX, u32::MAX, BLOCK_SIZE),
+            Err(Error::OutOfBounds)
+        );
+        assert_eq!(check(u32::MAX, u32::MAX - 1, BLOCK_SIZE), Ok(1));
+        // More blocks than fit in a u32
+        assert_eq!(
+            check(u32::MAX, 0, (u32::MAX as usize + 1) * BLOCK_SIZE),
+            Err(Error::OutOfBounds)
+        );
+    }
+}
@@ -0,0 +1,728 @@
+//! MBR and GPT partition tables.
+//!
+//! [`PartitionTable::read`] finds the partitions of a [`BlockDevice`], and
+//! [`PartitionInfo::open`] turns one of them into a block device of its own, which can only
+//! access the blocks of the partition:
+//!
+//! ```no_run
+//! # use alarmo::storage::partition::PartitionTable;
+//! # use alarmo::storage::{BlockDevice, EmmcStorage};
+//! # fn run(emmc: &mut EmmcStorage) {
+//! let table = PartitionTable::read(emmc).unwrap();
+//! let data = table.find("data").unwrap().open(emmc).unwrap();
+//! # }
+//! ```
+//!
+//! GPT headers and entries are checked against their CRC-32. If the primary GPT is damaged, the
+//! backup at the end of the device is used instead. Only the primary partitions of an MBR are
+//! listed, logical partitions in extended partitions are not.
+
+use super::{check_access, BlockDevice, Error, BLOCK_SIZE};
+use crate::crc::{crc32, Crc32};
+use core::fmt;
+
+/// Maximum number of partitions in a [`PartitionTable`]. Further partitions are ignored.
+pub const MAX_PARTITIONS: usize = 16;
+
+/// MBR partition type of the protective MBR in front of a GPT
+const MBR_TYPE_PROTECTIVE: u8 = 0xee;
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_LEN: usize = 16;
+const MBR_BOOTABLE: u8 = 0x80;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN_LEN: usize = 92;
+const GPT_ENTRY_MIN_LEN: usize = 128;
+/// Length of partition names in GPT entries, in UTF-16 code units
+const GPT_NAME_LEN: usize = 36;
+
+/// GPT attribute bit: the partition is bootable by legacy BIOS. Also set for MBR partitions
+/// flagged as active.
+pub const ATTRIBUTE_LEGACY_BOOTABLE: u64 = 1 << 2;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PartitionError<E> {
+    /// The device could not be read
+    Storage(Error<E>),
+    /// Block 0 holds no MBR
+    NoTable,
+    /// The GPT header or an entry has invalid fields, or points outside of the device
+    InvalidGpt,
+    /// The CRC-32 of the GPT header or of the entries doesn't match
+    ChecksumMismatch,
+}
+
+impl<E> From<Error<E>> for PartitionError<E> {
+    fn from(error: Error<E>) -> Self {
+        PartitionError::Storage(error)
+    }
+}
+
+/// A GUID, in the byte order it's stored in GPTs (the first three fields are little-endian).
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const ZERO: Guid = Guid([0; 16]);
+}
+
+impl fmt::Display for Guid {
+    /// Formats the GUID in its usual text form, e.g. `C12A7328-F81F-11D2-BA4B-00A0C93EC93B`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        b[10..].iter().try_for_each(|byte| write!(f, "{byte:02X}"))
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Name of a GPT partition, stored as UTF-16. MBR partitions have no name.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct PartitionName([u16; GPT_NAME_LEN]);
+
+impl PartitionName {
+    const EMPTY: PartitionName = PartitionName([0; GPT_NAME_LEN]);
+
+    /// Returns the characters of the name. Invalid UTF-16 is replaced by `U+FFFD`.
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        let len = self.0.iter().position(|&c| c == 0).unwrap_or(GPT_NAME_LEN);
+        char::decode_utf16(self.0[..len].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0[0] == 0
+    }
+}
+
+impl PartialEq<str> for PartitionName {
+    fn eq(&self, other: &str) -> bool {
+        self.chars().eq(other.chars())
+    }
+}
+
+impl fmt::Display for PartitionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chars().try_for_each(|c| fmt::Write::write_char(f, c))
+    }
+}
+
+impl fmt::Debug for PartitionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{self}\"")
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PartitionType {
+    /// MBR partition type, e.g. `0x0c` for FAT32
+    Mbr(u8),
+    /// GPT partition type GUID
+    Gpt(Guid),
+}
+
+/// A partition found in a [`PartitionTable`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PartitionInfo {
+    /// Index of the entry in the partition table
+    pub index: usize,
+    /// First block of the partition
+    pub start: u32,
+    pub block_count: u32,
+    pub kind: PartitionType,
+    /// Unique GUID of GPT partitions, [`Guid::ZERO`] for MBR partitions
+    pub guid: Guid,
+    pub name: PartitionName,
+    /// GPT attribute flags, see [`ATTRIBUTE_LEGACY_BOOTABLE`]
+    pub attributes: u64,
+}
+
+impl PartitionInfo {
+    pub(crate) const EMPTY: PartitionInfo = PartitionInfo {
+        index: 0,
+        start: 0,
+        block_count: 0,
+        kind: PartitionType::Mbr(0),
+        guid: Guid::ZERO,
+        name: PartitionName::EMPTY,
+        attributes: 0,
+    };
+
+    /// Returns a block device for the partition, on top of `device`, which must be the device
+    /// the table was read from.
+    pub fn open<D: BlockDevice>(&self, device: D) -> Result<Partition<D>, Error<D::Error>> {
+        Partition::new(device, self.start, self.block_count)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TableKind {
+    Mbr,
+    Gpt {
+        disk_guid: Guid,
+        /// Whether the primary GPT was damaged, and the backup was used instead
+        from_backup: bool,
+    },
+}
+
+/// The partitions of a block device, see the [module documentation](self).
+#[derive(Clone, Debug)]
+pub struct PartitionTable {
+    kind: TableKind,
+    partitions: [PartitionInfo; MAX_PARTITIONS],
+    len: usize,
+}
+
+impl PartitionTable {
+    /// Reads the partition table of `device`.
+    pub fn read<D: BlockDevice>(device: &mut D) -> Result<Self, PartitionError<D::Error>> {
+        let mut block = [0u8; BLOCK_SIZE];
+        device.read(0, &mut block)?;
+        if block[510..512] != [0x55, 0xaa] {
+            return Err(PartitionError::NoTable);
+        }
+
+        let mut table = Self {
+            kind: TableKind::Mbr,
+            partitions: [PartitionInfo::EMPTY; MAX_PARTITIONS],
+            len: 0,
+        };
+        let entries = block[MBR_ENTRIES_OFFSET..MBR_ENTRIES_OFFSET + 4 * MBR_ENTRY_LEN]
+            .chunks_exact(MBR_ENTRY_LEN);
+        if entries.clone().any(|e| e[4] == MBR_TYPE_PROTECTIVE) {
+            return match table.read_gpt(device, 1, &mut block) {
+                Err(error @ (PartitionError::InvalidGpt | PartitionError::ChecksumMismatch)) => {
+                    table.len = 0;
+                    let backup = device.block_count().saturating_sub(1);
+                    match table.read_gpt(device, backup, &mut block) {
+                        Ok(()) => Ok(table),
+                        Err(PartitionError::Storage(e)) => Err(PartitionError::Storage(e)),
+                        // Report the damage of the primary GPT, the backup is only a fallback
+                        Err(_) => Err(error),
+                    }
+                }
+                result => result.map(|()| table),
+            };
+        }
+
+        for (index, entry) in entries.enumerate() {
+            let start = u32::from_le_bytes(entry[8..12].try_into().unwrap());
+            let block_count = u32::from_le_bytes(entry[12..16].try_into().unwrap());
+            if entry[4] == 0 || block_count == 0 {
+                continue;
+            }
+            table.push(PartitionInfo {
+                index,
+                start,
+                block_count,
+                kind: PartitionType::Mbr(entry[4]),
+                attributes: if entry[0] & MBR_BOOTABLE != 0 {
+                    ATTRIBUTE_LEGACY_BOOTABLE
+                } else {
+                    0
+                },
+                ..PartitionInfo::EMPTY
+            });
+        }
+        Ok(table)
+    }
+
+    pub fn kind(&self) -> TableKind {
+        self.kind
+    }
+
+    pub fn partitions(&self) -> &[PartitionInfo] {
+        &self.partitions[..self.len]
+    }
+
+    /// Returns the first partition with the given name.
+    pub fn find(&self, name: &str) -> Option<&PartitionInfo> {
+        self.partitions().iter().find(|p| p.name == *name)
+    }
+
+    fn push(&mut self, partition: PartitionInfo) {
+        if self.len < MAX_PARTITIONS {
+            self.partitions[self.len] = partition;
+            self.len += 1;
+        }
+    }
+
+    /// Reads the GPT with its header at `lba`.
+    fn read_gpt<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        lba: u32,
+        block: &mut [u8; BLOCK_SIZE],
+    ) -> Result<(), PartitionError<D::Error>> {
+        device.read(lba, block)?;
+        let u32_at =
+            |block: &[u8], i: usize| u32::from_le_bytes(block[i..i + 4].try_into().unwrap());
+        let u64_at =
+            |block: &[u8], i: usize| u64::from_le_bytes(block[i..i + 8].try_into().unwrap());
+
+        let header_len = u32_at(block, 12) as usize;
+        if &block[..8] != GPT_SIGNATURE || !(GPT_HEADER_MIN_LEN..=BLOCK_SIZE).contains(&header_len)
+        {
+            return Err(PartitionError::InvalidGpt);
+        }
+        let header_crc = u32_at(block, 16);
+        block[16..20].fill(0);
+        if crc32(&block[..header_len]) != header_crc {
+            return Err(PartitionError::ChecksumMismatch);
+        }
+
+        let block_count = device.block_count() as u64;
+        let first_usable = u64_at(block, 40);
+        let last_usable = u64_at(block, 48);
+        let entries_lba = u64_at(block, 72);
+        let entry_count = u32_at(block, 80) as usize;
+        let entry_len = u32_at(block, 84) as usize;
+        let entries_crc = u32_at(block, 88);
+        // Entries are only supported if they don't cross block boundaries
+        if !(GPT_ENTRY_MIN_LEN..=BLOCK_SIZE).contains(&entry_len) || BLOCK_SIZE % entry_len != 0 {
+            return Err(PartitionError::InvalidGpt);
+        }
+        let entry_blocks = entry_count.div_ceil(BLOCK_SIZE / entry_len) as u64;
+        if u64_at(block, 24) != lba as u64
+            || first_usable > last_usable
+            || last_usable >= block_count
+            || entries_lba.saturating_add(entry_blocks) > block_count
+        {
+            return Err(PartitionError::InvalidGpt);
+        }
+        self.kind = TableKind::Gpt {
+            disk_guid: Guid(block[56..72].try_into().unwrap()),
+            from_backup: lba != 1,
+        };
+
+        // Check the whole array before keeping any entry, so the entries are read twice
+        let mut crc = Crc32::new();
+        for pass in 0..2 {
+            let mut index = 0;
+            for lba in entries_lba as u32..(entries_lba + entry_blocks) as u32 {
+                device.read(lba, block)?;
+                for entry in block.chunks_exact(entry_len) {
+                    if index == entry_count {
+                        break;
+                    }
+                    index += 1;
+                    if pass == 0 {
+                        crc.update(entry);
+                    } else {
+                        self.push_gpt_entry(index - 1, entry, first_usable..=last_usable)?;
+                    }
+                }
+            }
+            if pass == 0 && crc.finish() != entries_crc {
+                return Err(PartitionError::ChecksumMismatch);
+            }
+        }
+        Ok(())
+    }
+
+    fn push_gpt_entry<E>(
+        &mut self,
+        index: usize,
+        entry: &[u8],
+        usable: core::ops::RangeInclusive<u64>,
+    ) -> Result<(), PartitionError<E>> {
+        let kind = Guid(entry[..16].try_into().unwrap());
+        if kind == Guid::ZERO {
+            return Ok(());
+        }
+        let first = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+        let last = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+        if first > last || !usable.contains(&first) || !usable.contains(&last) {
+            return Err(PartitionError::InvalidGpt);
+        }
+        let mut name = [0u16; GPT_NAME_LEN];
+        for (c, bytes) in name.iter_mut().zip(entry[56..128].chunks_exact(2)) {
+            *c = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        // The usable range is within the device, so the blocks fit in u32
+        self.push(PartitionInfo {
+            index,
+            start: first as u32,
+            block_count: (last - first + 1) as u32,
+            kind: PartitionType::Gpt(kind),
+            guid: Guid(entry[16..32].try_into().unwrap()),
+            name: PartitionName(name),
+            attributes: u64::from_le_bytes(entry[48..56].try_into().unwrap()),
+        });
+        Ok(())
+    }
+}
+
+/// A range of blocks of another device, e.g. a partition, as a block device of its own.
+///
+/// Accesses are checked against the size of the partition, so they can't reach the blocks
+/// around it.
+pub struct Partition<D> {
+    device: D,
+    start: u32,
+    block_count: u32,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    /// Returns a device for `block_count` blocks of `device`, starting at block `start`.
+    pub fn new(device: D, start: u32, block_count: u32) -> Result<Self, Error<D::Error>> {
+        match start.checked_add(block_count) {
+            Some(end) if end <= device.block_count() => Ok(Self {
+                device,
+                start,
+                block_count,
+            }),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    /// Returns the first block of the partition on the underlying device.
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    type Error = D::Error;
+
+    fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    fn read(&mut self, start: u32, buf: &mut [u8]) -> Result<(), Error<D::Error>> {
+        check_access(self.block_count, start, buf.len())?;
+        self.device.read(self.start + start, buf)
+    }
+
+    fn write(&mut self, start: u32, buf: &[u8]) -> Result<(), Error<D::Error>> {
+        check_access(self.block_count, start, buf.len())?;
+        self.device.write(self.start + start, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Error<D::Error>> {
+        self.device.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::RamDisk;
+
+    /// Blocks of the test disks
+    const N: usize = 128;
+    const ENTRY_BLOCKS: usize = 128 * 128 / BLOCK_SIZE;
+    const FIRST_USABLE: u64 = 2 + ENTRY_BLOCKS as u64;
+    const LAST_USABLE: u64 = (N - 2 - ENTRY_BLOCKS) as u64;
+
+    const ESP: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+    const BASIC_DATA: &str = "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7";
+
+    /// Parses a GUID from its text form, as stored in GPTs.
+    fn guid(text: &str) -> Guid {
+        let hex: Vec<u8> = text
+            .split('-')
+            .flat_map(|part| {
+                (0..part.len())
+                    .step_by(2)
+                    .map(move |i| u8::from_str_radix(&part[i..i + 2], 16).unwrap())
+            })
+            .collect();
+        let mut bytes: [u8; 16] = hex.try_into().unwrap();
+        bytes[..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+        Guid(bytes)
+    }
+
+    fn mbr_entry(disk: &mut [u8], index: usize, flags: u8, kind: u8, start: u32, count: u32) {
+        let entry = &mut disk[MBR_ENTRIES_OFFSET + index * MBR_ENTRY_LEN..][..MBR_ENTRY_LEN];
+        entry[0] = flags;
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&count.to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+    }
+
+    struct GptEntry {
+        kind: &'static str,
+        first: u64,
+        last: u64,
+        attributes: u64,
+        name: &'static str,
+    }
+
+    fn gpt_entries(entries: &[GptEntry]) -> Vec<u8> {
+        let mut array = vec![0u8; ENTRY_BLOCKS * BLOCK_SIZE];
+        for (i, entry) in entries.iter().enumerate() {
+            let bytes = &mut array[i * 128..][..128];
+            bytes[..16].copy_from_slice(&guid(entry.kind).0);
+            bytes[16..32].fill(i as u8 + 1);
+            bytes[32..40].copy_from_slice(&entry.first.to_le_bytes());
+            bytes[40..48].copy_from_slice(&entry.last.to_le_bytes());
+            bytes[48..56].copy_from_slice(&entry.attributes.to_le_bytes());
+            for (c, unit) in bytes[56..]
+                .chunks_exact_mut(2)
+                .zip(entry.name.encode_utf16())
+            {
+                c.copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+        array
+    }
+
+    /// Writes a GPT header at `lba`, with its entries at `entries_lba`.
+    fn gpt_header(disk: &mut [u8], lba: usize, alternate: usize, entries_lba: usize) {
+        let entries = &disk[entries_lba * BLOCK_SIZE..][..ENTRY_BLOCKS * BLOCK_SIZE];
+        let entries_crc = crc32(entries);
+        let header = &mut disk[lba * BLOCK_SIZE..][..BLOCK_SIZE];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&0x10000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&(GPT_HEADER_MIN_LEN as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&(lba as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(alternate as u64).to_le_bytes());
+        header[40..48].copy_from_slice(&FIRST_USABLE.to_le_bytes());
+        header[48..56].copy_from_slice(&LAST_USABLE.to_le_bytes());
+        header[56..72].fill(0x42);
+        header[72..80].copy_from_slice(&(entries_lba as u64).to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let crc = crc32(&header[..GPT_HEADER_MIN_LEN]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Builds a disk with a protective MBR, and the same entries in the primary and backup GPT.
+    fn gpt_disk(entries: &[GptEntry]) -> Vec<u8> {
+        let mut disk = vec![0u8; N * BLOCK_SIZE];
+        mbr_entry(&mut disk, 0, 0, MBR_TYPE_PROTECTIVE, 1, N as u32 - 1);
+        let array = gpt_entries(entries);
+        let backup_entries = N - 1 - ENTRY_BLOCKS;
+        disk[2 * BLOCK_SIZE..][..array.len()].copy_from_slice(&array);
+        disk[backup_entries * BLOCK_SIZE..][..array.len()].copy_from_slice(&array);
+        gpt_header(&mut disk, 1, N - 1, 2);
+        gpt_header(&mut disk, N - 1, 1, backup_entries);
+        disk
+    }
+
+    fn default_entries() -> [GptEntry; 2] {
+        [
+            GptEntry {
+                kind: ESP,
+                first: FIRST_USABLE,
+                last: 49,
+                attributes: ATTRIBUTE_LEGACY_BOOTABLE,
+                name: "boot",
+            },
+            GptEntry {
+                kind: BASIC_DATA,
+                first: 50,
+                last: LAST_USABLE,
+                attributes: 0,
+                name: "dataé",
+            },
+        ]
+    }
+
+    fn read(disk: &mut [u8]) -> Result<PartitionTable, PartitionError<core::convert::Infallible>> {
+        PartitionTable::read(&mut RamDisk::new(disk))
+    }
+
+    #[test]
+    fn crc() {
+        // Standard check value, so the test images don't share a CRC bug with the parser
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn guid_display() {
+        assert_eq!(guid(ESP).to_string(), ESP);
+        assert_eq!(guid(ESP).0[..4], [0x28, 0x73, 0x2a, 0xc1]);
+    }
+
+    #[test]
+    fn no_table() {
+        let mut disk = vec![0u8; N * BLOCK_SIZE];
+        assert_eq!(read(&mut disk).unwrap_err(), PartitionError::NoTable);
+        let mut empty = [];
+        assert_eq!(
+            read(&mut empty).unwrap_err(),
+            PartitionError::Storage(Error::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn mbr() {
+        let mut disk = vec![0u8; N * BLOCK_SIZE];
+        mbr_entry(&mut disk, 0, MBR_BOOTABLE, 0x0c, 8, 40);
+        // Empty entries are skipped, but keep their index
+        mbr_entry(&mut disk, 2, 0, 0x83, 48, 80);
+        mbr_entry(&mut disk, 3, 0, 0x83, 10, 0);
+        let table = read(&mut disk).unwrap();
+        assert_eq!(table.kind(), TableKind::Mbr);
+        assert_eq!(
+            table.partitions(),
+            [
+                PartitionInfo {
+                    index: 0,
+                    start: 8,
+                    block_count: 40,
+                    kind: PartitionType::Mbr(0x0c),
+                    attributes: ATTRIBUTE_LEGACY_BOOTABLE,
+                    ..PartitionInfo::EMPTY
+                },
+                PartitionInfo {
+                    index: 2,
+                    start: 48,
+                    block_count: 80,
+                    kind: PartitionType::Mbr(0x83),
+                    ..PartitionInfo::EMPTY
+                },
+            ]
+        );
+        assert!(table.partitions()[0].name.is_empty());
+    }
+
+    #[test]
+    fn gpt() {
+        let mut disk = gpt_disk(&default_entries());
+        let table = read(&mut disk).unwrap();
+        assert_eq!(
+            table.kind(),
+            TableKind::Gpt {
+                disk_guid: Guid([0x42; 16]),
+                from_backup: false
+            }
+        );
+        let [boot, data] = table.partitions() else {
+            panic!("{:?}", table.partitions());
+        };
+        assert_eq!(boot.kind, PartitionType::Gpt(guid(ESP)));
+        assert_eq!(boot.guid, Guid([1; 16]));
+        assert_eq!((boot.start, boot.block_count), (34, 16));
+        assert_eq!(boot.attributes, ATTRIBUTE_LEGACY_BOOTABLE);
+        assert_eq!(data.index, 1);
+        assert_eq!(data.kind, PartitionType::Gpt(guid(BASIC_DATA)));
+        assert_eq!((data.start, data.block_count), (50, 45));
+        assert_eq!(data.name.to_string(), "dataé");
+        assert_eq!(table.find("dataé"), Some(data));
+        assert_eq!(table.find("data"), None);
+    }
+
+    #[test]
+    fn gpt_backup() {
+        let mut disk = gpt_disk(&default_entries());
+        // Damaged primary header
+        disk[BLOCK_SIZE + 40] ^= 1;
+        let table = read(&mut disk).unwrap();
+        assert!(matches!(
+            table.kind(),
+            TableKind::Gpt {
+                from_backup: true,
+                ..
+            }
+        ));
+        assert_eq!(table.partitions().len(), 2);
+        assert_eq!(table.partitions()[1].name.to_string(), "dataé");
+
+        // Damaged primary entries
+        let mut disk = gpt_disk(&default_entries());
+        disk[2 * BLOCK_SIZE + 60] ^= 1;
+        let table = read(&mut disk).unwrap();
+        assert!(matches!(
+            table.kind(),
+            TableKind::Gpt {
+                from_backup: true,
+                ..
+            }
+        ));
+        assert_eq!(table.partitions().len(), 2);
+    }
+
+    #[test]
+    fn gpt_checksum_mismatch() {
+        // Entries damaged in both copies
+        let mut disk = gpt_disk(&default_entries());
+        disk[2 * BLOCK_SIZE + 60] ^= 1;
+        disk[(N - 1 - ENTRY_BLOCKS) * BLOCK_SIZE + 60] ^= 1;
+        assert_eq!(
+            read(&mut disk).unwrap_err(),
+            PartitionError::ChecksumMismatch
+        );
+
+        // Invalid primary header and damaged backup: the primary's error is reported
+        let mut disk = gpt_disk(&default_entries());
+        disk[BLOCK_SIZE..BLOCK_SIZE + 8].fill(0);
+        disk[(N - 1) * BLOCK_SIZE + 40] ^= 1;
+        assert_eq!(read(&mut disk).unwrap_err(), PartitionError::InvalidGpt);
+    }
+
+    #[test]
+    fn gpt_out_of_range() {
+        // Entries outside of the usable blocks, with valid checksums
+        for (first, last) in [(FIRST_USABLE - 1, 40), (50, LAST_USABLE + 1), (60, 59)] {
+            let mut entries = default_entries();
+            entries[1].first = first;
+            entries[1].last = last;
+            let mut disk = gpt_disk(&entries);
+            assert_eq!(
+                read(&mut disk).unwrap_err(),
+                PartitionError::InvalidGpt,
+                "{first}..={last}"
+            );
+        }
+
+        // Usable blocks past the end of a smaller device than the GPT was made for, which also
+        // cuts off the backup
+        let mut disk = gpt_disk(&default_entries());
+        disk.truncate(LAST_USABLE as usize * BLOCK_SIZE);
+        assert_eq!(read(&mut disk).unwrap_err(), PartitionError::InvalidGpt);
+    }
+
+    #[test]
+    fn partition_bounds() {
+        let mut disk = gpt_disk(&default_entries());
+        let table = read(&mut disk).unwrap();
+        let boot = *table.find("boot").unwrap();
+        let mut ram = RamDisk::new(&mut disk);
+        let mut partition = boot.open(&mut ram).unwrap();
+        assert_eq!(partition.start(), 34);
+        assert_eq!(partition.block_count(), 16);
+
+        let block = [0x5au8; BLOCK_SIZE];
+        partition.write(15, &block).unwrap();
+        assert_eq!(partition.write(16, &block), Err(Error::OutOfBounds));
+        let mut buf = [0u8; 2 * BLOCK_SIZE];
+        assert_eq!(partition.read(15, &mut buf), Err(Error::OutOfBounds));
+        assert_eq!(disk[49 * BLOCK_SIZE], 0x5a);
+        assert_eq!(disk[50 * BLOCK_SIZE], 0);
+
+        let mut ram = RamDisk::new(&mut disk);
+        assert!(Partition::new(&mut ram, 100, 28).is_ok());
+        assert_eq!(
+            Partition::new(&mut ram, 100, 29).err(),
+            Some(Error::OutOfBounds)
+        );
+        assert_eq!(
+            Partition::new(&mut ram, u32::MAX, 2).err(),
+            Some(Error::OutOfBounds)
+        );
+    }
+}
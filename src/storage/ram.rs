@@ -0,0 +1,102 @@
+use super::{check_access, BlockDevice, Error, BLOCK_SIZE};
+use core::convert::Infallible;
+
+/// A block device backed by memory, e.g. a disk image for testing storage code on the host.
+///
+/// Trailing bytes that don't fill a whole block are not accessible.
+pub struct RamDisk<'a> {
+    data: &'a mut [u8],
+}
+
+impl<'a> RamDisk<'a> {
+    pub fn new(data: &'a mut [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Returns the contents of the disk.
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    fn range(&self, start: u32, len: usize) -> Result<core::ops::Range<usize>, Error<Infallible>> {
+        check_access(self.block_count(), start, len)?;
+        let offset = start as usize * BLOCK_SIZE;
+        Ok(offset..offset + len)
+    }
+}
+
+impl BlockDevice for RamDisk<'_> {
+    type Error = Infallible;
+
+    fn block_count(&self) -> u32 {
+        (self.data.len() / BLOCK_SIZE)
+            .try_into()
+            .unwrap_or(u32::MAX)
+    }
+
+    fn read(&mut self, start: u32, buf: &mut [u8]) -> Result<(), Error<Infallible>> {
+        let range = self.range(start, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn write(&mut self, start: u32, buf: &[u8]) -> Result<(), Error<Infallible>> {
+        let range = self.range(start, buf.len())?;
+        self.data[range].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error<Infallible>> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_write() {
+        let mut data = vec![0u8; 4 * BLOCK_SIZE];
+        let mut disk = RamDisk::new(&mut data);
+        assert_eq!(disk.block_count(), 4);
+        assert_eq!(disk.size(), 4 * BLOCK_SIZE as u64);
+
+        let blocks: Vec<u8> = (0..2 * BLOCK_SIZE).map(|i| i as u8).collect();
+        disk.write(1, &blocks).unwrap();
+        disk.flush().unwrap();
+        let mut buf = vec![0u8; 2 * BLOCK_SIZE];
+        disk.read(1, &mut buf).unwrap();
+        assert_eq!(buf, blocks);
+
+        // The other blocks are untouched
+        assert!(disk.data()[..BLOCK_SIZE].iter().all(|&b| b == 0));
+        assert!(disk.data()[3 * BLOCK_SIZE..].iter().all(|&b| b == 0));
+        assert_eq!(&data[BLOCK_SIZE..3 * BLOCK_SIZE], &blocks[..]);
+    }
+
+    #[test]
+    fn bounds() {
+        let mut data = vec![0xa5u8; 4 * BLOCK_SIZE];
+        let mut disk = RamDisk::new(&mut data);
+        let mut buf = [0u8; 2 * BLOCK_SIZE];
+        assert_eq!(disk.read(3, &mut buf), Err(Error::OutOfBounds));
+        assert_eq!(disk.write(3, &buf), Err(Error::OutOfBounds));
+        assert_eq!(disk.read(0, &mut buf[..100]), Err(Error::BadLength));
+        assert_eq!(disk.write(0, &buf[..100]), Err(Error::BadLength));
+        // Failed accesses change nothing
+        assert_eq!(buf, [0; 2 * BLOCK_SIZE]);
+        assert!(data.iter().all(|&b| b == 0xa5));
+    }
+
+    #[test]
+    fn partial_block() {
+        // The trailing 100 bytes are not a whole block
+        let mut data = vec![0u8; 2 * BLOCK_SIZE + 100];
+        let mut disk = RamDisk::new(&mut data);
+        assert_eq!(disk.block_count(), 2);
+        let mut buf = [0u8; BLOCK_SIZE];
+        disk.read(1, &mut buf).unwrap();
+        assert_eq!(disk.read(2, &mut buf), Err(Error::OutOfBounds));
+    }
+}